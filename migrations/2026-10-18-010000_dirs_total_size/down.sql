@@ -0,0 +1,3 @@
+ALTER TABLE buckets DROP COLUMN total_size;
+ALTER TABLE buckets DROP COLUMN file_count;
+ALTER TABLE dirs DROP COLUMN total_size;
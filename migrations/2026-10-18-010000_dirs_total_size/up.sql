@@ -0,0 +1,12 @@
+ALTER TABLE dirs ADD COLUMN total_size BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE buckets ADD COLUMN file_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE buckets ADD COLUMN total_size BIGINT NOT NULL DEFAULT 0;
+
+-- Backfill stats for existing data
+UPDATE dirs SET
+    file_count = (SELECT COUNT(*) FROM files WHERE files.dir_id = dirs.id),
+    total_size = (SELECT COALESCE(SUM(files.size), 0) FROM files WHERE files.dir_id = dirs.id);
+
+UPDATE buckets SET
+    file_count = (SELECT COALESCE(SUM(dirs.file_count), 0) FROM dirs WHERE dirs.bucket_id = buckets.id),
+    total_size = (SELECT COALESCE(SUM(dirs.total_size), 0) FROM dirs WHERE dirs.bucket_id = buckets.id);
@@ -0,0 +1,18 @@
+CREATE TABLE jobs (
+    id CHAR(32) PRIMARY KEY NOT NULL,
+    bucket_id CHAR(32) NOT NULL,
+    target_id CHAR(32) NOT NULL,
+    kind VARCHAR(50) NOT NULL,
+    status VARCHAR(20) NOT NULL,
+    payload TEXT NOT NULL,
+    cursor VARCHAR(250) NULL,
+    processed INTEGER NOT NULL DEFAULT 0,
+    total INTEGER NOT NULL DEFAULT 0,
+    error TEXT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    FOREIGN KEY (bucket_id) REFERENCES buckets(id)
+);
+CREATE INDEX jobs_bucket_id_idx ON jobs(bucket_id);
+CREATE INDEX jobs_target_id_status_idx ON jobs(target_id, status);
+CREATE INDEX jobs_status_idx ON jobs(status);
@@ -0,0 +1,17 @@
+-- Trashed items are dropped so the original unique indexes can be restored
+DELETE FROM files WHERE deleted_at IS NOT NULL;
+DELETE FROM files WHERE dir_id IN (SELECT id FROM dirs WHERE deleted_at IS NOT NULL);
+DELETE FROM dirs WHERE deleted_at IS NOT NULL;
+
+DROP INDEX dirs_deleted_at_idx;
+DROP INDEX files_deleted_at_idx;
+
+DROP INDEX dirs_bucket_id_label_idx;
+CREATE UNIQUE INDEX dirs_bucket_id_label_idx ON dirs(bucket_id, label);
+DROP INDEX dirs_bucket_id_name_idx;
+CREATE UNIQUE INDEX dirs_bucket_id_name_idx ON dirs(bucket_id, name);
+DROP INDEX files_dir_id_name_idx;
+CREATE UNIQUE INDEX files_dir_id_name_idx ON files(dir_id, name);
+
+ALTER TABLE dirs DROP COLUMN deleted_at;
+ALTER TABLE files DROP COLUMN deleted_at;
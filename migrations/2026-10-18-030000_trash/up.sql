@@ -0,0 +1,13 @@
+ALTER TABLE files ADD COLUMN deleted_at BIGINT NULL DEFAULT NULL;
+ALTER TABLE dirs ADD COLUMN deleted_at BIGINT NULL DEFAULT NULL;
+
+-- Names only need to be unique among items not in the trash
+DROP INDEX files_dir_id_name_idx;
+CREATE UNIQUE INDEX files_dir_id_name_idx ON files(dir_id, name) WHERE deleted_at IS NULL;
+DROP INDEX dirs_bucket_id_name_idx;
+CREATE UNIQUE INDEX dirs_bucket_id_name_idx ON dirs(bucket_id, name) WHERE deleted_at IS NULL;
+DROP INDEX dirs_bucket_id_label_idx;
+CREATE UNIQUE INDEX dirs_bucket_id_label_idx ON dirs(bucket_id, label) WHERE deleted_at IS NULL;
+
+CREATE INDEX files_deleted_at_idx ON files(deleted_at);
+CREATE INDEX dirs_deleted_at_idx ON dirs(deleted_at);
@@ -0,0 +1 @@
+ALTER TABLE buckets ADD COLUMN max_file_size BIGINT NULL DEFAULT NULL;
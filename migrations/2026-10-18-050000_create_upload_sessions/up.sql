@@ -0,0 +1,14 @@
+CREATE TABLE upload_sessions (
+    id CHAR(32) PRIMARY KEY NOT NULL,
+    bucket_id CHAR(32) NOT NULL,
+    dir_id CHAR(32) NOT NULL,
+    name VARCHAR(250) NOT NULL,
+    size BIGINT NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    FOREIGN KEY (bucket_id) REFERENCES buckets(id),
+    FOREIGN KEY (dir_id) REFERENCES dirs(id)
+);
+CREATE INDEX upload_sessions_dir_id_idx ON upload_sessions(dir_id);
+CREATE INDEX upload_sessions_updated_at_idx ON upload_sessions(updated_at);
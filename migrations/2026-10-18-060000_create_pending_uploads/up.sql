@@ -0,0 +1,11 @@
+CREATE TABLE pending_uploads (
+    id CHAR(32) PRIMARY KEY NOT NULL,
+    bucket_id CHAR(32) NOT NULL,
+    dir_id CHAR(32) NOT NULL,
+    name VARCHAR(250) NOT NULL,
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY (bucket_id) REFERENCES buckets(id),
+    FOREIGN KEY (dir_id) REFERENCES dirs(id)
+);
+CREATE INDEX pending_uploads_dir_id_idx ON pending_uploads(dir_id);
+CREATE INDEX pending_uploads_created_at_idx ON pending_uploads(created_at);
@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN checksum CHAR(64) NULL;
@@ -0,0 +1,2 @@
+DROP INDEX files_checksum_idx;
+ALTER TABLE buckets DROP COLUMN dedupe_policy;
@@ -0,0 +1,2 @@
+ALTER TABLE buckets ADD COLUMN dedupe_policy VARCHAR(10) NULL DEFAULT NULL;
+CREATE INDEX files_checksum_idx ON files(checksum);
@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN phash CHAR(16) NULL;
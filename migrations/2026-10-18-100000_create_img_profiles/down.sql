@@ -0,0 +1 @@
+DROP TABLE img_profiles;
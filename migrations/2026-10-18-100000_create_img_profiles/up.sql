@@ -0,0 +1,13 @@
+CREATE TABLE img_profiles (
+    id CHAR(32) PRIMARY KEY NOT NULL,
+    bucket_id CHAR(32) NOT NULL,
+    name VARCHAR(20) NOT NULL,
+    max_width INTEGER NOT NULL,
+    max_height INTEGER NOT NULL,
+    fit VARCHAR(10) NOT NULL,
+    format VARCHAR(10) NULL,
+    quality INTEGER NULL,
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY (bucket_id) REFERENCES buckets(id)
+);
+CREATE UNIQUE INDEX img_profiles_bucket_id_name_idx ON img_profiles(bucket_id, name);
@@ -0,0 +1 @@
+ALTER TABLE jobs ADD COLUMN failed INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+DROP INDEX files_linked_id_idx;
+ALTER TABLE files DROP COLUMN linked_id;
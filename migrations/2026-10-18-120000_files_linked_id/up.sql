@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN linked_id CHAR(32) NULL;
+CREATE INDEX files_linked_id_idx ON files(linked_id);
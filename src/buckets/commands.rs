@@ -1,7 +1,16 @@
 use crate::Result;
-use crate::buckets::{NewBucket, create_bucket, delete_bucket};
+use crate::buckets::{
+    DedupePolicy, ImgProfile, NewBucket, NewImgProfile, create_bucket, delete_bucket,
+    delete_img_profile, list_img_profiles, recount_bucket_stats, set_img_profile,
+    update_bucket_dedupe_policy, update_bucket_max_file_size,
+};
 use crate::config::{BucketCommand, Config};
 use crate::db::create_db_pool;
+use crate::files::{
+    RegenerateVersions, backfill_bucket_checksums, schedule_regenerate_versions,
+    verify_bucket_checksums,
+};
+use crate::jobs::{execute_job, get_job};
 use crate::storage::create_storage_client;
 
 use super::{get_bucket, list_buckets};
@@ -15,6 +24,48 @@ pub async fn run_bucket_command(cmd: BucketCommand, config: &Config) -> Result<(
             images_only,
         } => run_create_bucket(config, client_id, name, images_only).await,
         BucketCommand::Delete { id } => run_delete_bucket(config, id).await,
+        BucketCommand::Recount { id } => run_recount_bucket(config, id).await,
+        BucketCommand::SetMaxFileSize { id, max_file_size } => {
+            run_set_max_file_size(config, id, Some(max_file_size)).await
+        }
+        BucketCommand::UnsetMaxFileSize { id } => run_set_max_file_size(config, id, None).await,
+        BucketCommand::SetDedupePolicy { id, policy } => {
+            let Ok(policy) = DedupePolicy::try_from(policy.as_str()) else {
+                return Err("Policy must be either reject, warn or link".into());
+            };
+            run_set_dedupe_policy(config, id, Some(policy)).await
+        }
+        BucketCommand::UnsetDedupePolicy { id } => run_set_dedupe_policy(config, id, None).await,
+        BucketCommand::Verify { id } => run_verify_bucket(config, id).await,
+        BucketCommand::BackfillChecksums { id } => run_backfill_checksums(config, id).await,
+        BucketCommand::ListProfiles { id } => run_list_profiles(config, id).await,
+        BucketCommand::SetProfile {
+            id,
+            name,
+            max_width,
+            max_height,
+            fit,
+            format,
+            quality,
+        } => {
+            let data = NewImgProfile {
+                name,
+                max_width,
+                max_height,
+                fit,
+                format,
+                quality,
+            };
+            run_set_profile(config, id, data).await
+        }
+        BucketCommand::DeleteProfile { id, name } => run_delete_profile(config, id, name).await,
+        BucketCommand::RegenerateVersions { id, dir, file } => {
+            let data = RegenerateVersions {
+                dir_id: dir,
+                file_id: file,
+            };
+            run_regenerate_versions(config, id, data).await
+        }
     }
 }
 
@@ -37,7 +88,7 @@ async fn run_create_bucket(
     images_only: String,
 ) -> Result<()> {
     let db_pool = create_db_pool(config.db.url.as_str());
-    let storage_client = create_storage_client(config.cloud.credentials.as_str()).await?;
+    let storage_client = create_storage_client(config).await?;
 
     let res: Result<bool> = match images_only.as_str() {
         "true" => Ok(true),
@@ -53,7 +104,7 @@ async fn run_create_bucket(
         name,
         images_only: img_only,
     };
-    let bucket = create_bucket(&db_pool, &storage_client, &client_id, &data).await?;
+    let bucket = create_bucket(&db_pool, storage_client.as_ref(), &client_id, &data).await?;
     println!(
         "{{ id = {}, name = {}, images_only = {} }}",
         bucket.id, bucket.name, bucket.images_only
@@ -73,3 +124,203 @@ async fn run_delete_bucket(config: &Config, id: String) -> Result<()> {
     }
     Ok(())
 }
+
+async fn run_recount_bucket(config: &Config, id: String) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    if get_bucket(&db_pool, &id).await?.is_none() {
+        println!("Bucket not found.");
+        return Ok(());
+    }
+
+    recount_bucket_stats(&db_pool, &id).await?;
+    if let Some(bucket) = get_bucket(&db_pool, &id).await? {
+        println!(
+            "{{ id = {}, name = {}, file_count = {}, total_size = {} }}",
+            bucket.id, bucket.name, bucket.file_count, bucket.total_size
+        );
+    }
+    println!("Bucket stats recounted.");
+    Ok(())
+}
+
+async fn run_set_max_file_size(
+    config: &Config,
+    id: String,
+    max_file_size: Option<i64>,
+) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    if get_bucket(&db_pool, &id).await?.is_none() {
+        println!("Bucket not found.");
+        return Ok(());
+    }
+
+    update_bucket_max_file_size(&db_pool, &id, max_file_size).await?;
+    match max_file_size {
+        Some(size) => println!("Bucket max file size set to {} bytes.", size),
+        None => println!("Bucket max file size unset."),
+    }
+    Ok(())
+}
+
+async fn run_set_dedupe_policy(
+    config: &Config,
+    id: String,
+    policy: Option<DedupePolicy>,
+) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    if get_bucket(&db_pool, &id).await?.is_none() {
+        println!("Bucket not found.");
+        return Ok(());
+    }
+
+    update_bucket_dedupe_policy(&db_pool, &id, policy).await?;
+    match policy {
+        Some(policy) => println!("Bucket dedupe policy set to {}.", policy),
+        None => println!("Bucket dedupe policy unset."),
+    }
+    Ok(())
+}
+
+async fn run_verify_bucket(config: &Config, id: String) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    let Some(bucket) = get_bucket(&db_pool, &id).await? else {
+        println!("Bucket not found.");
+        return Ok(());
+    };
+
+    let storage_client = create_storage_client(config).await?;
+    let work_dir = config.upload_dir.join("verify");
+    let report =
+        verify_bucket_checksums(&db_pool, storage_client.as_ref(), &bucket, &work_dir).await?;
+
+    for path in report.mismatched.iter() {
+        println!("Checksum mismatch: {}", path);
+    }
+    for path in report.missing.iter() {
+        println!("Missing object: {}", path);
+    }
+    println!(
+        "{{ checked = {}, unchecked = {}, mismatched = {}, missing = {} }}",
+        report.checked,
+        report.unchecked,
+        report.mismatched.len(),
+        report.missing.len()
+    );
+
+    match report.is_intact() {
+        true => {
+            println!("Bucket files are intact.");
+            Ok(())
+        }
+        false => Err("Bucket files failed verification".into()),
+    }
+}
+
+async fn run_backfill_checksums(config: &Config, id: String) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    let Some(bucket) = get_bucket(&db_pool, &id).await? else {
+        println!("Bucket not found.");
+        return Ok(());
+    };
+
+    let storage_client = create_storage_client(config).await?;
+    let work_dir = config.upload_dir.join("verify");
+    let report =
+        backfill_bucket_checksums(&db_pool, storage_client.as_ref(), &bucket, &work_dir).await?;
+
+    for path in report.missing.iter() {
+        println!("Missing object: {}", path);
+    }
+    println!(
+        "{{ updated = {}, missing = {} }}",
+        report.updated,
+        report.missing.len()
+    );
+    Ok(())
+}
+
+async fn run_list_profiles(config: &Config, id: String) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    if get_bucket(&db_pool, &id).await?.is_none() {
+        println!("Bucket not found.");
+        return Ok(());
+    }
+
+    let profiles = list_img_profiles(&db_pool, &id).await?;
+    for profile in profiles.iter() {
+        print_profile(profile);
+    }
+    if profiles.is_empty() {
+        println!("No image profiles, default versions are created.");
+    }
+    Ok(())
+}
+
+async fn run_set_profile(config: &Config, id: String, data: NewImgProfile) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    if get_bucket(&db_pool, &id).await?.is_none() {
+        println!("Bucket not found.");
+        return Ok(());
+    }
+
+    let profile = set_img_profile(&db_pool, &id, &data).await?;
+    print_profile(&profile);
+    println!("Image profile saved.");
+    Ok(())
+}
+
+async fn run_delete_profile(config: &Config, id: String, name: String) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    if get_bucket(&db_pool, &id).await?.is_none() {
+        println!("Bucket not found.");
+        return Ok(());
+    }
+
+    match delete_img_profile(&db_pool, &id, &name).await? {
+        true => println!("Image profile deleted."),
+        false => println!("Image profile not found."),
+    }
+    Ok(())
+}
+
+async fn run_regenerate_versions(
+    config: &Config,
+    id: String,
+    data: RegenerateVersions,
+) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    let Some(bucket) = get_bucket(&db_pool, &id).await? else {
+        println!("Bucket not found.");
+        return Ok(());
+    };
+
+    let storage_client = create_storage_client(config).await?;
+    let job = schedule_regenerate_versions(&db_pool, &bucket, &data).await?;
+    println!("Regenerating versions of {} images...", job.total);
+    execute_job(&db_pool, storage_client.as_ref(), config, &job).await?;
+
+    let Some(job) = get_job(&db_pool, &job.id).await? else {
+        return Err("Job not found".into());
+    };
+    println!(
+        "{{ job = {}, processed = {}, total = {} }}",
+        job.id, job.processed, job.total
+    );
+    println!("Image versions regenerated.");
+    Ok(())
+}
+
+fn print_profile(profile: &ImgProfile) {
+    println!(
+        "{{ name = {}, max_width = {}, max_height = {}, fit = {}, format = {}, quality = {} }}",
+        profile.name,
+        profile.max_width,
+        profile.max_height,
+        profile.fit,
+        profile.format.as_deref().unwrap_or("default"),
+        profile
+            .quality
+            .map(|q| q.to_string())
+            .unwrap_or("default".to_string())
+    );
+}
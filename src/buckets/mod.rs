@@ -1,7 +1,9 @@
 mod commands;
 mod models;
+mod profiles;
 mod queries;
 
 pub use commands::*;
 pub use models::*;
+pub use profiles::*;
 pub use queries::*;
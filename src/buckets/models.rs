@@ -11,6 +11,14 @@ pub struct Bucket {
     pub name: String,
     pub images_only: i32,
     pub created_at: i64,
+    pub file_count: i32,
+    pub total_size: i64,
+
+    // Overrides the global upload limit when set
+    pub max_file_size: Option<i64>,
+
+    // What to do with uploads whose content already exists in the bucket
+    pub dedupe_policy: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -20,6 +28,28 @@ pub struct BucketDto {
     pub name: String,
     pub images_only: bool,
     pub created_at: i64,
+    pub file_count: i32,
+    pub total_size: i64,
+
+    // Overrides the global upload limit when set
+    pub max_file_size: Option<i64>,
+
+    // Duplicate uploads are accepted as usual when not set
+    pub dedupe_policy: Option<DedupePolicy>,
+}
+
+/// Handling of uploads with the same content as an existing file of the bucket
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupePolicy {
+    /// Upload is rejected
+    Reject,
+
+    /// Upload is saved but the response points to the existing file
+    Warn,
+
+    /// Upload is dropped and the existing file is returned instead
+    Link,
 }
 
 #[derive(Debug, Clone, Deserialize, Validate)]
@@ -43,6 +73,39 @@ pub struct ListBucketsParams {
     pub keyword: Option<String>,
 }
 
+impl BucketDto {
+    /// Maximum upload size in bytes, never above the global limit
+    pub fn upload_limit(&self, global_limit: u64) -> u64 {
+        match self.max_file_size {
+            Some(size) if size > 0 => (size as u64).min(global_limit),
+            _ => global_limit,
+        }
+    }
+}
+
+impl TryFrom<&str> for DedupePolicy {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "reject" => Ok(DedupePolicy::Reject),
+            "warn" => Ok(DedupePolicy::Warn),
+            "link" => Ok(DedupePolicy::Link),
+            _ => Err(format!("Invalid dedupe policy: {}", value)),
+        }
+    }
+}
+
+impl core::fmt::Display for DedupePolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            DedupePolicy::Reject => write!(f, "reject"),
+            DedupePolicy::Warn => write!(f, "warn"),
+            DedupePolicy::Link => write!(f, "link"),
+        }
+    }
+}
+
 impl From<BucketDto> for Bucket {
     fn from(dto: BucketDto) -> Self {
         Bucket {
@@ -51,6 +114,10 @@ impl From<BucketDto> for Bucket {
             name: dto.name,
             images_only: if dto.images_only { 1 } else { 0 },
             created_at: dto.created_at,
+            file_count: dto.file_count,
+            total_size: dto.total_size,
+            max_file_size: dto.max_file_size,
+            dedupe_policy: dto.dedupe_policy.map(|p| p.to_string()),
         }
     }
 }
@@ -63,6 +130,13 @@ impl From<Bucket> for BucketDto {
             name: bucket.name,
             images_only: bucket.images_only == 1,
             created_at: bucket.created_at,
+            file_count: bucket.file_count,
+            total_size: bucket.total_size,
+            max_file_size: bucket.max_file_size,
+            dedupe_policy: bucket
+                .dedupe_policy
+                .as_deref()
+                .and_then(|p| DedupePolicy::try_from(p).ok()),
         }
     }
 }
@@ -91,4 +165,13 @@ mod tests {
         };
         assert!(data.validate().is_err());
     }
+
+    #[test]
+    fn test_dedupe_policy() {
+        for policy in [DedupePolicy::Reject, DedupePolicy::Warn, DedupePolicy::Link] {
+            let value = policy.to_string();
+            assert_eq!(DedupePolicy::try_from(value.as_str()), Ok(policy));
+        }
+        assert!(DedupePolicy::try_from("ignore").is_err());
+    }
 }
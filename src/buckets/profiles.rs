@@ -0,0 +1,319 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use validator::{Validate, ValidationError};
+
+use crate::files::{
+    ImgFit, ImgFormat, ImgVersion, MAX_PREVIEW_DIMENSION, MAX_THUMB_DIMENSION, valid_version_name,
+};
+use crate::schema::img_profiles;
+use crate::util::generate_id;
+use crate::validators::flatten_errors;
+use crate::{Error, Result};
+
+/// Most image profiles a bucket may declare
+pub const MAX_IMG_PROFILES: i64 = 10;
+
+/// Image version declared by a bucket
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = crate::schema::img_profiles)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ImgProfile {
+    pub id: String,
+    pub bucket_id: String,
+    pub name: String,
+    pub max_width: i32,
+    pub max_height: i32,
+    pub fit: String,
+
+    // Configured image format and quality apply when not set
+    pub format: Option<String>,
+
+    // Only used by lossy formats
+    pub quality: Option<i32>,
+
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct NewImgProfile {
+    #[validate(custom(function = "profile_name"))]
+    pub name: String,
+
+    #[validate(range(min = 1, max = 10000))]
+    pub max_width: i32,
+
+    #[validate(range(min = 1, max = 10000))]
+    pub max_height: i32,
+
+    pub fit: Option<String>,
+    pub format: Option<String>,
+
+    #[validate(range(min = 1, max = 100))]
+    pub quality: Option<i32>,
+}
+
+/// Parsed image profile used when creating versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImgProfileDto {
+    pub version: ImgVersion,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub fit: ImgFit,
+    pub format: Option<ImgFormat>,
+    pub quality: Option<u8>,
+}
+
+impl TryFrom<&ImgProfile> for ImgProfileDto {
+    type Error = String;
+
+    fn try_from(profile: &ImgProfile) -> core::result::Result<Self, Self::Error> {
+        let format = match &profile.format {
+            Some(format) => Some(ImgFormat::try_from(format.as_str())?),
+            None => None,
+        };
+        Ok(Self {
+            version: ImgVersion::try_from(profile.name.as_str())?,
+            max_width: profile.max_width as u32,
+            max_height: profile.max_height as u32,
+            fit: ImgFit::try_from(profile.fit.as_str())?,
+            format,
+            quality: profile.quality.map(|q| q as u8),
+        })
+    }
+}
+
+fn profile_name(value: &str) -> core::result::Result<(), ValidationError> {
+    match valid_version_name(value) && value != "orig" {
+        true => Ok(()),
+        false => Err(ValidationError::new("profile_name")),
+    }
+}
+
+/// Versions created when the bucket declares no image profiles
+pub fn default_img_profiles() -> Vec<ImgProfileDto> {
+    vec![
+        ImgProfileDto {
+            version: ImgVersion::Preview,
+            max_width: MAX_PREVIEW_DIMENSION,
+            max_height: MAX_PREVIEW_DIMENSION,
+            fit: ImgFit::Contain,
+            format: None,
+            quality: None,
+        },
+        ImgProfileDto {
+            version: ImgVersion::Thumbnail,
+            max_width: MAX_THUMB_DIMENSION,
+            max_height: MAX_THUMB_DIMENSION,
+            fit: ImgFit::Contain,
+            format: None,
+            quality: None,
+        },
+    ]
+}
+
+/// Profiles used for new images of the bucket, the defaults when none are declared
+pub async fn bucket_img_profiles(db_pool: &Pool, bucket_id: &str) -> Result<Vec<ImgProfileDto>> {
+    let profiles = list_img_profiles(db_pool, bucket_id).await?;
+    if profiles.is_empty() {
+        return Ok(default_img_profiles());
+    }
+
+    let mut items: Vec<ImgProfileDto> = Vec::with_capacity(profiles.len());
+    for profile in profiles.iter() {
+        match ImgProfileDto::try_from(profile) {
+            Ok(item) => items.push(item),
+            Err(e) => {
+                error!("Image profile {}: {}", profile.id, e);
+                return Err("Invalid image profile".into());
+            }
+        }
+    }
+    Ok(items)
+}
+
+pub async fn list_img_profiles(db_pool: &Pool, bucket_id: &str) -> Result<Vec<ImgProfile>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            img_profiles::table
+                .filter(img_profiles::bucket_id.eq(bid.as_str()))
+                .select(ImgProfile::as_select())
+                .order(img_profiles::name.asc())
+                .load::<ImgProfile>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing image profiles".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Creates the profile or replaces the one with the same name
+pub async fn set_img_profile(
+    db_pool: &Pool,
+    bucket_id: &str,
+    data: &NewImgProfile,
+) -> Result<ImgProfile> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+
+    let fit = match &data.fit {
+        Some(fit) => ImgFit::try_from(fit.as_str()).map_err(Error::ValidationError)?,
+        None => ImgFit::Contain,
+    };
+    let format = match &data.format {
+        Some(format) => Some(ImgFormat::try_from(format.as_str()).map_err(Error::ValidationError)?),
+        None => None,
+    };
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let profile = ImgProfile {
+        id: generate_id(),
+        bucket_id: bucket_id.to_string(),
+        name: data.name.clone(),
+        max_width: data.max_width,
+        max_height: data.max_height,
+        fit: fit.to_string(),
+        format: format.map(|f| f.to_string()),
+        quality: data.quality,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+
+    let profile_copy = profile.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let others = img_profiles::table
+                    .filter(img_profiles::bucket_id.eq(profile_copy.bucket_id.as_str()))
+                    .filter(img_profiles::name.ne(profile_copy.name.as_str()))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                if others >= MAX_IMG_PROFILES {
+                    return Ok(false);
+                }
+
+                diesel::delete(
+                    img_profiles::table
+                        .filter(img_profiles::bucket_id.eq(profile_copy.bucket_id.as_str()))
+                        .filter(img_profiles::name.eq(profile_copy.name.as_str())),
+                )
+                .execute(conn)?;
+                diesel::insert_into(img_profiles::table)
+                    .values(&profile_copy)
+                    .execute(conn)?;
+                QueryResult::Ok(true)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(insert_res) => match insert_res {
+            Ok(true) => Ok(profile),
+            Ok(false) => Err(Error::ValidationError(format!(
+                "Maximum of {} image profiles reached",
+                MAX_IMG_PROFILES
+            ))),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error saving image profile".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Removes the profile, returns false when it does not exist
+pub async fn delete_img_profile(db_pool: &Pool, bucket_id: &str, name: &str) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let pname = name.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::delete(
+                img_profiles::table
+                    .filter(img_profiles::bucket_id.eq(bid.as_str()))
+                    .filter(img_profiles::name.eq(pname.as_str())),
+            )
+            .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(delete_res) => match delete_res {
+            Ok(count) => Ok(count > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error deleting image profile".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_img_profile_dto() {
+        let mut profile = ImgProfile {
+            id: generate_id(),
+            bucket_id: generate_id(),
+            name: "medium".to_string(),
+            max_width: 600,
+            max_height: 600,
+            fit: "cover".to_string(),
+            format: Some("jpeg".to_string()),
+            quality: Some(80),
+            created_at: 0,
+        };
+        let dto = ImgProfileDto::try_from(&profile).unwrap();
+        assert_eq!(dto.version, ImgVersion::Custom("medium".to_string()));
+        assert_eq!(dto.fit, ImgFit::Cover);
+        assert_eq!(dto.format, Some(ImgFormat::Jpeg));
+
+        profile.name = "thumb".to_string();
+        let dto = ImgProfileDto::try_from(&profile).unwrap();
+        assert_eq!(dto.version, ImgVersion::Thumbnail);
+
+        profile.fit = "stretch".to_string();
+        assert!(ImgProfileDto::try_from(&profile).is_err());
+
+        assert!(profile_name("medium_2x").is_ok());
+        assert!(profile_name("orig").is_err());
+        assert!(profile_name("Medium").is_err());
+        assert!(profile_name("_cache").is_err());
+        assert!(profile_name("tmp").is_err());
+        assert!(profile_name("verify").is_err());
+        assert!(profile_name("").is_err());
+    }
+}
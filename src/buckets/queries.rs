@@ -3,14 +3,14 @@ use deadpool_diesel::sqlite::Pool;
 use diesel::dsl::count_star;
 use diesel::prelude::*;
 use diesel::{QueryDsl, SelectableHelper};
-use google_cloud_storage::client::Client;
 use tracing::error;
 use validator::Validate;
 
-use crate::buckets::{Bucket, NewBucket};
+use crate::buckets::{Bucket, DedupePolicy, NewBucket};
 use crate::dirs::count_bucket_dirs;
 use crate::schema::buckets::{self, dsl};
-use crate::storage::read_bucket;
+use crate::schema::{dirs, files, img_profiles};
+use crate::storage::StorageBackend;
 use crate::util::generate_id;
 use crate::validators::flatten_errors;
 use crate::{Error, Result};
@@ -55,7 +55,7 @@ pub async fn list_buckets(db_pool: &Pool, client_id: &str) -> Result<Vec<BucketD
 
 pub async fn create_bucket(
     db_pool: &Pool,
-    storage_client: &Client,
+    storage_client: &dyn StorageBackend,
     client_id: &str,
     data: &NewBucket,
 ) -> Result<BucketDto> {
@@ -87,7 +87,7 @@ pub async fn create_bucket(
     }
 
     // Validate against the cloud storage
-    let _ = read_bucket(storage_client, &data.name).await?;
+    let _ = storage_client.read_bucket(&data.name).await?;
 
     let data_copy = data.clone();
     let today = chrono::Utc::now().timestamp();
@@ -97,6 +97,10 @@ pub async fn create_bucket(
         name: data_copy.name,
         images_only: if data_copy.images_only { 1 } else { 0 },
         created_at: today,
+        file_count: 0,
+        total_size: 0,
+        max_file_size: None,
+        dedupe_policy: None,
     };
 
     let bucket_copy = bucket.clone();
@@ -237,7 +241,13 @@ pub async fn delete_bucket(db_pool: &Pool, id: &str) -> Result<()> {
     let bucket_id = id.to_string();
     let conn_result = db
         .interact(move |conn| {
-            diesel::delete(dsl::buckets.filter(dsl::id.eq(bucket_id.as_str()))).execute(conn)
+            conn.transaction(|conn| {
+                diesel::delete(
+                    img_profiles::table.filter(img_profiles::bucket_id.eq(bucket_id.as_str())),
+                )
+                .execute(conn)?;
+                diesel::delete(dsl::buckets.filter(dsl::id.eq(bucket_id.as_str()))).execute(conn)
+            })
         })
         .await;
 
@@ -256,6 +266,150 @@ pub async fn delete_bucket(db_pool: &Pool, id: &str) -> Result<()> {
     }
 }
 
+/// Sets or clears the upload size limit of the bucket
+pub async fn update_bucket_max_file_size(
+    db_pool: &Pool,
+    id: &str,
+    max_file_size: Option<i64>,
+) -> Result<bool> {
+    if let Some(size) = max_file_size
+        && size <= 0
+    {
+        return Err(Error::ValidationError(
+            "Max file size must be greater than zero".to_string(),
+        ));
+    }
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::buckets)
+                .filter(dsl::id.eq(bid.as_str()))
+                .set(dsl::max_file_size.eq(max_file_size))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(item) => Ok(item > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating bucket".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Sets or clears the duplicate upload handling of the bucket
+pub async fn update_bucket_dedupe_policy(
+    db_pool: &Pool,
+    id: &str,
+    policy: Option<DedupePolicy>,
+) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = id.to_string();
+    let value = policy.map(|p| p.to_string());
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::buckets)
+                .filter(dsl::id.eq(bid.as_str()))
+                .set(dsl::dedupe_policy.eq(value))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(item) => Ok(item > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating bucket".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Recomputes dir and bucket file stats from the files table
+pub async fn recount_bucket_stats(db_pool: &Pool, id: &str) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bucket_id = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let dir_ids: Vec<(String, Option<i64>)> = dirs::table
+                    .filter(dirs::bucket_id.eq(bucket_id.as_str()))
+                    .select((dirs::id, dirs::deleted_at))
+                    .load(conn)?;
+
+                let mut bucket_count: i32 = 0;
+                let mut bucket_size: i64 = 0;
+
+                // Items in the trash are excluded from the stats
+                for (dir_id, deleted_at) in dir_ids.iter() {
+                    let sizes: Vec<i64> = files::table
+                        .filter(files::dir_id.eq(dir_id.as_str()))
+                        .filter(files::deleted_at.is_null())
+                        .select(files::size)
+                        .load(conn)?;
+
+                    let count = sizes.len() as i32;
+                    let size: i64 = sizes.iter().sum();
+                    if deleted_at.is_none() {
+                        bucket_count += count;
+                        bucket_size += size;
+                    }
+
+                    diesel::update(dirs::table.find(dir_id))
+                        .set((dirs::file_count.eq(count), dirs::total_size.eq(size)))
+                        .execute(conn)?;
+                }
+
+                diesel::update(dsl::buckets.find(bucket_id.as_str()))
+                    .set((
+                        dsl::file_count.eq(bucket_count),
+                        dsl::total_size.eq(bucket_size),
+                    ))
+                    .execute(conn)?;
+
+                QueryResult::Ok(())
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error recounting bucket stats".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
 pub async fn test_read_bucket(db_pool: &Pool) -> Result<()> {
     let Ok(db) = db_pool.get().await else {
         return Err("Error getting db connection".into());
@@ -3,14 +3,56 @@ use serde::Deserialize;
 use std::{fs, path::PathBuf};
 
 use crate::Result;
+use crate::files::{DEFAULT_IMG_QUALITY, ImgFormat};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub jwt_secret: String,
     pub upload_dir: PathBuf,
-    pub cloud: CloudConfig,
+
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    // Only required when using Google Cloud Storage
+    pub cloud: Option<CloudConfig>,
     pub server: ServerConfig,
     pub db: DbConfig,
+
+    #[serde(default)]
+    pub trash: TrashConfig,
+
+    #[serde(default)]
+    pub upload: UploadConfig,
+
+    #[serde(default)]
+    pub image: ImageConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageKind,
+
+    // Root directory for the local backend
+    pub dir: Option<PathBuf>,
+
+    // Public url of this server used in signed object and transform urls
+    pub base_url: Option<String>,
+
+    // Secret used to sign object urls, defaults to the JWT secret
+    pub url_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    #[default]
+    Gcs,
+    Local,
+
+    // Loses every object on restart, only available to tests
+    #[cfg(test)]
+    Memory,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -27,6 +69,121 @@ pub struct ServerConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct DbConfig {
     pub url: String,
+
+    // Applies pending migrations before the server starts
+    #[serde(default)]
+    pub auto_migrate: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrashConfig {
+    // Days before trashed files and dirs are permanently deleted
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: default_retention_days(),
+        }
+    }
+}
+
+fn default_retention_days() -> u32 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadConfig {
+    // Largest accepted file in bytes, buckets may only lower it
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+
+    // Largest multipart upload request in bytes, whatever the number of files
+    #[serde(default = "default_max_request_size")]
+    pub max_request_size: u64,
+
+    // Hours before an idle resumable upload session or a presigned upload
+    // that was never finalized is discarded
+    #[serde(default = "default_session_ttl_hours")]
+    pub session_ttl_hours: u32,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: default_max_file_size(),
+            max_request_size: default_max_request_size(),
+            session_ttl_hours: default_session_ttl_hours(),
+        }
+    }
+}
+
+fn default_max_file_size() -> u64 {
+    8_000_000
+}
+
+fn default_max_request_size() -> u64 {
+    50_000_000
+}
+
+fn default_session_ttl_hours() -> u32 {
+    24
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageConfig {
+    // Encoding of image versions unless their profile sets one
+    #[serde(default = "default_image_format")]
+    pub format: ImgFormat,
+
+    // Quality of lossy image versions unless their profile sets one
+    #[serde(default = "default_image_quality")]
+    pub quality: u8,
+
+    // Only these values are accepted by the transform endpoint, each
+    // combination is cached so they bound the work and storage per image
+    #[serde(default = "default_transform_sizes")]
+    pub transform_sizes: Vec<u32>,
+
+    #[serde(default = "default_transform_formats")]
+    pub transform_formats: Vec<ImgFormat>,
+
+    #[serde(default = "default_transform_qualities")]
+    pub transform_qualities: Vec<u8>,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            format: default_image_format(),
+            quality: default_image_quality(),
+            transform_sizes: default_transform_sizes(),
+            transform_formats: default_transform_formats(),
+            transform_qualities: default_transform_qualities(),
+        }
+    }
+}
+
+fn default_image_format() -> ImgFormat {
+    ImgFormat::Webp
+}
+
+fn default_image_quality() -> u8 {
+    DEFAULT_IMG_QUALITY
+}
+
+fn default_transform_sizes() -> Vec<u32> {
+    vec![100, 200, 400, 800, 1200, 1600, 2000]
+}
+
+fn default_transform_formats() -> Vec<ImgFormat> {
+    vec![ImgFormat::Jpeg, ImgFormat::Webp]
+}
+
+fn default_transform_qualities() -> Vec<u8> {
+    vec![60, 80, 90]
 }
 
 impl Config {
@@ -49,15 +206,49 @@ impl Config {
         if config.jwt_secret.len() == 0 {
             return Err("JWT secret is required.".into());
         }
-        if config.cloud.project_id.len() == 0 {
-            return Err("Google Cloud Project ID is required.".into());
-        }
-        if config.cloud.credentials.len() == 0 {
-            return Err("Google Cloud credentials file is required.".into());
+        match config.storage.backend {
+            StorageKind::Gcs => {
+                let Some(cloud) = &config.cloud else {
+                    return Err("Google Cloud config is required.".into());
+                };
+                if cloud.project_id.is_empty() {
+                    return Err("Google Cloud Project ID is required.".into());
+                }
+                if cloud.credentials.is_empty() {
+                    return Err("Google Cloud credentials file is required.".into());
+                }
+            }
+            StorageKind::Local => {
+                let Some(dir) = &config.storage.dir else {
+                    return Err("Local storage directory is required.".into());
+                };
+                if !dir.exists() {
+                    return Err("Local storage directory does not exist.".into());
+                }
+                if config
+                    .storage
+                    .base_url
+                    .as_deref()
+                    .is_none_or(|url| url.is_empty())
+                {
+                    return Err("Storage base url is required for the local backend.".into());
+                }
+                if config.storage.url_secret.as_deref() == Some("") {
+                    return Err("Storage url secret must not be empty.".into());
+                }
+            }
+            #[cfg(test)]
+            StorageKind::Memory => {}
         }
         if config.db.url.len() == 0 {
             return Err("Database URL required.".into());
         }
+        if config.upload.max_file_size == 0 {
+            return Err("Upload max file size must be greater than zero.".into());
+        }
+        if config.image.quality == 0 || config.image.quality > 100 {
+            return Err("Image quality must be from 1 to 100.".into());
+        }
         if config.server.port == 0 {
             return Err("PORT is required.".into());
         }
@@ -71,6 +262,18 @@ impl Config {
 
         Ok(config)
     }
+
+    pub fn url_secret(&self) -> &str {
+        match &self.storage.url_secret {
+            Some(secret) => secret.as_str(),
+            None => self.jwt_secret.as_str(),
+        }
+    }
+
+    /// Public url of this server, required by the local backend
+    pub fn base_url(&self) -> Option<&str> {
+        self.storage.base_url.as_deref()
+    }
 }
 
 /// File Management in the cloud
@@ -101,10 +304,26 @@ pub enum Commands {
     #[command(subcommand)]
     Buckets(BucketCommand),
 
+    /// Manages database migrations
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+
     /// Checks health of the API server
     CheckHealth,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum MigrateCommand {
+    /// Applies all pending migrations
+    Up,
+
+    /// Reverts the last applied migration
+    Down,
+
+    /// Lists migrations and whether they are applied
+    Status,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ClientCommand {
     List,
@@ -153,4 +372,74 @@ pub enum BucketCommand {
     Delete {
         id: String,
     },
+    /// Recomputes file count and total size of the bucket and its dirs
+    Recount {
+        id: String,
+    },
+    /// Limits the size in bytes of files uploaded to the bucket
+    SetMaxFileSize {
+        id: String,
+        max_file_size: i64,
+    },
+    /// Falls back to the global upload size limit
+    UnsetMaxFileSize {
+        id: String,
+    },
+    /// Handles uploads whose content already exists: reject, warn or link
+    SetDedupePolicy {
+        id: String,
+        policy: String,
+    },
+    /// Accepts duplicate uploads as usual
+    UnsetDedupePolicy {
+        id: String,
+    },
+    /// Compares stored originals against the checksums recorded on upload
+    Verify {
+        id: String,
+    },
+    /// Records checksums of files uploaded before checksums were
+    BackfillChecksums {
+        id: String,
+    },
+    /// Lists the image versions created for new images of the bucket
+    ListProfiles {
+        id: String,
+    },
+    /// Declares an image version, which replaces the default preview and thumbnail
+    SetProfile {
+        id: String,
+        name: String,
+        max_width: i32,
+        max_height: i32,
+
+        /// Either contain or cover
+        #[arg(long)]
+        fit: Option<String>,
+
+        /// Either webp, avif, jpeg or png, the configured format when not set
+        #[arg(long)]
+        format: Option<String>,
+
+        /// From 1 to 100, for webp, avif and jpeg versions
+        #[arg(long)]
+        quality: Option<i32>,
+    },
+    /// Removes an image version, the defaults apply once none are left
+    DeleteProfile {
+        id: String,
+        name: String,
+    },
+    /// Recreates the versions of existing images with the current profiles
+    RegenerateVersions {
+        id: String,
+
+        /// Only the images of the directory
+        #[arg(long)]
+        dir: Option<String>,
+
+        /// Only the image with this file id
+        #[arg(long)]
+        file: Option<String>,
+    },
 }
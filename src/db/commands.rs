@@ -0,0 +1,48 @@
+use crate::Result;
+use crate::config::{Config, MigrateCommand};
+use crate::db::create_db_pool;
+
+use super::{migration_status, revert_last_migration, run_pending_migrations};
+
+pub async fn run_migrate_command(cmd: MigrateCommand, config: &Config) -> Result<()> {
+    match cmd {
+        MigrateCommand::Up => run_migrate_up(config).await,
+        MigrateCommand::Down => run_migrate_down(config).await,
+        MigrateCommand::Status => run_migrate_status(config).await,
+    }
+}
+
+async fn run_migrate_up(config: &Config) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    let versions = run_pending_migrations(&db_pool).await?;
+    if versions.is_empty() {
+        println!("No pending migrations.");
+    }
+    for version in versions.iter() {
+        println!("Applied migration {}", version);
+    }
+    Ok(())
+}
+
+async fn run_migrate_down(config: &Config) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    let version = revert_last_migration(&db_pool).await?;
+    println!("Reverted migration {}", version);
+    Ok(())
+}
+
+async fn run_migrate_status(config: &Config) -> Result<()> {
+    let db_pool = create_db_pool(config.db.url.as_str());
+    let items = migration_status(&db_pool).await?;
+    for item in items.iter() {
+        let status = match item.applied {
+            true => "applied",
+            false => "pending",
+        };
+        println!(
+            "{{ version = {}, name = {}, status = {} }}",
+            item.version, item.name, status
+        );
+    }
+    Ok(())
+}
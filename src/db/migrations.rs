@@ -0,0 +1,144 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::migration::MigrationSource;
+use diesel::sqlite::Sqlite;
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use tracing::error;
+
+use crate::Result;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: String,
+    pub name: String,
+    pub applied: bool,
+}
+
+/// Applies all pending migrations, returns the applied migration versions
+pub async fn run_pending_migrations(db_pool: &Pool) -> Result<Vec<String>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| match conn.run_pending_migrations(MIGRATIONS) {
+            Ok(versions) => Ok(versions.iter().map(|v| v.to_string()).collect()),
+            Err(e) => Err(e.to_string()),
+        })
+        .await;
+
+    match conn_result {
+        Ok(res) => match res {
+            Ok(versions) => Ok(versions),
+            Err(e) => {
+                error!("{}", e);
+                Err(format!("Error running migrations: {}", e).into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Reverts the most recently applied migration, returns its version
+pub async fn revert_last_migration(db_pool: &Pool) -> Result<String> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| match conn.revert_last_migration(MIGRATIONS) {
+            Ok(version) => Ok(version.to_string()),
+            Err(e) => Err(e.to_string()),
+        })
+        .await;
+
+    match conn_result {
+        Ok(res) => match res {
+            Ok(version) => Ok(version),
+            Err(e) => {
+                error!("{}", e);
+                Err(format!("Error reverting migration: {}", e).into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Lists all embedded migrations and whether they are already applied
+pub async fn migration_status(db_pool: &Pool) -> Result<Vec<MigrationStatus>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            let applied = conn.applied_migrations().map_err(|e| e.to_string())?;
+            let migrations =
+                MigrationSource::<Sqlite>::migrations(&MIGRATIONS).map_err(|e| e.to_string())?;
+
+            let items: Vec<MigrationStatus> = migrations
+                .iter()
+                .map(|m| MigrationStatus {
+                    version: m.name().version().to_string(),
+                    name: m.name().to_string(),
+                    applied: applied.contains(&m.name().version()),
+                })
+                .collect();
+            Ok::<Vec<MigrationStatus>, String>(items)
+        })
+        .await;
+
+    match conn_result {
+        Ok(res) => match res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err(format!("Error reading migrations: {}", e).into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::create_db_pool;
+
+    #[tokio::test]
+    async fn test_migrations() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let db_url = temp_dir.path().join("db.sqlite3");
+        let db_pool = create_db_pool(db_url.to_str().unwrap());
+
+        let status = migration_status(&db_pool).await.unwrap();
+        assert!(!status.is_empty());
+        assert!(status.iter().all(|m| !m.applied));
+
+        let versions = run_pending_migrations(&db_pool).await.unwrap();
+        assert_eq!(versions.len(), status.len());
+
+        let status = migration_status(&db_pool).await.unwrap();
+        assert!(status.iter().all(|m| m.applied));
+
+        // Nothing left to apply
+        let versions = run_pending_migrations(&db_pool).await.unwrap();
+        assert!(versions.is_empty());
+
+        let reverted = revert_last_migration(&db_pool).await.unwrap();
+        let status = migration_status(&db_pool).await.unwrap();
+        let last = status.last().unwrap();
+        assert_eq!(last.version, reverted);
+        assert!(!last.applied);
+    }
+}
@@ -1,3 +1,7 @@
+mod commands;
 mod conn;
+mod migrations;
 
+pub use commands::*;
 pub use conn::*;
+pub use migrations::*;
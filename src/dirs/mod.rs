@@ -1,5 +1,7 @@
 mod models;
 mod queries;
+mod rename;
 
 pub use models::*;
 pub use queries::*;
+pub use rename::*;
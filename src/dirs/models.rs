@@ -13,6 +13,10 @@ pub struct Dir {
     pub file_count: i32,
     pub created_at: i64,
     pub updated_at: i64,
+    pub total_size: i64,
+
+    // Set when the dir is in the trash
+    pub deleted_at: Option<i64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Validate)]
@@ -25,13 +29,37 @@ pub struct NewDir {
     pub label: String,
 }
 
-#[derive(Debug, Clone, Deserialize, Validate, AsChangeset)]
-#[diesel(table_name = crate::schema::dirs)]
+#[derive(Debug, Clone, Deserialize, Validate)]
 pub struct UpdateDir {
+    // Renaming moves the stored objects, see `schedule_dir_rename`
+    #[validate(length(min = 1, max = 50))]
+    #[validate(custom(function = "crate::validators::sluggable"))]
+    pub name: Option<String>,
+
     #[validate(length(min = 1, max = 100))]
     pub label: Option<String>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteDirParams {
+    pub recursive: Option<bool>,
+}
+
+/// Outcome of deleting a dir together with its files
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteDirResult {
+    pub deleted_files: i32,
+    pub failed: Vec<FailedFileDelete>,
+    pub dir_deleted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedFileDelete {
+    pub id: String,
+    pub name: String,
+    pub error: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Validate)]
 pub struct ListDirsParams {
     #[validate(range(min = 1, max = 1000))]
@@ -6,9 +6,14 @@ use diesel::{QueryDsl, SelectableHelper};
 use tracing::error;
 use validator::Validate;
 
-use crate::dirs::{Dir, NewDir, UpdateDir};
-use crate::files::count_dir_files;
+use crate::buckets::BucketDto;
+use crate::dirs::{
+    DeleteDirResult, Dir, FailedFileDelete, NewDir, UpdateDir, is_dir_name_reserved,
+};
+use crate::files::{count_all_dir_files, delete_file, list_dir_files_after, release_file_objects};
+use crate::schema::buckets;
 use crate::schema::dirs::{self, dsl};
+use crate::storage::{StorageBackend, delete_dir_transforms};
 use crate::util::generate_id;
 use crate::validators::flatten_errors;
 use crate::web::pagination::Paginated;
@@ -18,6 +23,7 @@ use super::ListDirsParams;
 
 const MAX_DIRS: i32 = 1000;
 const MAX_PER_PAGE: i32 = 50;
+const DELETE_BATCH_SIZE: i64 = 50;
 
 pub async fn list_dirs(
     db_pool: &Pool,
@@ -63,7 +69,9 @@ pub async fn list_dirs(
     let conn_result = db
         .interact(move |conn| {
             let mut query = dsl::dirs.into_boxed();
-            query = query.filter(dsl::bucket_id.eq(bid.as_str()));
+            query = query
+                .filter(dsl::bucket_id.eq(bid.as_str()))
+                .filter(dsl::deleted_at.is_null());
 
             if let Some(keyword) = params_copy.keyword {
                 if keyword.len() > 0 {
@@ -107,7 +115,9 @@ async fn list_dirs_count(db_pool: &Pool, bucket_id: &str, params: &ListDirsParam
     let conn_result = db
         .interact(move |conn| {
             let mut query = dsl::dirs.into_boxed();
-            query = query.filter(dsl::bucket_id.eq(bid.as_str()));
+            query = query
+                .filter(dsl::bucket_id.eq(bid.as_str()))
+                .filter(dsl::deleted_at.is_null());
             if let Some(keyword) = params_copy.keyword {
                 if keyword.len() > 0 {
                     let pattern = format!("%{}%", keyword);
@@ -155,8 +165,12 @@ pub async fn create_dir(db_pool: &Pool, bucket_id: &str, data: &NewDir) -> Resul
         Err(e) => return Err(e),
     };
 
-    // Directory name must be unique for the bucket
-    if let Some(_) = find_bucket_dir(db_pool, bucket_id, data.name.as_str()).await? {
+    // Directory name must be unique for the bucket, including pending renames
+    if find_bucket_dir(db_pool, bucket_id, data.name.as_str())
+        .await?
+        .is_some()
+        || is_dir_name_reserved(db_pool, bucket_id, &data.name).await?
+    {
         return Err(Error::ValidationError(
             "Directory name already exists".to_string(),
         ));
@@ -172,6 +186,8 @@ pub async fn create_dir(db_pool: &Pool, bucket_id: &str, data: &NewDir) -> Resul
         file_count: 0,
         created_at: today,
         updated_at: today,
+        total_size: 0,
+        deleted_at: None,
     };
 
     let dir_copy = dir.clone();
@@ -208,6 +224,7 @@ pub async fn get_dir(pool: &Pool, id: &str) -> Result<Option<Dir>> {
         .interact(move |conn| {
             dsl::dirs
                 .find(did)
+                .filter(dsl::deleted_at.is_null())
                 .select(Dir::as_select())
                 .first::<Dir>(conn)
                 .optional()
@@ -241,6 +258,7 @@ pub async fn find_bucket_dir(pool: &Pool, bucket_id: &str, name: &str) -> Result
             dsl::dirs
                 .filter(dsl::bucket_id.eq(bid.as_str()))
                 .filter(dsl::name.eq(name_copy.as_str()))
+                .filter(dsl::deleted_at.is_null())
                 .select(Dir::as_select())
                 .first::<Dir>(conn)
                 .optional()
@@ -262,6 +280,39 @@ pub async fn find_bucket_dir(pool: &Pool, bucket_id: &str, name: &str) -> Result
     }
 }
 
+/// Lists every dir of the bucket, including the ones in the trash
+pub async fn list_all_bucket_dirs(db_pool: &Pool, bucket_id: &str) -> Result<Vec<Dir>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::dirs
+                .filter(dsl::bucket_id.eq(bid.as_str()))
+                .select(Dir::as_select())
+                .order(dsl::name.asc())
+                .load::<Dir>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing directories".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Counts dirs of the bucket including the ones in the trash
 pub async fn count_bucket_dirs(db_pool: &Pool, bucket_id: &str) -> Result<i64> {
     let Ok(db) = db_pool.get().await else {
         return Err("Error getting db connection".into());
@@ -301,18 +352,17 @@ pub async fn update_dir(db_pool: &Pool, id: &str, data: &UpdateDir) -> Result<bo
         return Err(Error::ValidationError(flatten_errors(&errors)));
     }
 
-    // Do not update if there is no data to update
-    if data.label.is_none() {
+    // Do not update if there is no data to update, name is updated by the rename job
+    let Some(label) = data.label.clone() else {
         return Ok(false);
-    }
+    };
 
-    let data_copy = data.clone();
     let dir_id = id.to_string();
     let conn_result = db
         .interact(move |conn| {
             diesel::update(dsl::dirs)
                 .filter(dsl::id.eq(dir_id.as_str()))
-                .set(data_copy)
+                .set(dsl::label.eq(label))
                 .execute(conn)
         })
         .await;
@@ -362,13 +412,58 @@ pub async fn update_dir_timestamp(db_pool: &Pool, id: &str, timestamp: i64) -> R
     }
 }
 
+/// Adjusts the file count and total size of the dir and its bucket.
+/// Must run in the same transaction as the file insert or delete.
+pub fn update_dir_stats(
+    conn: &mut SqliteConnection,
+    dir_id: &str,
+    file_count: i32,
+    total_size: i64,
+) -> QueryResult<()> {
+    let (bucket_id, deleted_at): (String, Option<i64>) = dsl::dirs
+        .find(dir_id)
+        .select((dsl::bucket_id, dsl::deleted_at))
+        .first(conn)?;
+
+    diesel::update(dsl::dirs.find(dir_id))
+        .set((
+            dsl::file_count.eq(dsl::file_count + file_count),
+            dsl::total_size.eq(dsl::total_size + total_size),
+        ))
+        .execute(conn)?;
+
+    // Trashed dirs are already excluded from the bucket stats
+    if deleted_at.is_none() {
+        update_bucket_stats(conn, &bucket_id, file_count, total_size)?;
+    }
+
+    Ok(())
+}
+
+/// Adds the file count and total size to the bucket stats
+pub fn update_bucket_stats(
+    conn: &mut SqliteConnection,
+    bucket_id: &str,
+    file_count: i32,
+    total_size: i64,
+) -> QueryResult<()> {
+    diesel::update(buckets::table.find(bucket_id))
+        .set((
+            buckets::file_count.eq(buckets::file_count + file_count),
+            buckets::total_size.eq(buckets::total_size + total_size),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
 pub async fn delete_dir(db_pool: &Pool, id: &str) -> Result<()> {
     let Ok(db) = db_pool.get().await else {
         return Err("Error getting db connection".into());
     };
 
-    // Do not delete if there are still files inside
-    let file_count = count_dir_files(db_pool, id).await?;
+    // Do not delete if there are still files inside, including trashed ones
+    let file_count = count_all_dir_files(db_pool, id).await?;
     if file_count > 0 {
         return Err(Error::ValidationError(
             "Cannot delete directory with files inside".to_string(),
@@ -396,3 +491,74 @@ pub async fn delete_dir(db_pool: &Pool, id: &str) -> Result<()> {
         }
     }
 }
+
+/// Deletes every file of the dir then the dir itself. Each file record is
+/// removed before its objects so no record ever points to a missing object.
+pub async fn delete_dir_recursive(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket: &BucketDto,
+    dir: &Dir,
+) -> Result<DeleteDirResult> {
+    let mut result = DeleteDirResult {
+        deleted_files: 0,
+        failed: Vec::new(),
+        dir_deleted: false,
+    };
+
+    let mut cursor: Option<String> = None;
+    loop {
+        let files =
+            list_dir_files_after(db_pool, &dir.id, cursor.as_deref(), DELETE_BATCH_SIZE).await?;
+        if files.is_empty() {
+            break;
+        }
+
+        for file in files.into_iter() {
+            cursor = Some(file.id.clone());
+
+            let deleted = match delete_file(db_pool, &file.id).await {
+                Ok(deleted) => deleted,
+                Err(e) => {
+                    result.failed.push(FailedFileDelete {
+                        id: file.id,
+                        name: file.name,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            result.deleted_files += 1;
+
+            // Record is gone, keep going even if some objects are left behind.
+            // Earlier files may have handed their objects over to this one.
+            let current = deleted.as_ref().unwrap_or(&file);
+            let res =
+                release_file_objects(db_pool, storage_client, &bucket.name, &dir.name, current)
+                    .await;
+            if let Err(e) = res {
+                result.failed.push(FailedFileDelete {
+                    id: file.id,
+                    name: file.name,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    // Files that failed to delete still belong to the dir
+    if count_all_dir_files(db_pool, &dir.id).await? == 0 {
+        delete_dir(db_pool, &dir.id).await?;
+        result.dir_deleted = true;
+
+        // A live dir may have taken the name, the cached images are its own
+        if find_bucket_dir(db_pool, &bucket.id, &dir.name)
+            .await?
+            .is_none()
+        {
+            delete_dir_transforms(storage_client, &bucket.name, &dir.name).await;
+        }
+    }
+
+    Ok(result)
+}
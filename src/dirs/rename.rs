@@ -0,0 +1,412 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+use tracing::error;
+use validator::Validate;
+
+use crate::buckets::BucketDto;
+use crate::files::{FileDto, count_all_dir_files, list_dir_files_after};
+use crate::jobs::{
+    DirRenamePayload, Job, JobKind, NewJob, RegenerateVersionsPayload, build_job, has_active_job,
+    list_active_bucket_jobs, update_job_progress,
+};
+use crate::schema::{dirs, jobs};
+use crate::storage::{StorageBackend, copy_file_object, delete_dir_transforms, delete_file_object};
+use crate::validators::flatten_errors;
+use crate::{Error, Result};
+
+use super::{Dir, UpdateDir, find_bucket_dir, get_dir};
+
+const RENAME_BATCH_SIZE: i64 = 50;
+
+/// Rejects changes to the dir while a job is still working on it
+pub async fn ensure_dir_idle(db_pool: &Pool, dir: &Dir) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = dir.bucket_id.clone();
+    let did = dir.id.clone();
+    let conn_result = db
+        .interact(move |conn| dir_has_active_job(conn, &bid, &did))
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(true) => Err(Error::Conflict(
+                "Directory has an operation in progress".to_string(),
+            )),
+            Ok(false) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding job".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Whether a pending or running job works on the dir objects, either a job
+/// of the dir itself or a regenerate job of the whole bucket or of one of
+/// the dir files
+pub fn dir_has_active_job(
+    conn: &mut SqliteConnection,
+    bucket_id: &str,
+    dir_id: &str,
+) -> QueryResult<bool> {
+    if has_active_job(conn, &[dir_id])? {
+        return Ok(true);
+    }
+    let jobs = list_active_bucket_jobs(conn, bucket_id, JobKind::RegenerateVersions)?;
+    Ok(jobs.iter().any(|job| {
+        serde_json::from_str::<RegenerateVersionsPayload>(&job.payload)
+            .is_ok_and(|payload| payload.dir_id.is_none_or(|id| id == dir_id))
+    }))
+}
+
+/// Checks, inside the transaction saving a file, that objects uploaded under
+/// `dir_name` are still where the dir expects them. Catches uploads and
+/// moves that passed `ensure_dir_idle` right before a job was created.
+pub fn dir_accepts_objects(
+    conn: &mut SqliteConnection,
+    dir_id: &str,
+    dir_name: &str,
+) -> QueryResult<bool> {
+    let found = dirs::table
+        .find(dir_id)
+        .select((dirs::bucket_id, dirs::name))
+        .first::<(String, String)>(conn)
+        .optional()?;
+    let Some((bucket_id, name)) = found else {
+        return Ok(false);
+    };
+    if name != dir_name {
+        return Ok(false);
+    }
+    Ok(!dir_has_active_job(conn, &bucket_id, dir_id)?)
+}
+
+/// Whether an unfinished rename is about to give the name to a dir
+pub async fn is_dir_name_reserved(db_pool: &Pool, bucket_id: &str, name: &str) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let name_copy = name.to_string();
+    let conn_result = db
+        .interact(move |conn| rename_reserves_name(conn, &bid, &name_copy))
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(reserved) => Ok(reserved),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding job".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Creates a job that moves the dir objects under the new name
+pub async fn schedule_dir_rename(
+    db_pool: &Pool,
+    bucket: &BucketDto,
+    dir: &Dir,
+    data: &UpdateDir,
+) -> Result<Option<Job>> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+
+    let Some(new_name) = &data.name else {
+        return Ok(None);
+    };
+    if new_name == &dir.name {
+        return Ok(None);
+    }
+
+    ensure_dir_idle(db_pool, dir).await?;
+
+    let payload = DirRenamePayload {
+        bucket_name: bucket.name.clone(),
+        old_name: dir.name.clone(),
+        new_name: new_name.clone(),
+    };
+
+    // Every file, trashed ones included, is processed twice, once to copy
+    // and once to clean up
+    let count = count_all_dir_files(db_pool, &dir.id).await?;
+    let data = NewJob {
+        bucket_id: bucket.id.clone(),
+        target_id: dir.id.clone(),
+        kind: JobKind::DirRename,
+        payload: serde_json::to_string(&payload).unwrap(),
+        total: (count * 2) as i32,
+    };
+    let job = create_rename_job(db_pool, &data, new_name).await?;
+    Ok(Some(job))
+}
+
+/// Inserts the job unless the dir is busy or the name is already used or
+/// reserved, all checked under the same write lock so renames are serialized
+async fn create_rename_job(db_pool: &Pool, data: &NewJob, new_name: &str) -> Result<Job> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let job = build_job(data);
+    let job_copy = job.clone();
+    let name_copy = new_name.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if dir_has_active_job(conn, &job_copy.bucket_id, &job_copy.target_id)? {
+                    return Ok(Some(Error::Conflict(
+                        "Directory has an operation in progress".to_string(),
+                    )));
+                }
+
+                // Directory name must be unique for the bucket
+                let taken = dirs::table
+                    .filter(dirs::bucket_id.eq(job_copy.bucket_id.as_str()))
+                    .filter(dirs::name.eq(name_copy.as_str()))
+                    .filter(dirs::deleted_at.is_null())
+                    .count()
+                    .get_result::<i64>(conn)?;
+                if taken > 0 || rename_reserves_name(conn, &job_copy.bucket_id, &name_copy)? {
+                    return Ok(Some(Error::ValidationError(
+                        "Directory name already exists".to_string(),
+                    )));
+                }
+
+                diesel::insert_into(jobs::table)
+                    .values(&job_copy)
+                    .execute(conn)?;
+                QueryResult::Ok(None)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(insert_res) => match insert_res {
+            Ok(None) => Ok(job),
+            Ok(Some(e)) => Err(e),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error creating job".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+fn rename_reserves_name(
+    conn: &mut SqliteConnection,
+    bucket_id: &str,
+    name: &str,
+) -> QueryResult<bool> {
+    let jobs = list_active_bucket_jobs(conn, bucket_id, JobKind::DirRename)?;
+    Ok(jobs.iter().any(|job| {
+        serde_json::from_str::<DirRenamePayload>(&job.payload)
+            .is_ok_and(|payload| payload.new_name == name)
+    }))
+}
+
+/// Copies objects to the new prefix, switches the dir name then deletes
+/// the old objects. Progress is saved per file so it can be resumed.
+pub async fn run_dir_rename(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    job: &Job,
+) -> Result<()> {
+    let Ok(payload) = serde_json::from_str::<DirRenamePayload>(&job.payload) else {
+        return Err("Invalid dir rename payload".into());
+    };
+    let Some(dir) = get_dir(db_pool, &job.target_id).await? else {
+        return Err(Error::NotFound("Directory not found".to_string()));
+    };
+
+    let bucket_name = payload.bucket_name.as_str();
+    let mut cursor = job.cursor.clone();
+    let mut processed = job.processed;
+
+    // Copy phase is already done when resuming after the switch
+    if dir.name == payload.old_name {
+        loop {
+            let files =
+                list_dir_files_after(db_pool, &dir.id, cursor.as_deref(), RENAME_BATCH_SIZE)
+                    .await?;
+            if files.is_empty() {
+                break;
+            }
+
+            for file in files.into_iter() {
+                // Linked files have no objects of their own
+                if file.linked_id.is_none() {
+                    let dto: FileDto = file.clone().into();
+                    let res = copy_file_object(
+                        storage_client,
+                        (bucket_name, &payload.old_name),
+                        (bucket_name, &payload.new_name),
+                        &dto,
+                        &file.filename,
+                    )
+                    .await;
+                    if let Err(e) = res {
+                        rollback_dir_copy(
+                            db_pool,
+                            storage_client,
+                            job,
+                            &payload,
+                            cursor.as_deref(),
+                        )
+                        .await;
+                        return Err(e);
+                    }
+                }
+
+                processed += 1;
+                cursor = Some(file.id);
+                update_job_progress(db_pool, &job.id, cursor.clone(), processed, 0).await?;
+            }
+        }
+
+        switch_dir_name(db_pool, &job.id, &dir.id, &payload.new_name).await?;
+        cursor = None;
+    }
+
+    // Cleanup phase, objects may already be gone when resuming
+    loop {
+        let files =
+            list_dir_files_after(db_pool, &dir.id, cursor.as_deref(), RENAME_BATCH_SIZE).await?;
+        if files.is_empty() {
+            break;
+        }
+
+        for file in files.into_iter() {
+            if file.linked_id.is_none() {
+                let dto: FileDto = file.clone().into();
+                let res =
+                    delete_file_object(storage_client, bucket_name, &payload.old_name, &dto).await;
+                if let Err(e) = res {
+                    error!("Cleanup renamed object(s): {}", e);
+                }
+            }
+
+            processed += 1;
+            cursor = Some(file.id);
+            update_job_progress(db_pool, &job.id, cursor.clone(), processed, 0).await?;
+        }
+    }
+
+    // Including those of files that failed to delete, unless a new dir took
+    // the old name meanwhile
+    if find_bucket_dir(db_pool, &job.bucket_id, &payload.old_name)
+        .await?
+        .is_none()
+    {
+        delete_dir_transforms(storage_client, bucket_name, &payload.old_name).await;
+    }
+
+    Ok(())
+}
+
+/// Removes the copies made before the copy phase failed, the dir keeps its
+/// name and a new rename starts over
+async fn rollback_dir_copy(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    job: &Job,
+    payload: &DirRenamePayload,
+    last_copied: Option<&str>,
+) {
+    let bucket_name = payload.bucket_name.as_str();
+    let mut cursor: Option<String> = None;
+    'batches: while let Some(last_copied) = last_copied {
+        let files = match list_dir_files_after(
+            db_pool,
+            &job.target_id,
+            cursor.as_deref(),
+            RENAME_BATCH_SIZE,
+        )
+        .await
+        {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Rollback renamed object(s): {}", e);
+                break;
+            }
+        };
+        if files.is_empty() {
+            break;
+        }
+
+        for file in files.into_iter() {
+            if file.id.as_str() > last_copied {
+                break 'batches;
+            }
+            if file.linked_id.is_none() {
+                let dto: FileDto = file.clone().into();
+                let res =
+                    delete_file_object(storage_client, bucket_name, &payload.new_name, &dto).await;
+                if let Err(e) = res {
+                    error!("Rollback renamed object(s): {}", e);
+                }
+            }
+            cursor = Some(file.id);
+        }
+    }
+
+    if let Err(e) = update_job_progress(db_pool, &job.id, None, 0, 0).await {
+        error!("{}", e);
+    }
+}
+
+/// Renames the dir and resets the job cursor for the cleanup phase
+async fn switch_dir_name(db_pool: &Pool, job_id: &str, dir_id: &str, name: &str) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let jid = job_id.to_string();
+    let did = dir_id.to_string();
+    let name_copy = name.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                diesel::update(dirs::table.find(did))
+                    .set((dirs::name.eq(name_copy), dirs::updated_at.eq(today)))
+                    .execute(conn)?;
+                diesel::update(jobs::table.find(jid))
+                    .set((jobs::cursor.eq(None::<String>), jobs::updated_at.eq(today)))
+                    .execute(conn)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error renaming directory".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
@@ -3,6 +3,8 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use derive_more::From;
+use std::io::ErrorKind;
+use tracing::error;
 
 use crate::web::response::to_error_response;
 
@@ -16,8 +18,15 @@ pub enum Error {
     Forbidden(String),
     ValidationError(String),
     MissingUploadFile(String),
+    MissingFileName,
+    InvalidMultipart(String),
+    UploadAborted(String),
+    InsufficientStorage,
+    PayloadTooLarge(String),
+    ChecksumMismatch,
     FileTypeNotAllowed,
     NotFound(String),
+    Conflict(String),
     InvalidAuthToken,
     InsufficientAuthScope,
     NoAuthToken,
@@ -47,8 +56,15 @@ impl core::fmt::Display for Error {
             Self::Forbidden(val) => write!(f, "{}", val),
             Self::ValidationError(val) => write!(f, "{}", val),
             Self::MissingUploadFile(val) => write!(f, "{}", val),
+            Self::MissingFileName => write!(f, "Upload file name is missing"),
+            Self::InvalidMultipart(val) => write!(f, "{}", val),
+            Self::UploadAborted(val) => write!(f, "{}", val),
+            Self::InsufficientStorage => write!(f, "Not enough storage space for the upload"),
+            Self::PayloadTooLarge(val) => write!(f, "{}", val),
+            Self::ChecksumMismatch => write!(f, "Checksum mismatch"),
             Self::FileTypeNotAllowed => write!(f, "{}", "File type not allowed"),
             Self::NotFound(val) => write!(f, "{}", val),
+            Self::Conflict(val) => write!(f, "{}", val),
             Self::InvalidAuthToken => write!(f, "Invalid auth token"),
             Self::InsufficientAuthScope => write!(f, "Insufficient auth scope"),
             Self::NoAuthToken => write!(f, "No auth token"),
@@ -64,6 +80,17 @@ impl core::fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// Maps a failed write of uploaded data, a full disk is not an internal error
+    pub fn from_write(e: std::io::Error, message: &str) -> Self {
+        error!("{}: {}", message, e);
+        match e.kind() {
+            ErrorKind::StorageFull => Self::InsufficientStorage,
+            _ => Self::AnyError(message.to_string()),
+        }
+    }
+}
+
 // Allow errors to be rendered as response
 impl IntoResponse for Error {
     fn into_response(self) -> Response<Body> {
@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use deadpool_diesel::sqlite::Pool;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::buckets::BucketDto;
+use crate::dirs::list_all_bucket_dirs;
+use crate::storage::{StorageBackend, object_path};
+use crate::{Error, Result};
+
+use super::{ORIGINAL_PATH, list_dir_files_after, update_file_checksum};
+
+const VERIFY_BATCH_SIZE: i64 = 50;
+
+/// Result of comparing stored objects against their recorded checksums
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumReport {
+    pub checked: usize,
+
+    // Files uploaded before checksums were recorded
+    pub unchecked: usize,
+
+    // Object paths of corrupted and missing originals
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl ChecksumReport {
+    pub fn is_intact(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Result of recording checksums of files uploaded before checksums were
+#[derive(Debug, Clone, Default)]
+pub struct BackfillReport {
+    pub updated: usize,
+
+    // Object paths of missing originals, their files keep no checksum
+    pub missing: Vec<String>,
+}
+
+/// Hex encoded SHA-256 of the file contents
+pub fn file_checksum(path: &Path) -> Result<String> {
+    let Ok(mut file) = File::open(path) else {
+        return Err("Unable to read file for checksum".into());
+    };
+    let mut hasher = Sha256::new();
+    if io::copy(&mut file, &mut hasher).is_err() {
+        return Err("Unable to read file for checksum".into());
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checksums supplied by clients must be a hex encoded SHA-256
+pub fn valid_checksum(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Downloads the original of every file of the bucket, including the trash,
+/// and compares it with the checksum recorded on upload
+pub async fn verify_bucket_checksums(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket: &BucketDto,
+    work_dir: &Path,
+) -> Result<ChecksumReport> {
+    if fs::create_dir_all(work_dir).await.is_err() {
+        return Err("Unable to create verify dir".into());
+    }
+
+    let mut report = ChecksumReport::default();
+    let dirs = list_all_bucket_dirs(db_pool, &bucket.id).await?;
+    for dir in dirs.iter() {
+        let mut cursor: Option<String> = None;
+        loop {
+            let files =
+                list_dir_files_after(db_pool, &dir.id, cursor.as_deref(), VERIFY_BATCH_SIZE)
+                    .await?;
+            let Some(last) = files.last() else {
+                break;
+            };
+            cursor = Some(last.id.clone());
+
+            for file in files.iter() {
+                let Some(expected) = &file.checksum else {
+                    report.unchecked += 1;
+                    continue;
+                };
+
+                let path = object_path(&dir.name, ORIGINAL_PATH, &file.filename);
+                let dest = work_dir.join(format!("{}.verify", file.id));
+                let actual = download_checksum(storage_client, &bucket.name, &path, &dest).await?;
+
+                report.checked += 1;
+                match actual {
+                    Some(actual) if &actual == expected => {}
+                    Some(_) => report.mismatched.push(path),
+                    None => report.missing.push(path),
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Downloads the original of every file of the bucket without a checksum,
+/// including the trash, and records its checksum
+pub async fn backfill_bucket_checksums(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket: &BucketDto,
+    work_dir: &Path,
+) -> Result<BackfillReport> {
+    if fs::create_dir_all(work_dir).await.is_err() {
+        return Err("Unable to create verify dir".into());
+    }
+
+    let mut report = BackfillReport::default();
+    let dirs = list_all_bucket_dirs(db_pool, &bucket.id).await?;
+    for dir in dirs.iter() {
+        let mut cursor: Option<String> = None;
+        loop {
+            let files =
+                list_dir_files_after(db_pool, &dir.id, cursor.as_deref(), VERIFY_BATCH_SIZE)
+                    .await?;
+            let Some(last) = files.last() else {
+                break;
+            };
+            cursor = Some(last.id.clone());
+
+            for file in files.iter().filter(|file| file.checksum.is_none()) {
+                let path = object_path(&dir.name, ORIGINAL_PATH, &file.filename);
+                let dest = work_dir.join(format!("{}.verify", file.id));
+                match download_checksum(storage_client, &bucket.name, &path, &dest).await? {
+                    Some(checksum) => {
+                        if update_file_checksum(db_pool, &file.id, &checksum).await? {
+                            report.updated += 1;
+                        }
+                    }
+                    None => report.missing.push(path),
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checksum of the stored object, None when the object does not exist.
+/// Any other storage error is returned as is.
+async fn download_checksum(
+    storage_client: &dyn StorageBackend,
+    bucket_name: &str,
+    path: &str,
+    dest: &Path,
+) -> Result<Option<String>> {
+    let res = storage_client
+        .download_object(bucket_name, path, dest)
+        .await;
+    let actual = match res {
+        Ok(_) => file_checksum(dest).map(Some),
+        // Client errors other than not found, ie: permissions, are not missing objects
+        Err(Error::ValidationError(message)) => {
+            match storage_client.object_exists(bucket_name, path).await {
+                Ok(false) => Ok(None),
+                Ok(true) => Err(Error::ValidationError(message)),
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let _ = fs::remove_file(dest).await;
+    actual
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::test_helpers::{TestApp, pdf_document, png_image};
+
+    #[tokio::test]
+    async fn test_verify_bucket_checksums() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let storage = app.storage.as_ref();
+        let work_dir = app.state.config.upload_dir.join("verify");
+
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        let notes = res.body["filename"].as_str().unwrap().to_string();
+        let res = app.upload("photo.png", &png_image(64, 48)).await;
+        let photo = res.body["filename"].as_str().unwrap().to_string();
+        app.upload("other.pdf", &pdf_document()).await;
+
+        let report = verify_bucket_checksums(db_pool, storage, &app.bucket, &work_dir)
+            .await
+            .unwrap();
+        assert_eq!(report.checked, 3);
+        assert!(report.is_intact());
+
+        let notes_path = format!("album/orig/{}", notes);
+        storage.put_object("photos", &notes_path, "application/pdf", b"corrupted");
+        let photo_path = format!("album/orig/{}", photo);
+        storage.delete_object("photos", &photo_path).await.unwrap();
+
+        let report = verify_bucket_checksums(db_pool, storage, &app.bucket, &work_dir)
+            .await
+            .unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(report.mismatched, vec![notes_path]);
+        assert_eq!(report.missing, vec![photo_path]);
+        assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_bucket_checksums() {
+        use diesel::prelude::*;
+
+        use crate::schema::files;
+
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let storage = app.storage.as_ref();
+        let work_dir = app.state.config.upload_dir.join("verify");
+
+        app.upload("notes.pdf", &pdf_document()).await;
+        let res = app.upload("photo.png", &png_image(64, 48)).await;
+        let photo = res.body["filename"].as_str().unwrap().to_string();
+
+        // Same as files uploaded before checksums were recorded
+        let db = db_pool.get().await.unwrap();
+        db.interact(|conn| {
+            diesel::update(files::table)
+                .set(files::checksum.eq(None::<String>))
+                .execute(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let photo_path = format!("album/orig/{}", photo);
+        storage.delete_object("photos", &photo_path).await.unwrap();
+
+        let report = verify_bucket_checksums(db_pool, storage, &app.bucket, &work_dir)
+            .await
+            .unwrap();
+        assert_eq!(report.unchecked, 2);
+
+        let report = backfill_bucket_checksums(db_pool, storage, &app.bucket, &work_dir)
+            .await
+            .unwrap();
+        assert_eq!(report.updated, 1);
+        assert_eq!(report.missing, vec![photo_path.clone()]);
+        assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+
+        let report = verify_bucket_checksums(db_pool, storage, &app.bucket, &work_dir)
+            .await
+            .unwrap();
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.unchecked, 1);
+        assert!(report.is_intact());
+
+        // Recorded checksums are never replaced
+        let report = backfill_bucket_checksums(db_pool, storage, &app.bucket, &work_dir)
+            .await
+            .unwrap();
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.missing, vec![photo_path]);
+    }
+
+    #[test]
+    fn test_valid_checksum() {
+        let checksum = hex::encode(Sha256::digest(b"hello"));
+        assert!(valid_checksum(&checksum));
+        assert!(valid_checksum(&checksum.to_uppercase()));
+        assert!(!valid_checksum(&checksum[1..]));
+        assert!(!valid_checksum(&format!("{}z", &checksum[1..])));
+    }
+}
@@ -0,0 +1,133 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use serde::Serialize;
+use tracing::error;
+
+use crate::Result;
+use crate::schema::{dirs, files};
+
+use super::{FileDto, FileObject};
+
+// Groups returned at once, clean up and list again for more
+const MAX_DUPLICATE_GROUPS: i64 = 100;
+
+/// Files of a bucket sharing the same content
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub checksum: String,
+    pub size: i64,
+    pub files: Vec<FileDto>,
+}
+
+/// Finds the oldest live file of the bucket with the given content that
+/// owns its objects, ie: not a link
+pub async fn find_bucket_duplicate(
+    db_pool: &Pool,
+    bucket_id: &str,
+    checksum: &str,
+) -> Result<Option<FileObject>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let sum = checksum.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            files::table
+                .inner_join(dirs::table)
+                .filter(dirs::bucket_id.eq(bid.as_str()))
+                .filter(dirs::deleted_at.is_null())
+                .filter(files::deleted_at.is_null())
+                .filter(files::checksum.eq(sum.as_str()))
+                .filter(files::linked_id.is_null())
+                .select(FileObject::as_select())
+                .order(files::created_at.asc())
+                .first::<FileObject>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding duplicate file".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Groups live files of the bucket by content, largest groups first
+pub async fn list_duplicate_groups(db_pool: &Pool, bucket_id: &str) -> Result<Vec<DuplicateGroup>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            let live_files = || {
+                files::table
+                    .inner_join(dirs::table)
+                    .filter(dirs::bucket_id.eq(bid.clone()))
+                    .filter(dirs::deleted_at.is_null())
+                    .filter(files::deleted_at.is_null())
+            };
+
+            let checksums = live_files()
+                .filter(files::checksum.is_not_null())
+                .group_by(files::checksum)
+                .having(count_star().gt(1))
+                .select(files::checksum)
+                .order((count_star().desc(), files::checksum.asc()))
+                .limit(MAX_DUPLICATE_GROUPS)
+                .load::<Option<String>>(conn)?;
+            let checksums: Vec<String> = checksums.into_iter().flatten().collect();
+
+            let items = live_files()
+                .filter(files::checksum.eq_any(&checksums))
+                .select(FileObject::as_select())
+                .order(files::created_at.asc())
+                .load::<FileObject>(conn)?;
+
+            let groups: Vec<DuplicateGroup> = checksums
+                .into_iter()
+                .map(|checksum| {
+                    let files: Vec<FileDto> = items
+                        .iter()
+                        .filter(|f| f.checksum.as_deref() == Some(checksum.as_str()))
+                        .map(|f| f.clone().into())
+                        .collect();
+                    let size = files.first().map(|f| f.size).unwrap_or(0);
+                    DuplicateGroup {
+                        checksum,
+                        size,
+                        files,
+                    }
+                })
+                .collect();
+            QueryResult::Ok(groups)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing duplicate files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
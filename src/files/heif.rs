@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use image::DynamicImage;
+
+use crate::Result;
+
+/// Images in a HEIF container, ie: iPhone photos, only decoded when built
+/// with the `heif` feature which links against libheif
+pub const HEIF_IMAGE_TYPES: [&str; 2] = ["image/heif", "image/avif"];
+
+pub fn is_heif_image(content_type: &str) -> bool {
+    HEIF_IMAGE_TYPES.contains(&content_type)
+}
+
+/// Decodes the primary image of the container. Rotation and mirroring stored
+/// in the container are already applied.
+#[cfg(feature = "heif")]
+pub fn read_heif_image(path: &Path) -> Result<DynamicImage> {
+    use image::{RgbImage, RgbaImage};
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+    use tracing::error;
+
+    let Some(name) = path.to_str() else {
+        return Err("Invalid image path".into());
+    };
+    let decoded = HeifContext::read_from_file(name).and_then(|ctx| {
+        let handle = ctx.primary_image_handle()?;
+        let chroma = match handle.has_alpha_channel() {
+            true => RgbChroma::Rgba,
+            false => RgbChroma::Rgb,
+        };
+        LibHeif::new().decode(&handle, ColorSpace::Rgb(chroma), None)
+    });
+    let decoded = match decoded {
+        Ok(img) => img,
+        Err(e) => {
+            let msg = format!("Unable to decode image: {}", e);
+            error!("{}", msg);
+            return Err(msg.as_str().into());
+        }
+    };
+
+    let planes = decoded.planes();
+    let Some(plane) = planes.interleaved else {
+        return Err("Unable to decode image: no interleaved plane".into());
+    };
+
+    // Rows may be padded, copy only the pixels
+    let channels = (plane.storage_bits_per_pixel / 8) as usize;
+    let row_len = plane.width as usize * channels;
+    let mut pixels: Vec<u8> = Vec::with_capacity(row_len * plane.height as usize);
+    for y in 0..plane.height as usize {
+        let start = y * plane.stride;
+        pixels.extend_from_slice(&plane.data[start..start + row_len]);
+    }
+
+    let img = match channels {
+        4 => RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8),
+        _ => RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8),
+    };
+    match img {
+        Some(img) => Ok(img),
+        None => Err("Unable to decode image: invalid pixel data".into()),
+    }
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn read_heif_image(_path: &Path) -> Result<DynamicImage> {
+    Err("Decoding HEIF images requires the heif feature".into())
+}
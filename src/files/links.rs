@@ -0,0 +1,208 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+use tracing::error;
+
+use crate::Result;
+use crate::schema::{dirs, files};
+use crate::storage::{StorageBackend, copy_file_object, delete_file_object};
+
+use super::{FileDto, FileObject};
+
+/// Fills in where the objects of linked files are stored, ie: under the dir
+/// and filename of the file they are linked to, with its current versions
+pub async fn resolve_links(db_pool: &Pool, items: Vec<FileDto>) -> Result<Vec<FileDto>> {
+    let ids: Vec<String> = items.iter().filter_map(|f| f.linked_id.clone()).collect();
+    if ids.is_empty() {
+        return Ok(items);
+    }
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            files::table
+                .inner_join(dirs::table)
+                .filter(files::id.eq_any(ids))
+                .select((FileObject::as_select(), dirs::name))
+                .load::<(FileObject, String)>(conn)
+        })
+        .await;
+
+    let owners = match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(owners) => owners,
+            Err(e) => {
+                error!("{}", e);
+                return Err("Error reading linked files".into());
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            return Err("Error using the db connection".into());
+        }
+    };
+
+    let items = items
+        .into_iter()
+        .map(|mut file| {
+            let owner = owners
+                .iter()
+                .find(|(owner, _)| file.linked_id.as_deref() == Some(owner.id.as_str()));
+            if let Some((owner, dir_name)) = owner {
+                let owner_dto: FileDto = owner.clone().into();
+                file.filename = owner_dto.filename;
+                file.img_versions = owner_dto.img_versions;
+                file.object_dir = Some(dir_name.clone());
+            }
+            file
+        })
+        .collect();
+    Ok(items)
+}
+
+/// Same as `resolve_links` for a single file
+pub async fn resolve_link(db_pool: &Pool, file: FileDto) -> Result<FileDto> {
+    let mut items = resolve_links(db_pool, vec![file]).await?;
+    match items.pop() {
+        Some(file) => Ok(file),
+        None => Err("Error reading linked files".into()),
+    }
+}
+
+/// Deletes the stored objects of a removed file, unless it is a link or its
+/// objects were handed over to one of its links
+pub async fn release_file_objects(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket_name: &str,
+    dir_name: &str,
+    file: &FileObject,
+) -> Result<()> {
+    if file.linked_id.is_some() {
+        return Ok(());
+    }
+    if hand_over_objects(db_pool, storage_client, bucket_name, dir_name, file).await? {
+        return Ok(());
+    }
+
+    let dto: FileDto = file.clone().into();
+    delete_file_object(storage_client, bucket_name, dir_name, &dto).await
+}
+
+/// Makes the oldest link of a file that is going away the owner of its
+/// objects, copied under the dir of the link when it lives elsewhere.
+/// Returns whether the objects are still used where they are.
+pub async fn hand_over_objects(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket_name: &str,
+    dir_name: &str,
+    file: &FileObject,
+) -> Result<bool> {
+    let Some((heir, heir_dir)) = find_oldest_link(db_pool, &file.id).await? else {
+        return Ok(false);
+    };
+
+    let kept = heir_dir == dir_name;
+    let dto: FileDto = file.clone().into();
+    if !kept {
+        copy_file_object(
+            storage_client,
+            (bucket_name, dir_name),
+            (bucket_name, &heir_dir),
+            &dto,
+            &file.filename,
+        )
+        .await?;
+    }
+
+    if let Err(e) = promote_link(db_pool, &heir.id, file).await {
+        if !kept {
+            let res = delete_file_object(storage_client, bucket_name, &heir_dir, &dto).await;
+            if let Err(e) = res {
+                error!("Cleanup copied object(s): {}", e);
+            }
+        }
+        return Err(e);
+    }
+
+    Ok(kept)
+}
+
+/// Oldest file linked to the given one along with its dir name, including
+/// the ones in the trash
+async fn find_oldest_link(db_pool: &Pool, id: &str) -> Result<Option<(FileObject, String)>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let fid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            files::table
+                .inner_join(dirs::table)
+                .filter(files::linked_id.eq(fid))
+                .select((FileObject::as_select(), dirs::name))
+                .order((files::created_at.asc(), files::id.asc()))
+                .first::<(FileObject, String)>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding linked file".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Turns the link into a regular file with the objects of the file it was
+/// linked to, the other links of that file now point to it
+async fn promote_link(db_pool: &Pool, heir_id: &str, file: &FileObject) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let hid = heir_id.to_string();
+    let file_copy = file.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                diesel::update(files::table.find(&hid))
+                    .set((
+                        files::linked_id.eq(None::<String>),
+                        files::filename.eq(&file_copy.filename),
+                        files::img_versions.eq(&file_copy.img_versions),
+                    ))
+                    .execute(conn)?;
+                diesel::update(files::table.filter(files::linked_id.eq(&file_copy.id)))
+                    .set(files::linked_id.eq(&hid))
+                    .execute(conn)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating linked files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
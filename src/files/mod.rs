@@ -1,5 +1,19 @@
+mod checksum;
+mod duplicates;
+mod heif;
+mod links;
 mod models;
 mod queries;
+mod regenerate;
+mod similar;
+mod transform;
 
+pub use checksum::*;
+pub use duplicates::*;
+pub use heif::*;
+pub use links::*;
 pub use models::*;
 pub use queries::*;
+pub use regenerate::*;
+pub use similar::*;
+pub use transform::*;
@@ -4,14 +4,38 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+use crate::buckets::BucketDto;
+use crate::dirs::Dir;
+
+use super::is_heif_image;
+
 pub const ORIGINAL_PATH: &str = "orig";
-pub const ALLOWED_IMAGE_TYPES: [&str; 4] = ["image/jpeg", "image/pjpeg", "image/png", "image/gif"];
+pub const ALLOWED_IMAGE_TYPES: [&str; 6] = [
+    "image/jpeg",
+    "image/pjpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/tiff",
+];
+
+/// Images that can be decoded to create their versions
+pub fn allowed_image_type(content_type: &str) -> bool {
+    ALLOWED_IMAGE_TYPES.contains(&content_type)
+        || (cfg!(feature = "heif") && is_heif_image(content_type))
+}
 
 /// Maximum image dimension before creating a preview version
 pub const MAX_DIMENSION: u32 = 1000;
 pub const MAX_PREVIEW_DIMENSION: u32 = 2000;
 pub const MAX_THUMB_DIMENSION: u32 = 200;
 
+// Dirs under the upload dir that hold other files than versions
+const RESERVED_VERSION_NAMES: [&str; 2] = ["tmp", "verify"];
+
+/// Quality of lossy image versions when neither the profile nor the config set one
+pub const DEFAULT_IMG_QUALITY: u8 = 80;
+
 #[derive(Debug, Clone, Queryable, Selectable, Insertable, Serialize)]
 #[diesel(table_name = crate::schema::files)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -27,6 +51,29 @@ pub struct FileObject {
     pub img_taken_at: Option<i64>,
     pub created_at: i64,
     pub updated_at: i64,
+    pub deleted_at: Option<i64>,
+
+    // Hex encoded SHA-256 of the original, missing on older files
+    pub checksum: Option<String>,
+
+    // Hex encoded perceptual hash of images, missing on older files
+    pub phash: Option<String>,
+
+    // File whose stored objects are shared by this linked upload
+    pub linked_id: Option<String>,
+}
+
+/// Outcome of a single file of a batch upload
+#[derive(Debug, Clone, Serialize)]
+pub struct FileUploadResult {
+    pub name: String,
+    pub status_code: u16,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileDto>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -47,8 +94,30 @@ pub struct FileDto {
     pub img_versions: Option<Vec<ImgVersionDto>>,
     pub img_taken_at: Option<i64>,
 
+    // Only available for image files, url template to resize the original on demand
+    pub transform_url: Option<String>,
+
     pub created_at: i64,
     pub updated_at: i64,
+
+    // Set when the file is in the trash
+    pub deleted_at: Option<i64>,
+
+    pub checksum: Option<String>,
+
+    // Only available for image files
+    pub phash: Option<String>,
+
+    // Existing file with the same content, set on upload and on linked files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+
+    #[serde(skip)]
+    pub linked_id: Option<String>,
+
+    // Dir name the objects of a linked file are stored under
+    #[serde(skip)]
+    pub object_dir: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +127,9 @@ pub struct FilePayload {
     pub filename: String,
     pub path: PathBuf,
     pub size: i64,
+
+    // Computed while receiving the upload, otherwise from the saved file
+    pub checksum: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -75,6 +147,31 @@ impl Default for PhotoExif {
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Validate, AsChangeset)]
+#[diesel(table_name = crate::schema::files)]
+pub struct UpdateFile {
+    #[validate(length(min = 1, max = 250))]
+    pub name: Option<String>,
+
+    pub img_taken_at: Option<i64>,
+}
+
+/// Target of a move or copy, bucket defaults to the current bucket
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct FileDestination {
+    pub bucket_id: Option<String>,
+    pub dir_id: String,
+
+    #[validate(length(min = 1, max = 250))]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileLocation {
+    pub bucket: BucketDto,
+    pub dir: Dir,
+}
+
 #[derive(Debug, Clone, Deserialize, Validate)]
 pub struct ListFilesParams {
     #[validate(range(min = 1, max = 1000))]
@@ -115,6 +212,10 @@ impl From<FileDto> for FileObject {
             img_taken_at: file.img_taken_at,
             created_at: file.created_at,
             updated_at: file.updated_at,
+            deleted_at: file.deleted_at,
+            checksum: file.checksum,
+            phash: file.phash,
+            linked_id: file.linked_id,
         }
     }
 }
@@ -127,6 +228,10 @@ impl From<FileObject> for FileDto {
                 let versions: Vec<ImgVersionDto> = versions_str
                     .split(',')
                     .filter_map(|s| s.parse::<ImgVersionDto>().ok())
+                    .map(|mut v| {
+                        v.content_type.get_or_insert(file.content_type.clone());
+                        v
+                    })
                     .collect();
 
                 if versions.len() > 0 {
@@ -148,9 +253,16 @@ impl From<FileObject> for FileDto {
             is_image: file.is_image == 1,
             img_versions,
             img_taken_at: file.img_taken_at,
+            transform_url: None,
             url: None,
             created_at: file.created_at,
             updated_at: file.updated_at,
+            deleted_at: file.deleted_at,
+            checksum: file.checksum,
+            phash: file.phash,
+            duplicate_of: file.linked_id.clone(),
+            linked_id: file.linked_id,
+            object_dir: None,
         }
     }
 }
@@ -161,16 +273,37 @@ pub struct ImgDimension {
     pub height: u32,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ImgVersion {
-    #[serde(rename = "orig")]
     Original,
-
-    #[serde(rename = "prev")]
     Preview,
-
-    #[serde(rename = "thumb")]
     Thumbnail,
+
+    /// Named version declared by a bucket image profile
+    Custom(String),
+}
+
+/// How an image is resized into the bounds of a version
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImgFit {
+    /// Fits within the bounds, keeping the aspect ratio
+    Contain,
+
+    /// Fills the bounds, cropping what overflows
+    Cover,
+}
+
+/// Encoding of an image version
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImgFormat {
+    Jpeg,
+    Png,
+
+    /// Lossless, the bundled encoder has no lossy mode
+    Webp,
+    Avif,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -178,11 +311,36 @@ pub struct ImgVersionDto {
     pub version: ImgVersion,
     pub dimension: ImgDimension,
     pub url: Option<String>,
+
+    // Missing on versions of older files, which share the content type of the original
+    pub content_type: Option<String>,
 }
 
 impl ImgVersionDto {
     pub fn to_path(&self, prefix: &PathBuf, filename: &str) -> PathBuf {
-        prefix.clone().join(self.version.to_string()).join(filename)
+        prefix
+            .clone()
+            .join(self.version.to_string())
+            .join(self.filename(filename))
+    }
+
+    /// Filename of the stored version, derived versions carry the extension
+    /// of their encoding, ie: the jpeg thumb of `abc-photo.png` is `abc-photo.jpeg`
+    pub fn filename(&self, filename: &str) -> String {
+        if self.version == ImgVersion::Original {
+            return filename.to_string();
+        }
+        let format = self
+            .content_type
+            .as_deref()
+            .and_then(ImgFormat::from_content_type);
+        match format {
+            Some(format) => {
+                let stem = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+                format!("{}.{}", stem, format)
+            }
+            None => filename.to_string(),
+        }
     }
 }
 
@@ -193,7 +351,11 @@ impl core::fmt::Display for ImgVersionDto {
             f,
             "{}:{}x{}",
             self.version, self.dimension.width, self.dimension.height
-        )
+        )?;
+        match &self.content_type {
+            Some(content_type) => write!(f, ":{}", content_type),
+            None => Ok(()),
+        }
     }
 }
 
@@ -201,10 +363,11 @@ impl core::fmt::Display for ImgVersionDto {
 impl FromStr for ImgVersionDto {
     type Err = String;
 
-    /// Parse string like "orig:200x400" into ImgVersionDto without the url part
+    /// Parse string like "orig:200x400" or "medium:600x400:image/jpeg"
+    /// into ImgVersionDto without the url part
     fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
         let parts: Vec<&str> = s.split(':').collect();
-        if parts.len() != 2 {
+        if parts.len() != 2 && parts.len() != 3 {
             return Err("Invalid image version dto".to_string());
         }
 
@@ -225,6 +388,7 @@ impl FromStr for ImgVersionDto {
                 height: dimension[1],
             },
             url: None,
+            content_type: parts.get(2).map(|c| c.to_string()),
         })
     }
 }
@@ -236,10 +400,20 @@ impl core::fmt::Display for ImgVersion {
             Self::Original => write!(f, "{}", "orig"),
             Self::Preview => write!(f, "{}", "prev"),
             Self::Thumbnail => write!(f, "{}", "thumb"),
+            Self::Custom(name) => write!(f, "{}", name),
         }
     }
 }
 
+impl Serialize for ImgVersion {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
 /// Convert from &str to ImgVersion
 impl TryFrom<&str> for ImgVersion {
     type Error = String;
@@ -249,7 +423,91 @@ impl TryFrom<&str> for ImgVersion {
             "orig" => Ok(Self::Original),
             "prev" => Ok(Self::Preview),
             "thumb" => Ok(Self::Thumbnail),
+            _ if valid_version_name(value) => Ok(Self::Custom(value.to_string())),
             _ => Err(format!("Invalid image version: {}", value)),
         }
     }
 }
+
+/// Version names end up in object paths, ie: lowercase letters, digits,
+/// dashes and underscores, up to 20 characters
+pub fn valid_version_name(name: &str) -> bool {
+    if RESERVED_VERSION_NAMES.contains(&name) {
+        return false;
+    }
+
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_lowercase() || c.is_ascii_digit() => {}
+        _ => return false,
+    }
+    name.len() <= 20
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+impl TryFrom<&str> for ImgFit {
+    type Error = String;
+
+    fn try_from(value: &str) -> core::result::Result<Self, Self::Error> {
+        match value {
+            "contain" => Ok(Self::Contain),
+            "cover" => Ok(Self::Cover),
+            _ => Err(format!("Invalid image fit: {}", value)),
+        }
+    }
+}
+
+impl core::fmt::Display for ImgFit {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Contain => write!(f, "contain"),
+            Self::Cover => write!(f, "cover"),
+        }
+    }
+}
+
+impl ImgFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/jpeg" => Some(Self::Jpeg),
+            "image/png" => Some(Self::Png),
+            "image/webp" => Some(Self::Webp),
+            "image/avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&str> for ImgFormat {
+    type Error = String;
+
+    fn try_from(value: &str) -> core::result::Result<Self, Self::Error> {
+        match value {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "png" => Ok(Self::Png),
+            "webp" => Ok(Self::Webp),
+            "avif" => Ok(Self::Avif),
+            _ => Err(format!("Invalid image format: {}", value)),
+        }
+    }
+}
+
+impl core::fmt::Display for ImgFormat {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Jpeg => write!(f, "jpeg"),
+            Self::Png => write!(f, "png"),
+            Self::Webp => write!(f, "webp"),
+            Self::Avif => write!(f, "avif"),
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use chrono::{DateTime, NaiveDateTime};
 use deadpool_diesel::sqlite::Pool;
 use exif::{In, Tag};
-use google_cloud_storage::client::Client;
 use image::ImageReader;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
 use image::imageops;
 use std::fs::File;
+use std::io::Write;
 use std::path::PathBuf;
 
 use diesel::dsl::count_star;
@@ -14,24 +17,31 @@ use image::DynamicImage;
 use tracing::error;
 use validator::Validate;
 
-use crate::buckets::BucketDto;
-use crate::dirs::{Dir, update_dir_timestamp};
+use crate::buckets::{BucketDto, DedupePolicy, ImgProfileDto, bucket_img_profiles};
+use crate::config::ImageConfig;
+use crate::dirs::{Dir, dir_accepts_objects, update_dir_stats, update_dir_timestamp};
 use crate::schema::files::{self, dsl};
-use crate::storage::upload_object;
-use crate::util::generate_id;
+use crate::storage::{StorageBackend, copy_file_object, delete_file_object, upload_object};
 use crate::util::truncate_string;
+use crate::util::{generate_id, slugify_prefixed};
 use crate::validators::flatten_errors;
 use crate::web::pagination::Paginated;
 use crate::{Error, Result};
 
 use super::{
-    ALLOWED_IMAGE_TYPES, FileDto, FileObject, FilePayload, ImgDimension, ImgVersion, ImgVersionDto,
-    ListFilesParams, MAX_DIMENSION, MAX_PREVIEW_DIMENSION, MAX_THUMB_DIMENSION, ORIGINAL_PATH,
-    PhotoExif,
+    FileDestination, FileDto, FileLocation, FileObject, FilePayload, ImgDimension, ImgFit,
+    ImgFormat, ImgTransform, ImgVersion, ImgVersionDto, ListFilesParams, MAX_DIMENSION,
+    ORIGINAL_PATH, PhotoExif, UpdateFile, allowed_image_type, file_checksum, find_bucket_duplicate,
+    image_hash, is_heif_image, read_heif_image, release_file_objects, resolve_link,
 };
 
 const MAX_PER_PAGE: i32 = 50;
-const MAX_FILES: i32 = 1000;
+
+/// Most files a dir may hold, trashed files excluded
+pub const MAX_FILES: i32 = 1000;
+
+// From 1 (slowest, smallest) to 10 (fastest)
+const AVIF_ENCODER_SPEED: u8 = 8;
 
 pub async fn list_files(
     db_pool: &Pool,
@@ -77,7 +87,9 @@ pub async fn list_files(
     let conn_result = db
         .interact(move |conn| {
             let mut query = dsl::files.into_boxed();
-            query = query.filter(dsl::dir_id.eq(did.as_str()));
+            query = query
+                .filter(dsl::dir_id.eq(did.as_str()))
+                .filter(dsl::deleted_at.is_null());
 
             if let Some(keyword) = params_copy.keyword {
                 if keyword.len() > 0 {
@@ -120,7 +132,9 @@ async fn list_files_count(db_pool: &Pool, dir_id: &str, params: &ListFilesParams
     let conn_result = db
         .interact(move |conn| {
             let mut query = dsl::files.into_boxed();
-            query = query.filter(dsl::dir_id.eq(did.as_str()));
+            query = query
+                .filter(dsl::dir_id.eq(did.as_str()))
+                .filter(dsl::deleted_at.is_null());
             if let Some(keyword) = params_copy.keyword {
                 if keyword.len() > 0 {
                     let pattern = format!("%{}%", keyword);
@@ -158,6 +172,7 @@ pub async fn find_dir_file(pool: &Pool, dir_id: &str, name: &str) -> Result<Opti
             dsl::files
                 .filter(dsl::dir_id.eq(did.as_str()))
                 .filter(dsl::name.eq(name_copy.as_str()))
+                .filter(dsl::deleted_at.is_null())
                 .select(FileObject::as_select())
                 .first::<FileObject>(conn)
                 .optional()
@@ -179,6 +194,7 @@ pub async fn find_dir_file(pool: &Pool, dir_id: &str, name: &str) -> Result<Opti
     }
 }
 
+/// Counts files of the dir that are not in the trash
 pub async fn count_dir_files(db_pool: &Pool, dir_id: &str) -> Result<i64> {
     let Ok(db) = db_pool.get().await else {
         return Err("Error getting db connection".into());
@@ -189,6 +205,7 @@ pub async fn count_dir_files(db_pool: &Pool, dir_id: &str) -> Result<i64> {
         .interact(move |conn| {
             dsl::files
                 .filter(dsl::dir_id.eq(did.as_str()))
+                .filter(dsl::deleted_at.is_null())
                 .select(count_star())
                 .get_result::<i64>(conn)
         })
@@ -209,13 +226,109 @@ pub async fn count_dir_files(db_pool: &Pool, dir_id: &str) -> Result<i64> {
     }
 }
 
+/// Counts files of the dir including the ones in the trash
+pub async fn count_all_dir_files(db_pool: &Pool, dir_id: &str) -> Result<i64> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let did = dir_id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::files
+                .filter(dsl::dir_id.eq(did.as_str()))
+                .select(count_star())
+                .get_result::<i64>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(count_res) => match count_res {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error counting files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Lists files of the dir ordered by id, starting after the cursor.
+/// Includes files in the trash since their objects live under the dir too.
+pub async fn list_dir_files_after(
+    db_pool: &Pool,
+    dir_id: &str,
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<Vec<FileObject>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let did = dir_id.to_string();
+    let cursor_copy = cursor.map(|c| c.to_string());
+    let conn_result = db
+        .interact(move |conn| {
+            let mut query = dsl::files.into_boxed();
+            query = query.filter(dsl::dir_id.eq(did));
+            if let Some(cursor) = cursor_copy {
+                query = query.filter(dsl::id.gt(cursor));
+            }
+            query
+                .limit(limit)
+                .select(FileObject::as_select())
+                .order(dsl::id.asc())
+                .load::<FileObject>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{e}");
+                Err("Error reading files".into())
+            }
+        },
+        Err(e) => {
+            error!("{e}");
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Checks early that a file with the name can still be added to the dir,
+/// before its contents are received
+pub async fn ensure_file_slot(db_pool: &Pool, dir_id: &str, name: &str) -> Result<()> {
+    if count_dir_files(db_pool, dir_id).await? >= MAX_FILES as i64 {
+        return Err(Error::ValidationError(
+            "Maximum number of files reached".to_string(),
+        ));
+    }
+
+    if find_dir_file(db_pool, dir_id, name).await?.is_some() {
+        let short_name = truncate_string(name, 20);
+        return Err(Error::ValidationError(format!(
+            "{} already exists",
+            short_name,
+        )));
+    }
+
+    Ok(())
+}
+
 pub async fn create_file(
     db_pool: &Pool,
-    storage_client: &Client,
+    storage_client: &dyn StorageBackend,
     bucket: &BucketDto,
     dir: &Dir,
     data: &FilePayload,
-) -> Result<FileObject> {
+    img_config: &ImageConfig,
+) -> Result<FileDto> {
     let mut file_dto = init_file(dir, data)?;
 
     if bucket.images_only && !file_dto.is_image {
@@ -254,6 +367,32 @@ pub async fn create_file(
         )));
     }
 
+    // Same content may already be stored somewhere in the bucket
+    if let Some(policy) = bucket.dedupe_policy
+        && let Some(checksum) = &file_dto.checksum
+        && let Some(existing) = find_bucket_duplicate(db_pool, &bucket.id, checksum).await?
+    {
+        match policy {
+            DedupePolicy::Reject => {
+                if let Err(e) = cleanup_temp_uploads(data, None) {
+                    error!("Cleanup orig file: {}", e);
+                }
+                let short_name = truncate_string(&existing.name, 20);
+                return Err(Error::Conflict(format!(
+                    "Same content already exists as {}",
+                    short_name
+                )));
+            }
+            DedupePolicy::Link => {
+                if let Err(e) = cleanup_temp_uploads(data, None) {
+                    error!("Cleanup orig file: {}", e);
+                }
+                return create_link(db_pool, dir, &file_dto, existing).await;
+            }
+            DedupePolicy::Warn => file_dto.duplicate_of = Some(existing.id),
+        }
+    }
+
     if file_dto.is_image {
         let exif_info = match parse_exif_info(&data.path) {
             Ok(info) => info,
@@ -264,11 +403,26 @@ pub async fn create_file(
             }
         };
 
-        match create_versions(data, &exif_info) {
-            Ok(versions) => {
+        let profiles = match bucket_img_profiles(db_pool, &bucket.id).await {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                if let Err(e) = cleanup_temp_uploads(data, None) {
+                    error!("Cleanup orig file: {}", e);
+                }
+                return Err(e);
+            }
+        };
+
+        let source = ImgSource {
+            content_type: &file_dto.content_type,
+            exif_info: &exif_info,
+        };
+        match create_versions(data, &source, &profiles, img_config) {
+            Ok((versions, phash)) => {
                 if versions.len() > 0 {
                     file_dto.img_versions = Some(versions);
                 }
+                file_dto.phash = Some(phash);
             }
             Err(e) => {
                 if let Err(e) = cleanup_temp_uploads(data, None) {
@@ -291,57 +445,113 @@ pub async fn create_file(
     }
 
     // Save to database
-    let file_db_pool = db_pool.clone();
-    let Ok(db) = file_db_pool.get().await else {
-        if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
-            error!("Cleanup file(s): {}", e);
+    let file: FileObject = file_dto.clone().into();
+    match insert_file(db_pool, &file, &dir.name).await {
+        Ok(false) => {
+            // Objects went under a name the dir is moving away from
+            let res = delete_file_object(storage_client, &bucket.name, &dir.name, &file_dto).await;
+            if let Err(e) = res {
+                error!("Cleanup uploaded object(s): {}", e);
+            }
+            if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
+                error!("Cleanup file(s): {}", e);
+            }
+            Err(Error::Conflict(
+                "Directory has an operation in progress".to_string(),
+            ))
+        }
+        Ok(true) => {
+            // Cleanup files before returning...
+            if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
+                // Can't afford to fail here, we will just log the error...
+                error!("Cleanup file(s): {}", e);
+            }
+
+            // Also update dir
+            let today = chrono::Utc::now().timestamp();
+            let dir_result = update_dir_timestamp(db_pool, &dir.id, today).await;
+            if let Err(e) = dir_result {
+                // Can't afford to fail here, we will just log the error...
+                error!("{}", e);
+            }
+
+            Ok(file_dto)
+        }
+        Err(e) => {
+            if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
+                error!("Cleanup file(s): {}", e);
+            }
+            Err(e)
         }
+    }
+}
+
+/// Records the upload in the dir as a link to the existing file with the
+/// same content, sharing its stored objects instead of uploading new ones
+async fn create_link(
+    db_pool: &Pool,
+    dir: &Dir,
+    file_dto: &FileDto,
+    existing: FileObject,
+) -> Result<FileDto> {
+    let link = FileObject {
+        id: file_dto.id.clone(),
+        dir_id: file_dto.dir_id.clone(),
+        name: file_dto.name.clone(),
+        created_at: file_dto.created_at,
+        updated_at: file_dto.updated_at,
+        deleted_at: None,
+        linked_id: Some(existing.id.clone()),
+        ..existing
+    };
+
+    if !insert_file(db_pool, &link, &dir.name).await? {
+        return Err(Error::Conflict(
+            "Directory has an operation in progress".to_string(),
+        ));
+    }
+
+    if let Err(e) = update_dir_timestamp(db_pool, &dir.id, link.created_at).await {
+        error!("{}", e);
+    }
+
+    Ok(link.into())
+}
+
+/// Saves a new file record, returns false when the dir no longer accepts
+/// objects under the given name
+async fn insert_file(db_pool: &Pool, file: &FileObject, dir_name: &str) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
         return Err("Error getting db connection".into());
     };
 
-    let file: FileObject = file_dto.clone().into();
     let file_copy = file.clone();
-
+    let dir_name = dir_name.to_string();
     let conn_result = db
         .interact(move |conn| {
-            diesel::insert_into(files::table)
-                .values(&file_copy)
-                .execute(conn)
+            conn.immediate_transaction(|conn| {
+                if !dir_accepts_objects(conn, &file_copy.dir_id, &dir_name)? {
+                    return Ok(false);
+                }
+                diesel::insert_into(files::table)
+                    .values(&file_copy)
+                    .execute(conn)?;
+                update_dir_stats(conn, &file_copy.dir_id, 1, file_copy.size)?;
+                QueryResult::Ok(true)
+            })
         })
         .await;
 
     match conn_result {
         Ok(insert_res) => match insert_res {
-            Ok(_) => {
-                // Cleanup files before returning...
-                if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
-                    // Can't afford to fail here, we will just log the error...
-                    error!("Cleanup file(s): {}", e);
-                }
-
-                // Also update dir
-                let today = chrono::Utc::now().timestamp();
-                let dir_result = update_dir_timestamp(db_pool, &dir.id, today).await;
-                if let Err(e) = dir_result {
-                    // Can't afford to fail here, we will just log the error...
-                    error!("{}", e);
-                }
-
-                Ok(file)
-            }
+            Ok(inserted) => Ok(inserted),
             Err(e) => {
                 error!("{}", e);
-                if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
-                    error!("Cleanup file(s): {}", e);
-                }
                 Err("Error creating file".into())
             }
         },
         Err(e) => {
             error!("{}", e);
-            if let Err(e) = cleanup_temp_uploads(data, Some(&file_dto)) {
-                error!("Cleanup file(s): {}", e);
-            }
             Err("Error using the db connection".into())
         }
     }
@@ -357,6 +567,7 @@ pub async fn get_file(pool: &Pool, id: &str) -> Result<Option<FileObject>> {
         .interact(move |conn| {
             dsl::files
                 .find(fid)
+                .filter(dsl::deleted_at.is_null())
                 .select(FileObject::as_select())
                 .first::<FileObject>(conn)
                 .optional()
@@ -378,19 +589,455 @@ pub async fn get_file(pool: &Pool, id: &str) -> Result<Option<FileObject>> {
     }
 }
 
-pub async fn delete_file(pool: &Pool, id: &str) -> Result<()> {
+pub async fn update_file(db_pool: &Pool, file: &FileObject, data: &UpdateFile) -> Result<bool> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+
+    // Do not update if there is no data to update
+    if data.name.is_none() && data.img_taken_at.is_none() {
+        return Ok(false);
+    }
+
+    // Name must still be unique for the dir
+    if let Some(name) = &data.name
+        && let Some(existing) = find_dir_file(db_pool, &file.dir_id, name).await?
+        && existing.id != file.id
+    {
+        let short_name = truncate_string(name, 20);
+        return Err(Error::ValidationError(format!(
+            "{} already exists",
+            short_name,
+        )));
+    }
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let data_copy = data.clone();
+    let file_id = file.id.clone();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::files)
+                .filter(dsl::id.eq(file_id.as_str()))
+                .set((data_copy, dsl::updated_at.eq(today)))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(item) => Ok(item > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating file".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Replaces the image versions and hash of the file, returns false when
+/// the file no longer exists
+pub async fn update_file_versions(
+    db_pool: &Pool,
+    file_id: &str,
+    versions: &[ImgVersionDto],
+    phash: &str,
+) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let fid = file_id.to_string();
+    let versions_str: String = versions
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    let phash_copy = phash.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::files.find(fid))
+                .set((
+                    dsl::img_versions.eq(versions_str),
+                    dsl::phash.eq(phash_copy),
+                    dsl::updated_at.eq(today),
+                ))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(count) => Ok(count > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating file versions".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Records the checksum of a file uploaded before checksums were, returns
+/// false when the file no longer exists or already has one
+pub async fn update_file_checksum(db_pool: &Pool, file_id: &str, checksum: &str) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let fid = file_id.to_string();
+    let checksum_copy = checksum.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::files.find(fid))
+                .filter(dsl::checksum.is_null())
+                .set(dsl::checksum.eq(checksum_copy))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(count) => Ok(count > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating file checksum".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Moves the file and its stored objects into another dir
+pub async fn move_file(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    source: &FileLocation,
+    file: &FileObject,
+    target: &FileLocation,
+    data: &FileDestination,
+) -> Result<FileObject> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+    if source.dir.id == target.dir.id {
+        return Err(Error::ValidationError(
+            "File is already in the directory".to_string(),
+        ));
+    }
+
+    let name = data.name.clone().unwrap_or(file.name.clone());
+    validate_file_target(db_pool, target, file, &name).await?;
+
+    // Links keep sharing the objects within the bucket, they get their own
+    // copy in another bucket
+    let same_bucket = source.bucket.id == target.bucket.id;
+    let shared = file.linked_id.is_some() && same_bucket;
+    let dto = resolve_link(db_pool, file.clone().into()).await?;
+    let source_dir = dto.object_dir.clone().unwrap_or(source.dir.name.clone());
+
+    // Objects keep their filename, only the dir and maybe the bucket changes
+    if !shared {
+        copy_file_object(
+            storage_client,
+            (&source.bucket.name, &source_dir),
+            (&target.bucket.name, &target.dir.name),
+            &dto,
+            &dto.filename,
+        )
+        .await?;
+    }
+
+    let mut moved = file.clone();
+    moved.dir_id = target.dir.id.clone();
+    moved.name = name;
+    moved.updated_at = chrono::Utc::now().timestamp();
+    if !shared {
+        let own: FileObject = dto.clone().into();
+        moved.filename = own.filename;
+        moved.img_versions = own.img_versions;
+        moved.linked_id = None;
+    }
+
+    if let Err(e) = save_moved_file(db_pool, source, &moved, &target.dir.name).await {
+        if !shared {
+            let target_bucket = &target.bucket.name;
+            let res =
+                delete_file_object(storage_client, target_bucket, &target.dir.name, &dto).await;
+            if let Err(e) = res {
+                error!("Cleanup copied object(s): {}", e);
+            }
+        }
+        return Err(e);
+    }
+
+    // Old objects are no longer referenced, failing to delete them is not fatal.
+    // Those of a linked file belong to the file it links to, and links left
+    // behind in the bucket take over the objects of a file moved away.
+    let res = if file.linked_id.is_some() {
+        Ok(())
+    } else if same_bucket {
+        delete_file_object(storage_client, &source.bucket.name, &source.dir.name, &dto).await
+    } else {
+        let (bucket_name, dir_name) = (&source.bucket.name, &source.dir.name);
+        release_file_objects(db_pool, storage_client, bucket_name, dir_name, file).await
+    };
+    if let Err(e) = res {
+        error!("Cleanup moved object(s): {}", e);
+    }
+
+    if let Err(e) = update_dir_timestamp(db_pool, &target.dir.id, moved.updated_at).await {
+        error!("{}", e);
+    }
+
+    Ok(moved)
+}
+
+/// Copies the file and its stored objects into a dir as a new file
+pub async fn copy_file(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    source: &FileLocation,
+    file: &FileObject,
+    target: &FileLocation,
+    data: &FileDestination,
+) -> Result<FileObject> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+
+    let name = data.name.clone().unwrap_or(file.name.clone());
+    validate_file_target(db_pool, target, file, &name).await?;
+
+    // Copies of a link within the bucket link to the same file, otherwise
+    // they get their own objects
+    let shared = file.linked_id.is_some() && source.bucket.id == target.bucket.id;
+    let dto = resolve_link(db_pool, file.clone().into()).await?;
+    let source_dir = dto.object_dir.clone().unwrap_or(source.dir.name.clone());
+    let own: FileObject = dto.clone().into();
+
+    let today = chrono::Utc::now().timestamp();
+    let copy = FileObject {
+        id: generate_id(),
+        dir_id: target.dir.id.clone(),
+        filename: match shared {
+            true => own.filename,
+            false => slugify_prefixed(&name),
+        },
+        name,
+        img_versions: own.img_versions,
+        created_at: today,
+        updated_at: today,
+        linked_id: match shared {
+            true => own.linked_id,
+            false => None,
+        },
+        ..file.clone()
+    };
+
+    if !shared {
+        copy_file_object(
+            storage_client,
+            (&source.bucket.name, &source_dir),
+            (&target.bucket.name, &target.dir.name),
+            &dto,
+            &copy.filename,
+        )
+        .await?;
+    }
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let copy_clone = copy.clone();
+    let dir_name = target.dir.name.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if !dir_accepts_objects(conn, &copy_clone.dir_id, &dir_name)? {
+                    return Ok(false);
+                }
+                diesel::insert_into(files::table)
+                    .values(&copy_clone)
+                    .execute(conn)?;
+                update_dir_stats(conn, &copy_clone.dir_id, 1, copy_clone.size)?;
+                QueryResult::Ok(true)
+            })
+        })
+        .await;
+
+    let res: Result<()> = match conn_result {
+        Ok(insert_res) => match insert_res {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::Conflict(
+                "Directory has an operation in progress".to_string(),
+            )),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error copying file".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    };
+
+    if let Err(e) = res {
+        if !shared {
+            let copy_dto: FileDto = copy.clone().into();
+            let target_bucket = &target.bucket.name;
+            let res =
+                delete_file_object(storage_client, target_bucket, &target.dir.name, &copy_dto)
+                    .await;
+            if let Err(e) = res {
+                error!("Cleanup copied object(s): {}", e);
+            }
+        }
+        return Err(e);
+    }
+
+    if let Err(e) = update_dir_timestamp(db_pool, &target.dir.id, today).await {
+        error!("{}", e);
+    }
+
+    Ok(copy)
+}
+
+/// Checks whether the target dir can accept the file under the given name
+async fn validate_file_target(
+    db_pool: &Pool,
+    target: &FileLocation,
+    file: &FileObject,
+    name: &str,
+) -> Result<()> {
+    if target.bucket.images_only && file.is_image == 0 {
+        return Err(Error::ValidationError("Bucket only accepts images".into()));
+    }
+
+    if count_dir_files(db_pool, &target.dir.id).await? >= MAX_FILES as i64 {
+        return Err(Error::ValidationError(
+            "Maximum number of files reached".to_string(),
+        ));
+    }
+
+    if find_dir_file(db_pool, &target.dir.id, name)
+        .await?
+        .is_some()
+    {
+        let short_name = truncate_string(name, 20);
+        return Err(Error::ValidationError(format!(
+            "{} already exists",
+            short_name,
+        )));
+    }
+
+    Ok(())
+}
+
+/// Points the file to the target dir, unless either dir started a job since
+/// the objects were copied
+async fn save_moved_file(
+    db_pool: &Pool,
+    source: &FileLocation,
+    file: &FileObject,
+    target_dir_name: &str,
+) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let source_dir_id = source.dir.id.clone();
+    let source_dir_name = source.dir.name.clone();
+    let target_dir_name = target_dir_name.to_string();
+    let file_copy = file.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if !dir_accepts_objects(conn, &source_dir_id, &source_dir_name)?
+                    || !dir_accepts_objects(conn, &file_copy.dir_id, &target_dir_name)?
+                {
+                    return Ok(false);
+                }
+                diesel::update(dsl::files.find(&file_copy.id))
+                    .set((
+                        dsl::dir_id.eq(&file_copy.dir_id),
+                        dsl::name.eq(&file_copy.name),
+                        dsl::updated_at.eq(file_copy.updated_at),
+                    ))
+                    .execute(conn)?;
+                update_dir_stats(conn, &source_dir_id, -1, -file_copy.size)?;
+                update_dir_stats(conn, &file_copy.dir_id, 1, file_copy.size)?;
+                QueryResult::Ok(true)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::Conflict(
+                "Directory has an operation in progress".to_string(),
+            )),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error moving file".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Deletes the file record, returns it as it was right before
+pub async fn delete_file(pool: &Pool, id: &str) -> Result<Option<FileObject>> {
     let Ok(db) = pool.get().await else {
         return Err("Error getting db connection".into());
     };
 
     let fid = id.to_string();
     let conn_result = db
-        .interact(move |conn| diesel::delete(dsl::files.filter(dsl::id.eq(fid))).execute(conn))
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let file = dsl::files
+                    .find(&fid)
+                    .select(FileObject::as_select())
+                    .first::<FileObject>(conn)
+                    .optional()?;
+
+                if let Some(file) = &file {
+                    diesel::delete(dsl::files.filter(dsl::id.eq(&fid))).execute(conn)?;
+
+                    // Trashed files are already excluded from the stats
+                    if file.deleted_at.is_none() {
+                        update_dir_stats(conn, &file.dir_id, -1, -file.size)?;
+                    }
+                }
+                QueryResult::Ok(file)
+            })
+        })
         .await;
 
     match conn_result {
         Ok(delete_res) => match delete_res {
-            Ok(_) => Ok(()),
+            Ok(file) => Ok(file),
             Err(e) => {
                 error!("{e}");
                 Err("Error deleting file".into())
@@ -445,15 +1092,30 @@ fn init_file(dir: &Dir, data: &FilePayload) -> Result<FileDto> {
     let mut is_image = false;
     let content_type = get_content_type(&data.path)?;
     if content_type.starts_with("image/") {
-        if !ALLOWED_IMAGE_TYPES.contains(&content_type.as_str()) {
+        if !allowed_image_type(&content_type) {
             if let Err(e) = cleanup_temp_uploads(data, None) {
                 error!("Cleanup orig file: {}", e);
             }
-            return Err("Uploaded image type not allowed".into());
+            return Err(Error::BadRequest(
+                "Uploaded image type not allowed".to_string(),
+            ));
         }
         is_image = true;
     }
 
+    let checksum = match &data.checksum {
+        Some(checksum) => checksum.clone(),
+        None => match file_checksum(&data.path) {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                if let Err(e) = cleanup_temp_uploads(data, None) {
+                    error!("Cleanup orig file: {}", e);
+                }
+                return Err(e);
+            }
+        },
+    };
+
     // May be a few second delayed due to image processing
     let today = chrono::Utc::now().timestamp();
 
@@ -468,8 +1130,15 @@ fn init_file(dir: &Dir, data: &FilePayload) -> Result<FileDto> {
         is_image,
         img_versions: None,
         img_taken_at: None,
+        transform_url: None,
         created_at: today,
         updated_at: today,
+        deleted_at: None,
+        checksum: Some(checksum),
+        phash: None,
+        duplicate_of: None,
+        linked_id: None,
+        object_dir: None,
     };
 
     Ok(file)
@@ -500,21 +1169,45 @@ fn read_image(path: &PathBuf) -> Result<DynamicImage> {
     }
 }
 
-fn create_versions(data: &FilePayload, exif_info: &PhotoExif) -> Result<Vec<ImgVersionDto>> {
-    let img = read_image(&data.path)?;
+/// Creates the versions of an already stored image from its original at
+/// `data.path`, along with the perceptual hash of the image
+pub fn recreate_versions(
+    data: &FilePayload,
+    content_type: &str,
+    profiles: &[ImgProfileDto],
+    img_config: &ImageConfig,
+) -> Result<(Vec<ImgVersionDto>, String)> {
+    let exif_info = parse_exif_info(&data.path).unwrap_or_default();
+    let source = ImgSource {
+        content_type,
+        exif_info: &exif_info,
+    };
+    create_versions(data, &source, profiles, img_config)
+}
 
-    // Rotate based on exif orientation before creating versions
-    let rotated_img = match exif_info.orientation {
-        8 => img.rotate270(),
-        7 => img.rotate270().fliph(),
-        6 => img.rotate90(),
-        5 => img.rotate90().fliph(),
-        4 => img.flipv(),
-        3 => img.rotate180(),
-        2 => img.fliph(),
-        _ => img,
+/// Uploaded image as read before creating its versions
+struct ImgSource<'a> {
+    content_type: &'a str,
+    exif_info: &'a PhotoExif,
+}
+
+/// Creates the image versions declared by the profiles along with the
+/// perceptual hash of the image
+fn create_versions(
+    data: &FilePayload,
+    source: &ImgSource,
+    profiles: &[ImgProfileDto],
+    img_config: &ImageConfig,
+) -> Result<(Vec<ImgVersionDto>, String)> {
+    // HEIF images are decoded with their orientation applied
+    let (img, orientation) = match is_heif_image(source.content_type) {
+        true => (read_heif_image(&data.path)?, 1),
+        false => (read_image(&data.path)?, source.exif_info.orientation),
     };
 
+    // Rotate based on exif orientation before creating versions
+    let rotated_img = orient_image(img, orientation);
+
     let source_width = rotated_img.width();
     let source_height = rotated_img.height();
 
@@ -525,107 +1218,172 @@ fn create_versions(data: &FilePayload, exif_info: &PhotoExif) -> Result<Vec<ImgV
             height: source_height,
         },
         url: None,
+        content_type: Some(source.content_type.to_string()),
     };
 
     let mut versions: Vec<ImgVersionDto> = vec![orig_version];
 
-    // // Only create preview if original image has side longer than max
-    if source_width > MAX_DIMENSION || source_height > MAX_DIMENSION {
-        let preview = create_preview(data, &rotated_img)?;
-        versions.push(preview);
-    }
+    for profile in profiles.iter() {
+        // Only create preview if original image has side longer than max,
+        // or than the preview itself when the profile is smaller
+        if profile.version == ImgVersion::Preview
+            && source_width <= profile.max_width.min(MAX_DIMENSION)
+            && source_height <= profile.max_height.min(MAX_DIMENSION)
+        {
+            continue;
+        }
 
-    // Create thumbnail
-    let thumb = create_thumbnail(data, &rotated_img)?;
-    versions.push(thumb);
+        let version = create_version(data, &rotated_img, profile, img_config)?;
+        versions.push(version);
+    }
 
-    Ok(versions)
+    Ok((versions, image_hash(&rotated_img)))
 }
 
-fn create_preview(data: &FilePayload, img: &DynamicImage) -> Result<ImgVersionDto> {
+fn create_version(
+    data: &FilePayload,
+    img: &DynamicImage,
+    profile: &ImgProfileDto,
+    img_config: &ImageConfig,
+) -> Result<ImgVersionDto> {
     // Prepare dir
-    let prev_dir = data
-        .upload_dir
-        .clone()
-        .join(ImgVersion::Preview.to_string());
+    let version_dir = data.upload_dir.clone().join(profile.version.to_string());
 
-    if let Err(err) = std::fs::create_dir_all(&prev_dir) {
-        return Err(format!("Unable to create preview dir: {}", err).into());
+    if let Err(err) = std::fs::create_dir_all(&version_dir) {
+        return Err(format!("Unable to create {} dir: {}", profile.version, err).into());
     }
 
-    // Either resize to max dimension or original dimension
-    // whichever is smaller
-    let mut max_width = MAX_PREVIEW_DIMENSION;
-    if img.width() < MAX_PREVIEW_DIMENSION {
-        max_width = img.width();
-    }
-    let mut max_height = MAX_PREVIEW_DIMENSION;
-    if img.height() < MAX_PREVIEW_DIMENSION {
-        max_height = img.height();
-    }
+    let resized_img = resize_image(img, profile.max_width, profile.max_height, profile.fit);
 
-    let resized_img = img.resize(max_width, max_height, imageops::FilterType::Lanczos3);
+    // Profile settings take precedence over the config
+    let format = profile.format.unwrap_or(img_config.format);
+    let quality = profile.quality.unwrap_or(img_config.quality);
 
     // Save the resized image
     let version = ImgVersionDto {
-        version: ImgVersion::Preview,
+        version: profile.version.clone(),
         dimension: ImgDimension {
             width: resized_img.width(),
             height: resized_img.height(),
         },
         url: None,
+        content_type: Some(format.content_type().to_string()),
     };
 
     let dest_file = version.to_path(&data.upload_dir, &data.filename);
 
-    if let Err(err) = resized_img.save(dest_file) {
-        return Err(format!("Unable to save preview: {}", err).into());
+    if let Err(err) = save_image(&resized_img, &dest_file, format, quality) {
+        return Err(format!("Unable to save {}: {}", profile.version, err).into());
     }
 
     Ok(version)
 }
 
-fn create_thumbnail(data: &FilePayload, img: &DynamicImage) -> Result<ImgVersionDto> {
-    // Prepare dir
-    let prev_dir = data
-        .upload_dir
-        .clone()
-        .join(ImgVersion::Thumbnail.to_string());
+/// Resizes and encodes the original image at `source` into `dest`
+pub fn transform_image(source: &PathBuf, dest: &PathBuf, transform: &ImgTransform) -> Result<()> {
+    let content_type = get_content_type(source)?;
+    if !allowed_image_type(&content_type) {
+        return Err(Error::BadRequest("Object is not an image".to_string()));
+    }
 
-    if let Err(err) = std::fs::create_dir_all(&prev_dir) {
-        return Err(format!("Unable to create preview dir: {}", err).into());
+    let img = match is_heif_image(&content_type) {
+        true => read_heif_image(source)?,
+        false => {
+            let orientation = match parse_exif_info(source) {
+                Ok(info) => info.orientation,
+                Err(_) => 1,
+            };
+            orient_image(read_image(source)?, orientation)
+        }
+    };
+
+    let max_width = transform.width.unwrap_or(img.width());
+    let max_height = transform.height.unwrap_or(img.height());
+    let resized_img = resize_image(&img, max_width, max_height, transform.fit);
+    match save_image(&resized_img, dest, transform.format, transform.quality) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Unable to save transformed image: {}", e).into()),
     }
+}
 
+/// Applies the exif orientation to the decoded pixels
+fn orient_image(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        8 => img.rotate270(),
+        7 => img.rotate270().fliph(),
+        6 => img.rotate90(),
+        5 => img.rotate90().fliph(),
+        4 => img.flipv(),
+        3 => img.rotate180(),
+        2 => img.fliph(),
+        _ => img,
+    }
+}
+
+/// Resizes within the bounds, images are never upscaled
+fn resize_image(img: &DynamicImage, max_width: u32, max_height: u32, fit: ImgFit) -> DynamicImage {
     // Either resize to max dimension or original dimension
     // whichever is smaller
-    let mut max_width = MAX_THUMB_DIMENSION;
-    if img.width() < MAX_THUMB_DIMENSION {
-        max_width = img.width();
-    }
-    let mut max_height = MAX_THUMB_DIMENSION;
-    if img.height() < MAX_THUMB_DIMENSION {
-        max_height = img.height();
-    }
+    let max_width = max_width.min(img.width());
+    let max_height = max_height.min(img.height());
 
-    let resized_img = img.resize(max_width, max_height, imageops::FilterType::Lanczos3);
+    match fit {
+        ImgFit::Contain => img.resize(max_width, max_height, imageops::FilterType::Lanczos3),
+        ImgFit::Cover => img.resize_to_fill(max_width, max_height, imageops::FilterType::Lanczos3),
+    }
+}
 
-    // Save the resized image
-    let version = ImgVersionDto {
-        version: ImgVersion::Thumbnail,
-        dimension: ImgDimension {
-            width: resized_img.width(),
-            height: resized_img.height(),
-        },
-        url: None,
+/// Encodes the image regardless of the extension of the destination
+fn save_image(
+    img: &DynamicImage,
+    dest_file: &PathBuf,
+    format: ImgFormat,
+    quality: u8,
+) -> image::ImageResult<()> {
+    let writer = std::io::BufWriter::new(File::create(dest_file)?);
+
+    // Encoders only accept 8 bit pixels, jpeg has no alpha channel
+    let img = match (img.color().has_alpha(), format) {
+        (true, ImgFormat::Jpeg) => flatten_alpha(img),
+        (true, _) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        (false, _) => DynamicImage::ImageRgb8(img.to_rgb8()),
     };
 
-    let dest_file = version.to_path(&data.upload_dir, &data.filename);
-
-    if let Err(err) = resized_img.save(dest_file) {
-        return Err(format!("Unable to save preview: {}", err).into());
+    match format {
+        ImgFormat::Jpeg => img.write_with_encoder(JpegEncoder::new_with_quality(writer, quality)),
+        ImgFormat::Png => img.write_with_encoder(PngEncoder::new(writer)),
+        ImgFormat::Webp => save_webp(&img, writer, quality),
+        ImgFormat::Avif => img.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            writer,
+            AVIF_ENCODER_SPEED,
+            quality,
+        )),
     }
+}
 
-    Ok(version)
+/// Lossy webp, the encoder of the image crate only writes lossless ones
+fn save_webp(img: &DynamicImage, mut writer: impl Write, quality: u8) -> image::ImageResult<()> {
+    let encoder = match img {
+        DynamicImage::ImageRgba8(rgba) => {
+            webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height())
+        }
+        _ => webp::Encoder::from_rgb(img.as_bytes(), img.width(), img.height()),
+    };
+    writer.write_all(&encoder.encode(f32::from(quality)))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Blends transparent pixels over a white background
+fn flatten_alpha(img: &DynamicImage) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    let flat = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let pixel = rgba.get_pixel(x, y);
+        let alpha = u16::from(pixel[3]);
+        let blend = |c: u8| ((u16::from(c) * alpha + 255 * (255 - alpha)) / 255) as u8;
+        image::Rgb([blend(pixel[0]), blend(pixel[1]), blend(pixel[2])])
+    });
+    DynamicImage::ImageRgb8(flat)
 }
 
 fn get_content_type(path: &PathBuf) -> Result<String> {
@@ -0,0 +1,488 @@
+use std::path::{Path, PathBuf};
+
+use deadpool_diesel::sqlite::Pool;
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use serde::Deserialize;
+use tokio::fs;
+use tracing::{error, info};
+
+use crate::buckets::{BucketDto, ImgProfileDto, bucket_img_profiles};
+use crate::config::{Config, ImageConfig};
+use crate::dirs::{dir_has_active_job, get_dir};
+use crate::jobs::{
+    Job, JobKind, JobStatus, NewJob, RegenerateVersionsPayload, build_job, find_active_job,
+    has_active_bucket_job, update_job_progress,
+};
+use crate::schema::{dirs, files, jobs};
+use crate::storage::{StorageBackend, object_path, version_object_path};
+use crate::util::valid_id;
+use crate::{Error, Result};
+
+use super::{
+    FileDto, FileObject, FilePayload, ImgVersion, ORIGINAL_PATH, get_file, recreate_versions,
+    update_file_versions,
+};
+
+const REGENERATE_BATCH_SIZE: i64 = 50;
+
+/// Images to regenerate, the whole bucket when neither is set
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RegenerateVersions {
+    pub dir_id: Option<String>,
+    pub file_id: Option<String>,
+}
+
+/// Settings shared by every image of a regenerate job
+struct RegenerateContext<'a> {
+    bucket_name: &'a str,
+    profiles: &'a [ImgProfileDto],
+    img_config: &'a ImageConfig,
+    work_dir: &'a Path,
+}
+
+/// Creates a job that recreates the versions of existing images with the
+/// current profiles and image settings
+pub async fn schedule_regenerate_versions(
+    db_pool: &Pool,
+    bucket: &BucketDto,
+    data: &RegenerateVersions,
+) -> Result<Job> {
+    let mut dir_id = data.dir_id.clone();
+    if let Some(file_id) = &data.file_id {
+        let file = match valid_id(file_id) {
+            true => get_file(db_pool, file_id).await?,
+            false => None,
+        };
+        let Some(file) = file else {
+            return Err(Error::ValidationError("File not found".to_string()));
+        };
+        if file.is_image != 1 {
+            return Err(Error::ValidationError("File is not an image".to_string()));
+        }
+        if dir_id.as_ref().is_some_and(|id| id != &file.dir_id) {
+            return Err(Error::ValidationError(
+                "File does not belong to the directory".to_string(),
+            ));
+        }
+        dir_id = Some(file.dir_id);
+    }
+
+    if let Some(id) = &dir_id {
+        let dir = match valid_id(id) {
+            true => get_dir(db_pool, id).await?,
+            false => None,
+        };
+        if dir.is_none_or(|dir| dir.bucket_id != bucket.id) {
+            return Err(Error::ValidationError("Directory not found".to_string()));
+        }
+    }
+
+    let payload = RegenerateVersionsPayload {
+        bucket_name: bucket.name.clone(),
+        dir_id: dir_id.clone(),
+        file_id: data.file_id.clone(),
+    };
+    let target_id = match (&payload.file_id, &payload.dir_id) {
+        (Some(file_id), _) => file_id.clone(),
+        (None, Some(dir_id)) => dir_id.clone(),
+        (None, None) => bucket.id.clone(),
+    };
+
+    let count = count_images(db_pool, &bucket.id, &payload).await?;
+    let data = NewJob {
+        bucket_id: bucket.id.clone(),
+        target_id,
+        kind: JobKind::RegenerateVersions,
+        payload: serde_json::to_string(&payload).unwrap(),
+        total: count as i32,
+    };
+    create_regenerate_job(db_pool, &data, dir_id).await
+}
+
+/// Inserts the job unless another job works on the same images
+async fn create_regenerate_job(
+    db_pool: &Pool,
+    data: &NewJob,
+    dir_id: Option<String>,
+) -> Result<Job> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let job = build_job(data);
+    let job_copy = job.clone();
+    let dir_id_copy = dir_id.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if overlaps_active_job(conn, &job_copy.bucket_id, dir_id_copy.as_deref())? {
+                    return Ok(false);
+                }
+                diesel::insert_into(jobs::table)
+                    .values(&job_copy)
+                    .execute(conn)?;
+                QueryResult::Ok(true)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(insert_res) => match insert_res {
+            Ok(true) => Ok(job),
+            Ok(false) => Err(overlap_error(dir_id.as_deref())),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error creating job".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Puts a failed job back to pending, it resumes after the last processed
+/// image once spawned again
+pub async fn retry_regenerate_versions(db_pool: &Pool, job: &Job) -> Result<Job> {
+    if job.kind != JobKind::RegenerateVersions.to_string() {
+        return Err(Error::ValidationError(
+            "Only regenerate versions jobs can be retried".to_string(),
+        ));
+    }
+    if job.status != JobStatus::Failed.to_string() {
+        return Err(Error::ValidationError(
+            "Only failed jobs can be retried".to_string(),
+        ));
+    }
+    let Ok(payload) = serde_json::from_str::<RegenerateVersionsPayload>(&job.payload) else {
+        return Err("Invalid regenerate versions payload".into());
+    };
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let jid = job.id.clone();
+    let bid = job.bucket_id.clone();
+    let dir_id = payload.dir_id.clone();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if overlaps_active_job(conn, &bid, dir_id.as_deref())? {
+                    return Ok(false);
+                }
+                let count = diesel::update(
+                    jobs::table
+                        .find(jid)
+                        .filter(jobs::status.eq(JobStatus::Failed.to_string())),
+                )
+                .set((
+                    jobs::status.eq(JobStatus::Pending.to_string()),
+                    jobs::error.eq(None::<String>),
+                    jobs::updated_at.eq(today),
+                ))
+                .execute(conn)?;
+                QueryResult::Ok(count > 0)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(true) => Ok(Job {
+                status: JobStatus::Pending.to_string(),
+                error: None,
+                updated_at: today,
+                ..job.clone()
+            }),
+            Ok(false) => Err(overlap_error(payload.dir_id.as_deref())),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating job status".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// A dir or file job waits for the dir, a bucket job for every job of the
+/// bucket
+fn overlaps_active_job(
+    conn: &mut SqliteConnection,
+    bucket_id: &str,
+    dir_id: Option<&str>,
+) -> QueryResult<bool> {
+    match dir_id {
+        Some(dir_id) => dir_has_active_job(conn, bucket_id, dir_id),
+        None => has_active_bucket_job(conn, bucket_id),
+    }
+}
+
+fn overlap_error(dir_id: Option<&str>) -> Error {
+    match dir_id {
+        Some(_) => Error::Conflict("Directory has an operation in progress".to_string()),
+        None => Error::Conflict("Bucket has an operation in progress".to_string()),
+    }
+}
+
+/// Recreates the versions of every image of the job, oldest ids first.
+/// Progress is saved per file so it can be resumed.
+pub async fn run_regenerate_versions(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    config: &Config,
+    job: &Job,
+) -> Result<()> {
+    let Ok(payload) = serde_json::from_str::<RegenerateVersionsPayload>(&job.payload) else {
+        return Err("Invalid regenerate versions payload".into());
+    };
+
+    let profiles = bucket_img_profiles(db_pool, &job.bucket_id).await?;
+    let work_dir = config.upload_dir.join("tmp").join(&job.id);
+    let ctx = RegenerateContext {
+        bucket_name: &payload.bucket_name,
+        profiles: &profiles,
+        img_config: &config.image,
+        work_dir: &work_dir,
+    };
+
+    let mut cursor = job.cursor.clone();
+    let mut processed = job.processed;
+    let mut failed = job.failed;
+    loop {
+        let files = list_images_after(
+            db_pool,
+            &job.bucket_id,
+            &payload,
+            cursor.as_deref(),
+            REGENERATE_BATCH_SIZE,
+        )
+        .await?;
+        if files.is_empty() {
+            break;
+        }
+
+        for file in files.into_iter() {
+            let Some(dir) = get_dir(db_pool, &file.dir_id).await? else {
+                return Err("Error finding the file directory".into());
+            };
+
+            // Objects may be moving around, try again once the dir is idle
+            if let Some(active) = find_active_job(db_pool, &dir.id).await?
+                && active.id != job.id
+            {
+                return Err(Error::Conflict(
+                    "Directory has an operation in progress".to_string(),
+                ));
+            }
+
+            // A broken original should not hold back the rest of the images
+            if let Err(e) =
+                regenerate_file_versions(db_pool, storage_client, &ctx, &dir.name, &file).await
+            {
+                error!("Regenerate versions of file {}: {}", file.id, e);
+                failed += 1;
+            }
+            if let Err(e) = remove_work_dir(&work_dir).await {
+                error!("{}", e);
+            }
+
+            processed += 1;
+            cursor = Some(file.id);
+            update_job_progress(db_pool, &job.id, cursor.clone(), processed, failed).await?;
+        }
+
+        info!(
+            "Regenerated versions of {} of {} images, {} failed, job {}",
+            processed, job.total, failed, job.id
+        );
+    }
+
+    Ok(())
+}
+
+/// Uploads the new versions from the original, saves them then deletes the
+/// objects of versions no longer declared
+async fn regenerate_file_versions(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    ctx: &RegenerateContext<'_>,
+    dir_name: &str,
+    file: &FileObject,
+) -> Result<()> {
+    let orig_dir = ctx.work_dir.join(ORIGINAL_PATH);
+    if fs::create_dir_all(&orig_dir).await.is_err() {
+        return Err("Unable to create upload dir".into());
+    }
+
+    let source: PathBuf = orig_dir.join(&file.filename);
+    let orig_path = object_path(dir_name, ORIGINAL_PATH, &file.filename);
+    storage_client
+        .download_object(ctx.bucket_name, &orig_path, &source)
+        .await?;
+
+    let data = FilePayload {
+        upload_dir: ctx.work_dir.to_path_buf(),
+        name: file.name.clone(),
+        filename: file.filename.clone(),
+        path: source,
+        size: file.size,
+        checksum: None,
+    };
+    let (versions, phash) =
+        recreate_versions(&data, &file.content_type, ctx.profiles, ctx.img_config)?;
+
+    // Original stays untouched, only derived versions are uploaded
+    let mut uploaded: Vec<String> = Vec::with_capacity(versions.len());
+    for version in versions.iter() {
+        if version.version == ImgVersion::Original {
+            continue;
+        }
+        let path = version_object_path(dir_name, version, &file.filename);
+        let source_path = version.to_path(&data.upload_dir, &file.filename);
+        let content_type = version.content_type.as_ref().unwrap_or(&file.content_type);
+        storage_client
+            .upload_object(ctx.bucket_name, &path, content_type, &source_path)
+            .await?;
+        uploaded.push(path);
+    }
+
+    // File was purged meanwhile, its new objects are orphans
+    if !update_file_versions(db_pool, &file.id, &versions, &phash).await? {
+        for path in uploaded.iter() {
+            if let Err(e) = storage_client.delete_object(ctx.bucket_name, path).await {
+                error!("Cleanup regenerated object: {}", e);
+            }
+        }
+        return Ok(());
+    }
+
+    let old_file: FileDto = file.clone().into();
+    let old_versions = old_file.img_versions.unwrap_or_default();
+    for old in old_versions.iter() {
+        // Versions encoded as another format are stored under another name
+        let path = version_object_path(dir_name, old, &file.filename);
+        if old.version == ImgVersion::Original || uploaded.contains(&path) {
+            continue;
+        }
+        if let Err(e) = storage_client.delete_object(ctx.bucket_name, &path).await {
+            error!("Cleanup obsolete version: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn remove_work_dir(work_dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(work_dir).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Unable to remove regenerate dir: {}", e).into()),
+    }
+}
+
+/// Images of the job scope after the cursor, including the ones in the trash.
+/// Linked files are left out, they use the versions of the file they link to.
+async fn list_images_after(
+    db_pool: &Pool,
+    bucket_id: &str,
+    payload: &RegenerateVersionsPayload,
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<Vec<FileObject>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let payload_copy = payload.clone();
+    let cursor_copy = cursor.map(|c| c.to_string());
+    let conn_result = db
+        .interact(move |conn| {
+            let mut query = files::table
+                .inner_join(dirs::table)
+                .filter(dirs::bucket_id.eq(bid))
+                .filter(files::is_image.eq(1))
+                .filter(files::linked_id.is_null())
+                .into_boxed();
+            if let Some(dir_id) = payload_copy.dir_id {
+                query = query.filter(files::dir_id.eq(dir_id));
+            }
+            if let Some(file_id) = payload_copy.file_id {
+                query = query.filter(files::id.eq(file_id));
+            }
+            if let Some(cursor) = cursor_copy {
+                query = query.filter(files::id.gt(cursor));
+            }
+            query
+                .limit(limit)
+                .select(FileObject::as_select())
+                .order(files::id.asc())
+                .load::<FileObject>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error reading files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+async fn count_images(
+    db_pool: &Pool,
+    bucket_id: &str,
+    payload: &RegenerateVersionsPayload,
+) -> Result<i64> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let payload_copy = payload.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            let mut query = files::table
+                .inner_join(dirs::table)
+                .filter(dirs::bucket_id.eq(bid))
+                .filter(files::is_image.eq(1))
+                .filter(files::linked_id.is_null())
+                .into_boxed();
+            if let Some(dir_id) = payload_copy.dir_id {
+                query = query.filter(files::dir_id.eq(dir_id));
+            }
+            if let Some(file_id) = payload_copy.file_id {
+                query = query.filter(files::id.eq(file_id));
+            }
+            query.select(count_star()).get_result::<i64>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(count_res) => match count_res {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error counting files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+use image::{DynamicImage, imageops};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use validator::Validate;
+
+use crate::schema::{dirs, files};
+use crate::validators::flatten_errors;
+use crate::{Error, Result};
+
+use super::{FileDto, FileObject};
+
+/// Bits that may differ between two images considered similar
+pub const DEFAULT_SIMILAR_THRESHOLD: u32 = 10;
+
+// Clusters returned at once, clean up and list again for more
+const MAX_SIMILAR_GROUPS: usize = 100;
+
+// Images compared at once, the most recent ones of the bucket
+const MAX_SIMILAR_IMAGES: i64 = 10_000;
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct ListSimilarParams {
+    #[validate(range(min = 0, max = 32))]
+    pub threshold: Option<u32>,
+}
+
+/// Images of a bucket that look alike
+#[derive(Debug, Clone, Serialize)]
+pub struct SimilarGroup {
+    pub files: Vec<FileDto>,
+}
+
+/// Difference hash (dHash) of the image, hex encoded. Each of the 64 bits tells
+/// whether a pixel is brighter than its right neighbor on a 9x8 grayscale copy,
+/// so resized or re-encoded copies end up with the same or a close hash.
+pub fn image_hash(img: &DynamicImage) -> String {
+    let small = img
+        .resize_exact(9, 8, imageops::FilterType::Triangle)
+        .to_luma8();
+
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// Number of differing bits between two hashes
+pub fn hash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups hashes within the threshold of the first hash of the group, the
+/// oldest image when hashes are ordered by age. Each hash joins the first
+/// group close enough so unrelated images never chain into the same group.
+/// Returns the indexes of each group of at least two hashes.
+pub fn cluster_hashes(hashes: &[u64], threshold: u32) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        let group = groups
+            .iter_mut()
+            .find(|group| hash_distance(hashes[group[0]], *hash) <= threshold);
+        match group {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Clusters live images of the bucket whose hashes are within the threshold,
+/// largest clusters first
+pub async fn list_similar_groups(
+    db_pool: &Pool,
+    bucket_id: &str,
+    params: &ListSimilarParams,
+) -> Result<Vec<SimilarGroup>> {
+    if let Err(errors) = params.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+
+    let threshold = params.threshold.unwrap_or(DEFAULT_SIMILAR_THRESHOLD);
+    let hashed = list_image_hashes(db_pool, bucket_id).await?;
+
+    // Comparing hashes is CPU bound, keep it off the async workers
+    let hashes: Vec<u64> = hashed.iter().map(|(_, hash)| *hash).collect();
+    let clusters = tokio::task::spawn_blocking(move || {
+        let mut clusters = cluster_hashes(&hashes, threshold);
+        clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.len()));
+        clusters.truncate(MAX_SIMILAR_GROUPS);
+        clusters
+    })
+    .await;
+    let clusters = match clusters {
+        Ok(clusters) => clusters,
+        Err(e) => {
+            error!("{}", e);
+            return Err("Error listing similar files".into());
+        }
+    };
+
+    let ids: Vec<String> = clusters
+        .iter()
+        .flatten()
+        .map(|i| hashed[*i].0.clone())
+        .collect();
+    let items = list_files_by_ids(db_pool, ids).await?;
+
+    let groups: Vec<SimilarGroup> = clusters
+        .into_iter()
+        .map(|cluster| {
+            let files: Vec<FileDto> = cluster
+                .iter()
+                .filter_map(|i| items.iter().find(|f| f.id == hashed[*i].0))
+                .map(|f| f.clone().into())
+                .collect();
+            SimilarGroup { files }
+        })
+        .collect();
+    Ok(groups)
+}
+
+/// Ids and hashes of the most recent live images of the bucket, oldest first
+async fn list_image_hashes(db_pool: &Pool, bucket_id: &str) -> Result<Vec<(String, u64)>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            files::table
+                .inner_join(dirs::table)
+                .filter(dirs::bucket_id.eq(bid))
+                .filter(dirs::deleted_at.is_null())
+                .filter(files::deleted_at.is_null())
+                .filter(files::phash.is_not_null())
+                .select((files::id, files::phash))
+                .order(files::created_at.desc())
+                .limit(MAX_SIMILAR_IMAGES)
+                .load::<(String, Option<String>)>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items
+                .into_iter()
+                .rev()
+                .filter_map(|(id, phash)| {
+                    let hash = u64::from_str_radix(phash?.as_str(), 16).ok()?;
+                    Some((id, hash))
+                })
+                .collect()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing similar files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+async fn list_files_by_ids(db_pool: &Pool, ids: Vec<String>) -> Result<Vec<FileObject>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            files::table
+                .filter(files::id.eq_any(&ids))
+                .select(FileObject::as_select())
+                .load::<FileObject>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing similar files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::test_helpers::gradient_image as gradient;
+
+    #[test]
+    fn test_image_hash() {
+        let hash = image_hash(&gradient(400, 300, false));
+        assert_eq!(hash.len(), 16);
+
+        let resized = image_hash(&gradient(200, 150, false));
+        let flipped = image_hash(&gradient(400, 300, true));
+        let parse = |h: &str| u64::from_str_radix(h, 16).unwrap();
+        assert!(hash_distance(parse(&hash), parse(&resized)) <= 2);
+        assert!(hash_distance(parse(&hash), parse(&flipped)) > 32);
+    }
+
+    #[test]
+    fn test_cluster_hashes() {
+        let hashes = [0b0000, 0xffff_0000, 0b0011, 0b0111, 0xffff_0001];
+        assert_eq!(cluster_hashes(&hashes, 0), Vec::<Vec<usize>>::new());
+        assert_eq!(cluster_hashes(&hashes, 1), vec![vec![1, 4], vec![2, 3]]);
+        // 0b0111 is close to 0b0011 but too far from the first hash of its group
+        assert_eq!(cluster_hashes(&hashes, 2), vec![vec![0, 2], vec![1, 4]]);
+        assert_eq!(cluster_hashes(&hashes, 3), vec![vec![0, 2, 3], vec![1, 4]]);
+        assert_eq!(cluster_hashes(&hashes, 32).len(), 1);
+    }
+}
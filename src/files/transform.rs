@@ -0,0 +1,295 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use tokio::fs::{self, create_dir_all};
+use tracing::error;
+use validator::Validate;
+
+use crate::config::ImageConfig;
+use crate::storage::StorageBackend;
+use crate::util::generate_id;
+use crate::validators::flatten_errors;
+use crate::{Error, Result};
+
+use super::{ImgFit, ImgFormat, ORIGINAL_PATH, transform_image};
+
+// Transformed images live outside of the dirs, names can't start with `_`
+const CACHE_PATH: &str = "_cache";
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct TransformParams {
+    // Largest side of an image transformed on demand
+    #[validate(range(min = 1, max = 4000))]
+    pub width: Option<u32>,
+
+    #[validate(range(min = 1, max = 4000))]
+    pub height: Option<u32>,
+
+    pub fit: Option<String>,
+    pub format: Option<String>,
+
+    #[validate(range(min = 1, max = 100))]
+    pub quality: Option<u8>,
+}
+
+/// Parsed transformation, a missing side is only bound by the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImgTransform {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: ImgFit,
+    pub format: ImgFormat,
+    pub quality: u8,
+}
+
+impl ImgTransform {
+    /// Configured image format and quality apply when not requested
+    pub fn new(params: &TransformParams, img_config: &ImageConfig) -> Result<Self> {
+        if let Err(errors) = params.validate() {
+            return Err(Error::ValidationError(flatten_errors(&errors)));
+        }
+
+        // Requests are only signed for the original, not the parameters
+        for side in [params.width, params.height].into_iter().flatten() {
+            if !img_config.transform_sizes.contains(&side) {
+                return Err(Error::ValidationError(format!(
+                    "Width and height must be one of {:?}",
+                    img_config.transform_sizes
+                )));
+            }
+        }
+        if let Some(quality) = params.quality
+            && quality != img_config.quality
+            && !img_config.transform_qualities.contains(&quality)
+        {
+            return Err(Error::ValidationError(format!(
+                "Quality must be one of {:?}",
+                img_config.transform_qualities
+            )));
+        }
+
+        let fit = match &params.fit {
+            Some(fit) => ImgFit::try_from(fit.as_str()).map_err(Error::ValidationError)?,
+            None => ImgFit::Contain,
+        };
+        let format = match &params.format {
+            Some(format) => ImgFormat::try_from(format.as_str()).map_err(Error::ValidationError)?,
+            None => img_config.format,
+        };
+        if format != img_config.format && !img_config.transform_formats.contains(&format) {
+            return Err(Error::ValidationError(format!(
+                "Format {} is not available",
+                format
+            )));
+        }
+
+        match (params.width, params.height, fit) {
+            (None, None, _) => Err(Error::ValidationError(
+                "Width or height is required".to_string(),
+            )),
+            (None, _, ImgFit::Cover) | (_, None, ImgFit::Cover) => Err(Error::ValidationError(
+                "Cover requires both width and height".to_string(),
+            )),
+            _ => Ok(Self {
+                width: params.width,
+                height: params.height,
+                fit,
+                format,
+                quality: params.quality.unwrap_or(img_config.quality),
+            }),
+        }
+    }
+
+    /// Name of the cached object, the quality only matters to lossy formats
+    pub fn cache_key(&self) -> String {
+        let size = format!(
+            "{}x{}-{}",
+            self.width.unwrap_or(0),
+            self.height.unwrap_or(0),
+            self.fit
+        );
+        match self.format {
+            ImgFormat::Jpeg | ImgFormat::Webp | ImgFormat::Avif => {
+                format!("{}-q{}.{}", size, self.quality, self.format)
+            }
+            ImgFormat::Png => format!("{}.{}", size, self.format),
+        }
+    }
+}
+
+/// Where every transformation of the files in the dir is cached
+pub fn transform_dir_prefix(dir_name: &str) -> String {
+    format!("{}/{}/", CACHE_PATH, dir_name)
+}
+
+/// Where every transformation of the file is cached
+pub fn transform_cache_prefix(dir_name: &str, filename: &str) -> String {
+    format!("{}{}/", transform_dir_prefix(dir_name), filename)
+}
+
+/// Splits the path of an original object, ie: `album/orig/abc-photo.jpg`,
+/// into its dir name and filename
+pub fn parse_original_path(path: &str) -> Option<(&str, &str)> {
+    let (head, filename) = path.rsplit_once('/')?;
+    let dir_name = head.strip_suffix(ORIGINAL_PATH)?.strip_suffix('/')?;
+    match dir_name.is_empty() || dir_name.contains('/') || filename.is_empty() {
+        true => None,
+        false => Some((dir_name, filename)),
+    }
+}
+
+/// Returns the path of the transformed original object, which is only
+/// created on the first request
+pub async fn transform_object(
+    storage_client: &dyn StorageBackend,
+    upload_dir: &Path,
+    bucket_name: &str,
+    path: &str,
+    transform: &ImgTransform,
+) -> Result<String> {
+    let Some((dir_name, filename)) = parse_original_path(path) else {
+        return Err(Error::NotFound("Object not found".to_string()));
+    };
+
+    let cache_path = format!(
+        "{}{}",
+        transform_cache_prefix(dir_name, filename),
+        transform.cache_key()
+    );
+    if storage_client
+        .object_exists(bucket_name, &cache_path)
+        .await?
+    {
+        return Ok(cache_path);
+    }
+
+    let tmp_dir = upload_dir.join("tmp");
+    if create_dir_all(&tmp_dir).await.is_err() {
+        return Err("Unable to create upload dir".into());
+    }
+
+    let id = generate_id();
+    let source = tmp_dir.join(format!("{}-source", id));
+    let dest = tmp_dir.join(format!("{}-{}", id, transform.cache_key()));
+
+    let res = match storage_client
+        .download_object(bucket_name, path, &source)
+        .await
+    {
+        Ok(_) => match blocking_transform(&source, &dest, transform).await {
+            Ok(_) => {
+                storage_client
+                    .upload_object(
+                        bucket_name,
+                        &cache_path,
+                        transform.format.content_type(),
+                        &dest,
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        },
+        Err(Error::ValidationError(_)) => Err(Error::NotFound("Object not found".to_string())),
+        Err(e) => Err(e),
+    };
+
+    for tmp_file in [&source, &dest] {
+        if let Err(e) = fs::remove_file(tmp_file).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            error!("Remove transform file {}: {}", tmp_file.display(), e);
+        }
+    }
+
+    res.map(|_| cache_path)
+}
+
+/// Decoding and encoding run on the blocking pool, large images take seconds
+async fn blocking_transform(source: &Path, dest: &Path, transform: &ImgTransform) -> Result<()> {
+    let source = source.to_path_buf();
+    let dest = dest.to_path_buf();
+    let transform = transform.clone();
+    let res =
+        tokio::task::spawn_blocking(move || transform_image(&source, &dest, &transform)).await;
+    match res {
+        Ok(res) => res,
+        Err(e) => {
+            error!("{}", e);
+            Err("Unable to transform image".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(width: Option<u32>, height: Option<u32>, fit: Option<&str>) -> TransformParams {
+        TransformParams {
+            width,
+            height,
+            fit: fit.map(|f| f.to_string()),
+            format: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_img_transform() {
+        let config = ImageConfig::default();
+
+        let transform = ImgTransform::new(&params(Some(400), None, None), &config).unwrap();
+        assert_eq!(transform.fit, ImgFit::Contain);
+        assert_eq!(transform.format, ImgFormat::Webp);
+        assert_eq!(transform.cache_key(), "400x0-contain-q80.webp");
+
+        let mut cover = params(Some(400), Some(200), Some("cover"));
+        cover.format = Some("jpeg".to_string());
+        cover.quality = Some(60);
+        let transform = ImgTransform::new(&cover, &config).unwrap();
+        assert_eq!(transform.cache_key(), "400x200-cover-q60.jpeg");
+
+        cover.format = Some("webp".to_string());
+        let transform = ImgTransform::new(&cover, &config).unwrap();
+        assert_eq!(transform.cache_key(), "400x200-cover-q60.webp");
+
+        assert!(ImgTransform::new(&params(None, None, None), &config).is_err());
+        assert!(ImgTransform::new(&params(Some(400), None, Some("cover")), &config).is_err());
+        assert!(ImgTransform::new(&params(Some(0), None, None), &config).is_err());
+        assert!(ImgTransform::new(&params(Some(5000), None, None), &config).is_err());
+        assert!(ImgTransform::new(&params(Some(400), None, Some("fill")), &config).is_err());
+
+        // Only configured values are accepted
+        assert!(ImgTransform::new(&params(Some(300), None, None), &config).is_err());
+        assert!(ImgTransform::new(&params(Some(400), Some(401), None), &config).is_err());
+        cover.format = Some("avif".to_string());
+        assert!(ImgTransform::new(&cover, &config).is_err());
+        cover.format = None;
+        cover.quality = Some(61);
+        assert!(ImgTransform::new(&cover, &config).is_err());
+
+        // The configured format is used even when not listed
+        let config = ImageConfig {
+            format: ImgFormat::Avif,
+            ..ImageConfig::default()
+        };
+        let transform = ImgTransform::new(&params(Some(400), None, None), &config).unwrap();
+        assert_eq!(transform.cache_key(), "400x0-contain-q80.avif");
+        cover.format = Some("avif".to_string());
+        cover.quality = Some(80);
+        let transform = ImgTransform::new(&cover, &config).unwrap();
+        assert_eq!(transform.cache_key(), "400x200-cover-q80.avif");
+    }
+
+    #[test]
+    fn test_parse_original_path() {
+        assert_eq!(
+            parse_original_path("album/orig/abc-photo.jpg"),
+            Some(("album", "abc-photo.jpg"))
+        );
+        assert_eq!(parse_original_path("album/thumb/abc-photo.jpg"), None);
+        assert_eq!(parse_original_path("orig/abc-photo.jpg"), None);
+        assert_eq!(parse_original_path("a/b/orig/abc-photo.jpg"), None);
+        assert_eq!(parse_original_path("album/orig/"), None);
+    }
+}
@@ -1,12 +1,7 @@
 use deadpool_diesel::sqlite::Pool;
 use tracing::error;
 
-use crate::{
-    Result,
-    buckets::test_read_bucket,
-    config::Config,
-    storage::{create_storage_client, test_list_hmac_keys},
-};
+use crate::{Result, buckets::test_read_bucket, storage::StorageBackend};
 
 use super::{HealthChecks, HealthStatus, LiveStatus};
 
@@ -17,8 +12,11 @@ pub async fn check_liveness() -> Result<LiveStatus> {
     })
 }
 
-pub async fn check_readiness(config: &Config, db_pool: &Pool) -> Result<HealthStatus> {
-    let checks = perform_checks(config, db_pool).await?;
+pub async fn check_readiness(
+    storage_client: &dyn StorageBackend,
+    db_pool: &Pool,
+) -> Result<HealthStatus> {
+    let checks = perform_checks(storage_client, db_pool).await?;
     let mut status = "DOWN".to_string();
     let mut message = "One or more health checks are failing".to_string();
 
@@ -34,18 +32,20 @@ pub async fn check_readiness(config: &Config, db_pool: &Pool) -> Result<HealthSt
     })
 }
 
-async fn perform_checks(config: &Config, db_pool: &Pool) -> Result<HealthChecks> {
+async fn perform_checks(
+    storage_client: &dyn StorageBackend,
+    db_pool: &Pool,
+) -> Result<HealthChecks> {
     let mut checks = HealthChecks::new();
 
-    checks.cloud_storage = check_cloud_storage(config).await?;
+    checks.cloud_storage = check_cloud_storage(storage_client).await?;
     checks.database = check_database(db_pool).await?;
 
     Ok(checks)
 }
 
-async fn check_cloud_storage(config: &Config) -> Result<String> {
-    let client = create_storage_client(config.cloud.credentials.as_str()).await?;
-    match test_list_hmac_keys(&client, config.cloud.project_id.as_str()).await {
+async fn check_cloud_storage(storage_client: &dyn StorageBackend) -> Result<String> {
+    match storage_client.check_health().await {
         Ok(_) => Ok("UP".to_string()),
         Err(e) => {
             let msg = format!("{}", e);
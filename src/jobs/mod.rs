@@ -0,0 +1,7 @@
+mod models;
+mod queries;
+mod runner;
+
+pub use models::*;
+pub use queries::*;
+pub use runner::*;
@@ -0,0 +1,139 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Long running operation that records its progress so it can be resumed
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = crate::schema::jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Job {
+    pub id: String,
+    pub bucket_id: String,
+    pub target_id: String,
+    pub kind: String,
+    pub status: String,
+
+    // Kind specific data encoded as JSON
+    #[serde(skip_serializing)]
+    pub payload: String,
+
+    // Last processed item, used when resuming
+    #[serde(skip_serializing)]
+    pub cursor: Option<String>,
+
+    pub processed: i32,
+    pub total: i32,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    // Processed items that could not be handled, counted in `processed`
+    pub failed: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewJob {
+    pub bucket_id: String,
+    pub target_id: String,
+    pub kind: JobKind,
+    pub payload: String,
+    pub total: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobKind {
+    DirRename,
+    RegenerateVersions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirRenamePayload {
+    pub bucket_name: String,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// Images of the bucket to process, narrowed down to a dir or a single file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegenerateVersionsPayload {
+    pub bucket_name: String,
+    pub dir_id: Option<String>,
+    pub file_id: Option<String>,
+}
+
+impl TryFrom<&str> for JobKind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "dir_rename" => Ok(JobKind::DirRename),
+            "regenerate_versions" => Ok(JobKind::RegenerateVersions),
+            _ => Err(format!("Invalid job kind: {}", value)),
+        }
+    }
+}
+
+impl core::fmt::Display for JobKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            JobKind::DirRename => write!(f, "dir_rename"),
+            JobKind::RegenerateVersions => write!(f, "regenerate_versions"),
+        }
+    }
+}
+
+impl TryFrom<&str> for JobStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "pending" => Ok(JobStatus::Pending),
+            "running" => Ok(JobStatus::Running),
+            "completed" => Ok(JobStatus::Completed),
+            "failed" => Ok(JobStatus::Failed),
+            _ => Err(format!("Invalid job status: {}", value)),
+        }
+    }
+}
+
+impl core::fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            JobStatus::Pending => write!(f, "pending"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Completed => write!(f, "completed"),
+            JobStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_kind_and_status() {
+        assert_eq!(JobKind::try_from("dir_rename"), Ok(JobKind::DirRename));
+        assert_eq!(
+            JobKind::try_from("regenerate_versions"),
+            Ok(JobKind::RegenerateVersions)
+        );
+        assert!(JobKind::try_from("unknown").is_err());
+
+        for status in [
+            JobStatus::Pending,
+            JobStatus::Running,
+            JobStatus::Completed,
+            JobStatus::Failed,
+        ] {
+            assert_eq!(JobStatus::try_from(status.to_string().as_str()), Ok(status));
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use deadpool_diesel::sqlite::Pool;
+
+use diesel::prelude::*;
+use diesel::{QueryDsl, SelectableHelper};
+use tracing::error;
+
+use crate::Result;
+use crate::schema::jobs::dsl;
+use crate::util::generate_id;
+
+use super::{Job, JobKind, JobStatus, NewJob};
+
+const ACTIVE_STATUSES: [JobStatus; 2] = [JobStatus::Pending, JobStatus::Running];
+
+/// Pending job ready to be inserted
+pub fn build_job(data: &NewJob) -> Job {
+    let today = chrono::Utc::now().timestamp();
+    Job {
+        id: generate_id(),
+        bucket_id: data.bucket_id.clone(),
+        target_id: data.target_id.clone(),
+        kind: data.kind.to_string(),
+        status: JobStatus::Pending.to_string(),
+        payload: data.payload.clone(),
+        cursor: None,
+        processed: 0,
+        total: data.total,
+        error: None,
+        created_at: today,
+        updated_at: today,
+        failed: 0,
+    }
+}
+
+pub async fn get_job(db_pool: &Pool, id: &str) -> Result<Option<Job>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let jid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::jobs
+                .find(jid)
+                .select(Job::as_select())
+                .first::<Job>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding job".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Finds a pending or running job operating on the target
+pub async fn find_active_job(db_pool: &Pool, target_id: &str) -> Result<Option<Job>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let tid = target_id.to_string();
+    let statuses: Vec<String> = ACTIVE_STATUSES.iter().map(|s| s.to_string()).collect();
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::jobs
+                .filter(dsl::target_id.eq(tid.as_str()))
+                .filter(dsl::status.eq_any(statuses))
+                .select(Job::as_select())
+                .first::<Job>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding job".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Whether a pending or running job operates on any of the targets, meant
+/// for checks made inside the transaction of the guarded change
+pub fn has_active_job(conn: &mut SqliteConnection, target_ids: &[&str]) -> QueryResult<bool> {
+    let statuses: Vec<String> = ACTIVE_STATUSES.iter().map(|s| s.to_string()).collect();
+    let count = dsl::jobs
+        .filter(dsl::target_id.eq_any(target_ids))
+        .filter(dsl::status.eq_any(statuses))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(count > 0)
+}
+
+/// Whether any job of the bucket is pending or running
+pub fn has_active_bucket_job(conn: &mut SqliteConnection, bucket_id: &str) -> QueryResult<bool> {
+    let statuses: Vec<String> = ACTIVE_STATUSES.iter().map(|s| s.to_string()).collect();
+    let count = dsl::jobs
+        .filter(dsl::bucket_id.eq(bucket_id))
+        .filter(dsl::status.eq_any(statuses))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(count > 0)
+}
+
+/// Pending or running jobs of the bucket of the given kind
+pub fn list_active_bucket_jobs(
+    conn: &mut SqliteConnection,
+    bucket_id: &str,
+    kind: JobKind,
+) -> QueryResult<Vec<Job>> {
+    let statuses: Vec<String> = ACTIVE_STATUSES.iter().map(|s| s.to_string()).collect();
+    dsl::jobs
+        .filter(dsl::bucket_id.eq(bucket_id))
+        .filter(dsl::kind.eq(kind.to_string()))
+        .filter(dsl::status.eq_any(statuses))
+        .select(Job::as_select())
+        .load::<Job>(conn)
+}
+
+/// Lists pending or running jobs, oldest first
+pub async fn list_active_jobs(db_pool: &Pool) -> Result<Vec<Job>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let statuses: Vec<String> = ACTIVE_STATUSES.iter().map(|s| s.to_string()).collect();
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::jobs
+                .filter(dsl::status.eq_any(statuses))
+                .select(Job::as_select())
+                .order(dsl::created_at.asc())
+                .load::<Job>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing jobs".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn update_job_status(
+    db_pool: &Pool,
+    id: &str,
+    status: JobStatus,
+    job_error: Option<String>,
+) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let jid = id.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::jobs.find(jid))
+                .set((
+                    dsl::status.eq(status.to_string()),
+                    dsl::error.eq(job_error),
+                    dsl::updated_at.eq(today),
+                ))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating job status".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Records the last processed item so the job can resume from there
+pub async fn update_job_progress(
+    db_pool: &Pool,
+    id: &str,
+    cursor: Option<String>,
+    processed: i32,
+    failed: i32,
+) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let jid = id.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::jobs.find(jid))
+                .set((
+                    dsl::cursor.eq(cursor),
+                    dsl::processed.eq(processed),
+                    dsl::failed.eq(failed),
+                    dsl::updated_at.eq(today),
+                ))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating job progress".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
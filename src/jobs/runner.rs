@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use deadpool_diesel::sqlite::Pool;
+use tracing::{error, info};
+
+use crate::Result;
+use crate::config::Config;
+use crate::dirs::run_dir_rename;
+use crate::files::run_regenerate_versions;
+use crate::storage::StorageBackend;
+
+use super::{Job, JobKind, JobStatus, list_active_jobs, update_job_status};
+
+/// Runs the job in the background, marking it failed on error
+pub fn spawn_job(
+    db_pool: Pool,
+    storage_client: Arc<dyn StorageBackend>,
+    config: Arc<Config>,
+    job: Job,
+) {
+    tokio::spawn(async move {
+        let _ = execute_job(&db_pool, storage_client.as_ref(), &config, &job).await;
+    });
+}
+
+/// Runs the job until done, marking it failed on error
+pub async fn execute_job(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    config: &Config,
+    job: &Job,
+) -> Result<()> {
+    let res = run_job(db_pool, storage_client, config, job).await;
+    if let Err(e) = &res {
+        error!("Job {} failed: {}", job.id, e);
+        let status_res =
+            update_job_status(db_pool, &job.id, JobStatus::Failed, Some(e.to_string())).await;
+        if let Err(e) = status_res {
+            error!("{}", e);
+        }
+    }
+    res
+}
+
+/// Picks up jobs interrupted by a shutdown or crash
+pub async fn resume_jobs(
+    db_pool: &Pool,
+    storage_client: &Arc<dyn StorageBackend>,
+    config: &Arc<Config>,
+) -> Result<()> {
+    let jobs = list_active_jobs(db_pool).await?;
+    for job in jobs.into_iter() {
+        info!("Resuming {} job {}", job.kind, job.id);
+        spawn_job(db_pool.clone(), storage_client.clone(), config.clone(), job);
+    }
+    Ok(())
+}
+
+async fn run_job(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    config: &Config,
+    job: &Job,
+) -> Result<()> {
+    let kind = JobKind::try_from(job.kind.as_str())?;
+    update_job_status(db_pool, &job.id, JobStatus::Running, None).await?;
+
+    match kind {
+        JobKind::DirRename => run_dir_rename(db_pool, storage_client, job).await?,
+        JobKind::RegenerateVersions => {
+            run_regenerate_versions(db_pool, storage_client, config, job).await?
+        }
+    }
+
+    update_job_status(db_pool, &job.id, JobStatus::Completed, None).await
+}
@@ -12,10 +12,13 @@ mod dirs;
 mod error;
 mod files;
 mod health;
+mod jobs;
 mod roles;
 mod run;
 mod schema;
 mod storage;
+mod trash;
+mod uploads;
 mod users;
 mod util;
 mod validators;
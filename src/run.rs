@@ -4,8 +4,9 @@ use crate::clients::run_client_command;
 use crate::config::CliArgs;
 use crate::config::Commands;
 use crate::config::Config;
-use crate::db::create_db_pool;
+use crate::db::{create_db_pool, run_migrate_command};
 use crate::health::check_readiness;
+use crate::storage::create_storage_client;
 use crate::users::run_user_command;
 use crate::web::server::run_web_server;
 
@@ -16,13 +17,15 @@ pub async fn run_command(args: CliArgs) -> Result<()> {
         Commands::Clients(cmd) => run_client_command(cmd, &config).await,
         Commands::Buckets(cmd) => run_bucket_command(cmd, &config).await,
         Commands::Users(cmd) => run_user_command(cmd, &config).await,
+        Commands::Migrate(cmd) => run_migrate_command(cmd, &config).await,
         Commands::CheckHealth => check_health(&config).await,
     }
 }
 
 pub async fn check_health(config: &Config) -> Result<()> {
     let pool = create_db_pool(config.db.url.as_str());
-    let health = check_readiness(config, &pool).await?;
+    let storage_client = create_storage_client(config).await?;
+    let health = check_readiness(storage_client.as_ref(), &pool).await?;
 
     // Print the health status
     println!("Status: {}", health.status);
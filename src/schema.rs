@@ -7,6 +7,10 @@ diesel::table! {
         name -> Text,
         images_only -> Integer,
         created_at -> BigInt,
+        file_count -> Integer,
+        total_size -> BigInt,
+        max_file_size -> Nullable<BigInt>,
+        dedupe_policy -> Nullable<Text>,
     }
 }
 
@@ -29,6 +33,8 @@ diesel::table! {
         file_count -> Integer,
         created_at -> BigInt,
         updated_at -> BigInt,
+        total_size -> BigInt,
+        deleted_at -> Nullable<BigInt>,
     }
 }
 
@@ -46,6 +52,65 @@ diesel::table! {
         created_at -> BigInt,
         updated_at -> BigInt,
         img_taken_at -> Nullable<BigInt>,
+        deleted_at -> Nullable<BigInt>,
+        checksum -> Nullable<Text>,
+        phash -> Nullable<Text>,
+        linked_id -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    img_profiles (id) {
+        id -> Text,
+        bucket_id -> Text,
+        name -> Text,
+        max_width -> Integer,
+        max_height -> Integer,
+        fit -> Text,
+        format -> Nullable<Text>,
+        quality -> Nullable<Integer>,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Text,
+        bucket_id -> Text,
+        target_id -> Text,
+        kind -> Text,
+        status -> Text,
+        payload -> Text,
+        cursor -> Nullable<Text>,
+        processed -> Integer,
+        total -> Integer,
+        error -> Nullable<Text>,
+        created_at -> BigInt,
+        updated_at -> BigInt,
+        failed -> Integer,
+    }
+}
+
+diesel::table! {
+    pending_uploads (id) {
+        id -> Text,
+        bucket_id -> Text,
+        dir_id -> Text,
+        name -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    upload_sessions (id) {
+        id -> Text,
+        bucket_id -> Text,
+        dir_id -> Text,
+        name -> Text,
+        size -> BigInt,
+        received -> BigInt,
+        created_at -> BigInt,
+        updated_at -> BigInt,
     }
 }
 
@@ -64,6 +129,23 @@ diesel::table! {
 
 diesel::joinable!(buckets -> clients (client_id));
 diesel::joinable!(dirs -> buckets (bucket_id));
+diesel::joinable!(files -> dirs (dir_id));
+diesel::joinable!(img_profiles -> buckets (bucket_id));
+diesel::joinable!(jobs -> buckets (bucket_id));
+diesel::joinable!(pending_uploads -> buckets (bucket_id));
+diesel::joinable!(pending_uploads -> dirs (dir_id));
+diesel::joinable!(upload_sessions -> buckets (bucket_id));
+diesel::joinable!(upload_sessions -> dirs (dir_id));
 diesel::joinable!(users -> clients (client_id));
 
-diesel::allow_tables_to_appear_in_same_query!(buckets, clients, dirs, files, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    buckets,
+    clients,
+    dirs,
+    files,
+    img_profiles,
+    jobs,
+    pending_uploads,
+    upload_sessions,
+    users,
+);
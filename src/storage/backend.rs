@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::Result;
+
+/// Url where clients PUT an object, along with the headers they must send
+#[derive(Debug, Clone, Serialize)]
+pub struct SignedUpload {
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Storage operations required by the service regardless of where the objects live
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Verifies that the bucket exists and is accessible, returns the bucket name
+    async fn read_bucket(&self, name: &str) -> Result<String>;
+
+    /// Uploads the file at `source` into the bucket under the given object path
+    async fn upload_object(
+        &self,
+        bucket: &str,
+        path: &str,
+        content_type: &str,
+        source: &Path,
+    ) -> Result<()>;
+
+    /// Copies an object, possibly into another bucket
+    async fn copy_object(
+        &self,
+        source_bucket: &str,
+        source_path: &str,
+        dest_bucket: &str,
+        dest_path: &str,
+    ) -> Result<()>;
+
+    async fn delete_object(&self, bucket: &str, path: &str) -> Result<()>;
+
+    /// Checks whether the object exists
+    async fn object_exists(&self, bucket: &str, path: &str) -> Result<bool>;
+
+    /// Size of the object in bytes, None when it does not exist
+    async fn object_size(&self, bucket: &str, path: &str) -> Result<Option<u64>>;
+
+    /// Deletes every object whose path starts with the prefix, if any
+    async fn delete_prefix(&self, bucket: &str, prefix: &str) -> Result<()>;
+
+    /// Saves the object into the file at `dest`
+    async fn download_object(&self, bucket: &str, path: &str, dest: &Path) -> Result<()>;
+
+    /// Generates a time-limited download url for the object
+    async fn signed_url(&self, bucket: &str, path: &str) -> Result<String>;
+
+    /// Generates a time-limited url where clients can PUT the object themselves,
+    /// the storage refuses objects larger than `max_size` where it supports it
+    async fn signed_upload_url(
+        &self,
+        bucket: &str,
+        path: &str,
+        max_size: u64,
+    ) -> Result<SignedUpload>;
+
+    /// Checks whether the storage is reachable, used by health checks
+    async fn check_health(&self) -> Result<()>;
+}
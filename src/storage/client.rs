@@ -1,55 +1,59 @@
 use std::path::PathBuf;
-use std::time::Duration;
+use std::sync::Arc;
 
-use google_cloud_storage::client::google_cloud_auth::credentials::CredentialsFile;
-use google_cloud_storage::client::{Client, ClientConfig};
-use google_cloud_storage::http::Error as CloudError;
-use google_cloud_storage::http::buckets::get::GetBucketRequest;
-use google_cloud_storage::http::hmac_keys::list::ListHmacKeysRequest;
-use google_cloud_storage::http::objects::delete::DeleteObjectRequest;
-use google_cloud_storage::http::objects::upload::{Media, UploadObjectRequest, UploadType};
-use google_cloud_storage::sign::SignedURLOptions;
+use tracing::error;
 
+use crate::Result;
 use crate::buckets::BucketDto;
+use crate::config::{Config, StorageKind};
 use crate::dirs::Dir;
-use crate::files::{FileDto, ImgVersionDto, ORIGINAL_PATH};
-use crate::{Error, Result};
-
-pub async fn create_storage_client(key_file: &str) -> Result<Client> {
-    match CredentialsFile::new_from_file(key_file.to_string()).await {
-        Ok(creds) => match ClientConfig::default().with_credentials(creds).await {
-            Ok(config) => Ok(Client::new(config)),
-            Err(err) => Err(format!("Error creating Cloud Storage config: {}", err).into()),
-        },
-        Err(err) => Err(format!("Error reading credentials file: {}", err).into()),
+use crate::files::{
+    FileDto, ImgVersionDto, ORIGINAL_PATH, transform_cache_prefix, transform_dir_prefix,
+};
+
+use super::{GcsBackend, LocalBackend, SIGNED_URL_EXPIRY, StorageBackend, create_transform_url};
+
+/// Creates the storage backend selected in the config
+pub async fn create_storage_client(config: &Config) -> Result<Arc<dyn StorageBackend>> {
+    match config.storage.backend {
+        StorageKind::Gcs => {
+            let Some(cloud) = &config.cloud else {
+                return Err("Google Cloud config is required.".into());
+            };
+            let backend = GcsBackend::new(&cloud.credentials, &cloud.project_id).await?;
+            Ok(Arc::new(backend))
+        }
+        StorageKind::Local => {
+            let Some(dir) = &config.storage.dir else {
+                return Err("Local storage directory is required.".into());
+            };
+            let Some(base_url) = config.base_url() else {
+                return Err("Storage base url is required for the local backend.".into());
+            };
+            let backend = LocalBackend::new(dir, base_url, config.url_secret());
+            Ok(Arc::new(backend))
+        }
+        #[cfg(test)]
+        StorageKind::Memory => Ok(Arc::new(super::MemoryBackend::new())),
     }
 }
 
-pub async fn read_bucket(client: &Client, name: &str) -> Result<String> {
-    let res = client
-        .get_bucket(&GetBucketRequest {
-            bucket: name.to_string(),
-            ..Default::default()
-        })
-        .await;
+/// Object path of a file version inside the bucket
+pub fn object_path(dir_name: &str, version: &str, filename: &str) -> String {
+    format!("{}/{}/{}", dir_name, version, filename)
+}
 
-    match res {
-        Ok(bucket) => Ok(bucket.name),
-        Err(e) => match e {
-            CloudError::Response(gerr) => {
-                if gerr.code >= 400 && gerr.code < 500 {
-                    Err(Error::ValidationError(gerr.message))
-                } else {
-                    Err(format!("Google error: {}", gerr.message).as_str().into())
-                }
-            }
-            _ => Err("Failed to read bucket from cloud storage.".into()),
-        },
-    }
+/// Object path of an image version, named after its encoding
+pub fn version_object_path(dir_name: &str, version: &ImgVersionDto, filename: &str) -> String {
+    object_path(
+        dir_name,
+        &version.version.to_string(),
+        &version.filename(filename),
+    )
 }
 
 pub async fn upload_object(
-    client: &Client,
+    client: &dyn StorageBackend,
     bucket: &BucketDto,
     dir: &Dir,
     source_dir: &PathBuf,
@@ -62,52 +66,21 @@ pub async fn upload_object(
 }
 
 async fn upload_regular_object(
-    client: &Client,
+    client: &dyn StorageBackend,
     bucket: &BucketDto,
     dir: &Dir,
     source_dir: &PathBuf,
     file: &FileDto,
 ) -> Result<()> {
-    // Prepare media
-    let file_path = format!("{}/{}/{}", &dir.name, ORIGINAL_PATH, &file.filename);
-    let mut media = Media::new(file_path.clone());
-    media.content_type = file.content_type.clone().into();
-    let upload_type = UploadType::Simple(media);
-
-    // Read file, preferred a stream but skill issues...
+    let file_path = object_path(&dir.name, ORIGINAL_PATH, &file.filename);
     let source_path = source_dir.join(ORIGINAL_PATH).join(&file.filename);
-    let Ok(data) = std::fs::read(&source_path) else {
-        return Err("Failed to read file for upload.".into());
-    };
-
-    let upload_res = client
-        .upload_object(
-            &UploadObjectRequest {
-                bucket: bucket.name.clone(),
-                ..Default::default()
-            },
-            data,
-            &upload_type,
-        )
-        .await;
-
-    match upload_res {
-        Ok(_) => Ok(()),
-        Err(e) => match e {
-            CloudError::Response(gerr) => {
-                if gerr.code >= 400 && gerr.code < 500 {
-                    Err(Error::ValidationError(gerr.message))
-                } else {
-                    Err(format!("Google error: {}", gerr.message).as_str().into())
-                }
-            }
-            _ => Err("Failed to upload object to cloud storage.".into()),
-        },
-    }
+    client
+        .upload_object(&bucket.name, &file_path, &file.content_type, &source_path)
+        .await
 }
 
 async fn upload_image_object(
-    client: &Client,
+    client: &dyn StorageBackend,
     bucket: &BucketDto,
     dir: &Dir,
     source_dir: &PathBuf,
@@ -115,7 +88,7 @@ async fn upload_image_object(
 ) -> Result<()> {
     if let Some(versions) = &file.img_versions {
         for version in versions.iter() {
-            let _ = upload_image_version(&client, bucket, dir, source_dir, &file, version).await?;
+            upload_image_version(client, bucket, dir, source_dir, file, version).await?;
         }
     }
 
@@ -123,105 +96,114 @@ async fn upload_image_object(
 }
 
 async fn upload_image_version(
-    client: &Client,
+    client: &dyn StorageBackend,
     bucket: &BucketDto,
     dir: &Dir,
     source_dir: &PathBuf,
     file: &FileDto,
     version: &ImgVersionDto,
 ) -> Result<()> {
-    // Prepare media
-    let version_dir: String = version.version.to_string();
-    let file_path = format!("{}/{}/{}", &dir.name, &version_dir, &file.filename);
-    let mut media = Media::new(file_path.clone());
-    media.content_type = file.content_type.clone().into();
-    let upload_type = UploadType::Simple(media);
-
-    // Read file, preferred a stream but skill issues...
-    let source_path = source_dir.join(&version_dir).join(&file.filename);
-    let Ok(data) = std::fs::read(&source_path) else {
-        return Err("Failed to read image version for upload.".into());
-    };
+    let file_path = version_object_path(&dir.name, version, &file.filename);
+    let source_path = version.to_path(source_dir, &file.filename);
+    let content_type = version.content_type.as_ref().unwrap_or(&file.content_type);
+    client
+        .upload_object(&bucket.name, &file_path, content_type, &source_path)
+        .await
+}
 
-    let upload_res = client
-        .upload_object(
-            &UploadObjectRequest {
-                bucket: bucket.name.clone(),
-                ..Default::default()
-            },
-            data,
-            &upload_type,
-        )
-        .await;
+/// Lists the object paths of every stored version of the file
+pub fn file_object_paths(dir_name: &str, file: &FileDto) -> Vec<String> {
+    if file.is_image {
+        match &file.img_versions {
+            Some(versions) => versions
+                .iter()
+                .map(|version| version_object_path(dir_name, version, &file.filename))
+                .collect(),
+            None => Vec::new(),
+        }
+    } else {
+        vec![object_path(dir_name, ORIGINAL_PATH, &file.filename)]
+    }
+}
 
-    match upload_res {
-        Ok(_) => Ok(()),
-        Err(e) => match e {
-            CloudError::Response(gerr) => {
-                if gerr.code >= 400 && gerr.code < 500 {
-                    Err(Error::ValidationError(gerr.message))
-                } else {
-                    Err(format!("Google error: {}", gerr.message).as_str().into())
+/// Copies every stored version of the file to the destination.
+/// Already copied objects are removed when one of the copies fails.
+pub async fn copy_file_object(
+    client: &dyn StorageBackend,
+    source: (&str, &str),
+    dest: (&str, &str),
+    file: &FileDto,
+    dest_filename: &str,
+) -> Result<()> {
+    let (source_bucket, source_dir) = source;
+    let (dest_bucket, dest_dir) = dest;
+
+    let mut dest_file = file.clone();
+    dest_file.filename = dest_filename.to_string();
+
+    let source_paths = file_object_paths(source_dir, file);
+    let dest_paths = file_object_paths(dest_dir, &dest_file);
+
+    let mut copied: Vec<&String> = Vec::with_capacity(dest_paths.len());
+    for (source_path, dest_path) in source_paths.iter().zip(dest_paths.iter()) {
+        let res = client
+            .copy_object(source_bucket, source_path, dest_bucket, dest_path)
+            .await;
+        if let Err(e) = res {
+            for path in copied.iter() {
+                if let Err(e) = client.delete_object(dest_bucket, path).await {
+                    error!("Cleanup copied object: {}", e);
                 }
             }
-            _ => Err("Failed to upload object to cloud storage.".into()),
-        },
+            return Err(e);
+        }
+        copied.push(dest_path);
     }
+
+    Ok(())
 }
 
+/// Deletes every stored version of the file and its transformed images.
+/// Keeps going when an object can't be deleted and returns the first error.
 pub async fn delete_file_object(
-    client: &Client,
+    client: &dyn StorageBackend,
     bucket_name: &str,
     dir_name: &str,
     file: &FileDto,
 ) -> Result<()> {
-    if file.is_image {
-        // Delete all versions
-        if let Some(versions) = &file.img_versions {
-            for version in versions.iter() {
-                let path = format!(
-                    "{}/{}/{}",
-                    dir_name,
-                    version.version.to_string(),
-                    &file.filename
-                );
-                let _ = delete_object_by_path(&client, bucket_name, &path).await?;
+    let mut res = Ok(());
+    for path in file_object_paths(dir_name, file).iter() {
+        if let Err(e) = client.delete_object(bucket_name, path).await {
+            error!("Delete object {}: {}", path, e);
+            if res.is_ok() {
+                res = Err(e);
             }
         }
-    } else {
-        let path = format!("{}/{}/{}", dir_name, ORIGINAL_PATH, &file.filename);
-        let _ = delete_object_by_path(&client, bucket_name, &path).await?;
     }
 
-    Ok(())
-}
+    // Transformed images are regenerated on demand, no need to fail
+    if file.is_image {
+        let prefix = transform_cache_prefix(dir_name, &file.filename);
+        if let Err(e) = client.delete_prefix(bucket_name, &prefix).await {
+            error!("Cleanup transformed images: {}", e);
+        }
+    }
 
-async fn delete_object_by_path(client: &Client, bucket_name: &str, path: &str) -> Result<()> {
-    let res = client
-        .delete_object(&DeleteObjectRequest {
-            bucket: bucket_name.to_string(),
-            object: path.to_string(),
-            ..Default::default()
-        })
-        .await;
+    res
+}
 
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => match e {
-            CloudError::Response(gerr) => {
-                if gerr.code >= 400 && gerr.code < 500 {
-                    Err(Error::ValidationError(gerr.message))
-                } else {
-                    Err(format!("Google error: {}", gerr.message).as_str().into())
-                }
-            }
-            _ => Err("Failed to delete object from cloud storage.".into()),
-        },
+/// Deletes the transformed images left under a dir name that is no longer
+/// used, ie: of files that failed to delete or were trashed
+pub async fn delete_dir_transforms(client: &dyn StorageBackend, bucket_name: &str, dir_name: &str) {
+    let prefix = transform_dir_prefix(dir_name);
+    if let Err(e) = client.delete_prefix(bucket_name, &prefix).await {
+        error!("Cleanup transformed images: {}", e);
     }
 }
 
 pub async fn format_files(
-    client: &Client,
+    client: &Arc<dyn StorageBackend>,
+    config: &Arc<Config>,
     bucket_name: &str,
     dir: &str,
     files: Vec<FileDto>,
@@ -229,12 +211,20 @@ pub async fn format_files(
     let mut tasks = Vec::with_capacity(files.len());
     for file in files.iter() {
         let client_copy = client.clone();
+        let config_copy = config.clone();
         let file_copy = file.clone();
         let bname = bucket_name.to_string();
         let dir_name = dir.to_string();
 
         tasks.push(tokio::spawn(async move {
-            format_file_single(&client_copy, &bname, &dir_name, file_copy).await
+            format_file_single(
+                client_copy.as_ref(),
+                config_copy.as_ref(),
+                &bname,
+                &dir_name,
+                file_copy,
+            )
+            .await
         }));
     }
 
@@ -251,90 +241,54 @@ pub async fn format_files(
 }
 
 pub async fn format_file(
-    client: &Client,
+    client: &dyn StorageBackend,
+    config: &Config,
     bucket_name: &str,
     dir_name: &str,
     file: FileDto,
 ) -> Result<FileDto> {
-    format_file_single(&client, bucket_name, dir_name, file).await
+    format_file_single(client, config, bucket_name, dir_name, file).await
 }
 
 async fn format_file_single(
-    client: &Client,
+    client: &dyn StorageBackend,
+    config: &Config,
     bucket_name: &str,
     dir_name: &str,
     mut file: FileDto,
 ) -> Result<FileDto> {
+    // Linked files share the objects stored under another dir
+    let dir_name = file.object_dir.clone().unwrap_or(dir_name.to_string());
+    let dir_name = dir_name.as_str();
     if file.is_image {
         if let Some(versions) = &file.img_versions {
             let mut updated_versions: Vec<ImgVersionDto> = Vec::with_capacity(versions.len());
             for version in versions.iter() {
-                let url = generate_url(
-                    client,
-                    bucket_name,
-                    &format!(
-                        "{}/{}/{}",
-                        dir_name,
-                        version.version.to_string(),
-                        file.filename
-                    ),
-                )
-                .await?;
+                let path = version_object_path(dir_name, version, &file.filename);
+                let url = client.signed_url(bucket_name, &path).await?;
                 let mut version_copy = version.clone();
                 version_copy.url = Some(url);
                 updated_versions.push(version_copy);
             }
-            if updated_versions.len() > 0 {
+            if !updated_versions.is_empty() {
                 file.img_versions = Some(updated_versions);
             }
         }
+
+        // Served by this service regardless of the storage backend, only
+        // when its public url is known
+        if let Some(base_url) = config.base_url() {
+            let path = object_path(dir_name, ORIGINAL_PATH, &file.filename);
+            let expires = chrono::Utc::now().timestamp() + SIGNED_URL_EXPIRY;
+            let url =
+                create_transform_url(base_url, config.url_secret(), bucket_name, &path, expires)?;
+            file.transform_url = Some(url);
+        }
     } else {
-        let url = generate_url(
-            client,
-            bucket_name,
-            &format!("{}/{}/{}", dir_name, ORIGINAL_PATH, file.filename),
-        )
-        .await?;
+        let path = object_path(dir_name, ORIGINAL_PATH, &file.filename);
+        let url = client.signed_url(bucket_name, &path).await?;
         file.url = Some(url);
     }
 
     Ok(file)
 }
-
-async fn generate_url(client: &Client, bucket_name: &str, file_path: &str) -> Result<String> {
-    let expires = Duration::from_secs(3600 * 12);
-    let mut options = SignedURLOptions::default();
-    options.expires = expires;
-
-    let res = client
-        .signed_url(bucket_name, file_path, None, None, options)
-        .await;
-
-    match res {
-        Ok(url) => Ok(url),
-        Err(_) => Err("Failed to sign object URL.".into()),
-    }
-}
-
-pub async fn test_list_hmac_keys(client: &Client, project_id: &str) -> Result<()> {
-    let res = client
-        .list_hmac_keys(&ListHmacKeysRequest {
-            project_id: project_id.to_string(),
-            ..Default::default()
-        })
-        .await;
-
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => match e {
-            CloudError::Response(gerr) => {
-                if gerr.code >= 400 && gerr.code < 500 {
-                    Err(Error::ValidationError(gerr.message))
-                } else {
-                    Err(format!("Google error: {}", gerr.message).as_str().into())
-                }
-            }
-            _ => Err("Failed to list buckets from cloud storage.".into()),
-        },
-    }
-}
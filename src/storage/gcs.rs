@@ -0,0 +1,518 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use google_cloud_storage::client::google_cloud_auth::credentials::CredentialsFile;
+use google_cloud_storage::client::{Client, ClientConfig};
+use google_cloud_storage::http::Error as CloudError;
+use google_cloud_storage::http::buckets::get::GetBucketRequest;
+use google_cloud_storage::http::hmac_keys::list::ListHmacKeysRequest;
+use google_cloud_storage::http::objects::copy::CopyObjectRequest;
+use google_cloud_storage::http::objects::delete::DeleteObjectRequest;
+use google_cloud_storage::http::objects::download::Range;
+use google_cloud_storage::http::objects::get::GetObjectRequest;
+use google_cloud_storage::http::objects::list::ListObjectsRequest;
+use google_cloud_storage::http::objects::upload::{Media, UploadObjectRequest, UploadType};
+use google_cloud_storage::http::resumable_upload_client::{ChunkSize, UploadStatus};
+use google_cloud_storage::sign::{SignedURLMethod, SignedURLOptions};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio_util::io::ReaderStream;
+
+use crate::{Error, Result};
+
+use super::{SignedUpload, StorageBackend, UPLOAD_URL_EXPIRY};
+
+// Files larger than this are uploaded through a resumable session
+const RESUMABLE_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+// Must be a multiple of 256 KiB except for the last chunk
+const UPLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+// Attempts per chunk before giving up on the whole upload
+const UPLOAD_CHUNK_ATTEMPTS: u32 = 3;
+
+pub struct GcsBackend {
+    client: Client,
+    project_id: String,
+}
+
+impl GcsBackend {
+    pub async fn new(key_file: &str, project_id: &str) -> Result<Self> {
+        match CredentialsFile::new_from_file(key_file.to_string()).await {
+            Ok(creds) => match ClientConfig::default().with_credentials(creds).await {
+                Ok(config) => Ok(Self {
+                    client: Client::new(config),
+                    project_id: project_id.to_string(),
+                }),
+                Err(err) => Err(format!("Error creating Cloud Storage config: {}", err).into()),
+            },
+            Err(err) => Err(format!("Error reading credentials file: {}", err).into()),
+        }
+    }
+}
+
+impl GcsBackend {
+    /// Uploads the file through a resumable session, one chunk at a time.
+    /// A failed chunk is sent again from the last byte the server persisted.
+    async fn upload_resumable(
+        &self,
+        req: &UploadObjectRequest,
+        upload_type: &UploadType,
+        mut file: File,
+        total: u64,
+    ) -> Result<()> {
+        let uploader = match self.client.prepare_resumable_upload(req, upload_type).await {
+            Ok(uploader) => uploader,
+            Err(e) => {
+                return Err(to_storage_error(
+                    e,
+                    "Failed to start upload to cloud storage.",
+                ));
+            }
+        };
+
+        let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE as usize];
+        let mut offset: u64 = 0;
+        let mut attempts: u32 = 0;
+        while offset < total {
+            let last_byte = (offset + UPLOAD_CHUNK_SIZE).min(total) - 1;
+            let len = (last_byte - offset + 1) as usize;
+            let read_res = match file.seek(SeekFrom::Start(offset)).await {
+                Ok(_) => file.read_exact(&mut buffer[..len]).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            if read_res.is_err() {
+                let _ = uploader.cancel().await;
+                return Err("Failed to read file for upload.".into());
+            }
+
+            let size = ChunkSize::new(offset, last_byte, Some(total));
+            let res = uploader
+                .upload_multiple_chunk(buffer[..len].to_vec(), &size)
+                .await;
+            let status = match res {
+                Ok(status) => status,
+                Err(e) => {
+                    if attempts + 1 >= UPLOAD_CHUNK_ATTEMPTS || !is_retryable(&e) {
+                        let _ = uploader.cancel().await;
+                        return Err(to_storage_error(
+                            e,
+                            "Failed to upload object to cloud storage.",
+                        ));
+                    }
+
+                    // Ask the server how much it kept before sending again
+                    let delay = 500 * (attempts as u64 + 1);
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    match uploader.status(Some(total)).await {
+                        Ok(status) => status,
+                        Err(_) => {
+                            attempts += 1;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            match resume_offset(&status) {
+                None => return Ok(()),
+                Some(next) if next > offset => {
+                    attempts = 0;
+                    offset = next;
+                }
+                // Nothing new was persisted, the same bytes are sent again
+                Some(next) => {
+                    attempts += 1;
+                    if attempts >= UPLOAD_CHUNK_ATTEMPTS {
+                        let _ = uploader.cancel().await;
+                        return Err("Incomplete chunk upload to cloud storage.".into());
+                    }
+                    offset = next;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for GcsBackend {
+    async fn read_bucket(&self, name: &str) -> Result<String> {
+        let res = self
+            .client
+            .get_bucket(&GetBucketRequest {
+                bucket: name.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        match res {
+            Ok(bucket) => Ok(bucket.name),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to read bucket from cloud storage.",
+            )),
+        }
+    }
+
+    async fn upload_object(
+        &self,
+        bucket: &str,
+        path: &str,
+        content_type: &str,
+        source: &Path,
+    ) -> Result<()> {
+        let Ok(file) = File::open(source).await else {
+            return Err("Failed to read file for upload.".into());
+        };
+        let Ok(meta) = file.metadata().await else {
+            return Err("Failed to read file for upload.".into());
+        };
+
+        // Prepare media, streamed uploads need the length up front
+        let mut media = Media::new(path.to_string());
+        media.content_type = content_type.to_string().into();
+        media.content_length = Some(meta.len());
+        let upload_type = UploadType::Simple(media);
+
+        let req = UploadObjectRequest {
+            bucket: bucket.to_string(),
+            ..Default::default()
+        };
+
+        // Large objects are sent in chunks, a failed chunk is retried on its own
+        if meta.len() > RESUMABLE_THRESHOLD {
+            return self
+                .upload_resumable(&req, &upload_type, file, meta.len())
+                .await;
+        }
+
+        let upload_res = self
+            .client
+            .upload_streamed_object(&req, ReaderStream::new(file), &upload_type)
+            .await;
+
+        match upload_res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to upload object to cloud storage.",
+            )),
+        }
+    }
+
+    async fn copy_object(
+        &self,
+        source_bucket: &str,
+        source_path: &str,
+        dest_bucket: &str,
+        dest_path: &str,
+    ) -> Result<()> {
+        let res = self
+            .client
+            .copy_object(&CopyObjectRequest {
+                source_bucket: source_bucket.to_string(),
+                source_object: source_path.to_string(),
+                destination_bucket: dest_bucket.to_string(),
+                destination_object: dest_path.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to copy object in cloud storage.",
+            )),
+        }
+    }
+
+    async fn delete_object(&self, bucket: &str, path: &str) -> Result<()> {
+        let res = self
+            .client
+            .delete_object(&DeleteObjectRequest {
+                bucket: bucket.to_string(),
+                object: path.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to delete object from cloud storage.",
+            )),
+        }
+    }
+
+    async fn object_exists(&self, bucket: &str, path: &str) -> Result<bool> {
+        let res = self
+            .client
+            .get_object(&GetObjectRequest {
+                bucket: bucket.to_string(),
+                object: path.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(CloudError::Response(gerr)) if gerr.code == 404 => Ok(false),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to read object from cloud storage.",
+            )),
+        }
+    }
+
+    async fn object_size(&self, bucket: &str, path: &str) -> Result<Option<u64>> {
+        let res = self
+            .client
+            .get_object(&GetObjectRequest {
+                bucket: bucket.to_string(),
+                object: path.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        match res {
+            Ok(object) => Ok(Some(object.size.max(0) as u64)),
+            Err(CloudError::Response(gerr)) if gerr.code == 404 => Ok(None),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to read object from cloud storage.",
+            )),
+        }
+    }
+
+    async fn delete_prefix(&self, bucket: &str, prefix: &str) -> Result<()> {
+        let mut page_token: Option<String> = None;
+        loop {
+            let res = self
+                .client
+                .list_objects(&ListObjectsRequest {
+                    bucket: bucket.to_string(),
+                    prefix: Some(prefix.to_string()),
+                    page_token: page_token.clone(),
+                    ..Default::default()
+                })
+                .await;
+            let listing = match res {
+                Ok(listing) => listing,
+                Err(e) => {
+                    return Err(to_storage_error(
+                        e,
+                        "Failed to list objects from cloud storage.",
+                    ));
+                }
+            };
+
+            for item in listing.items.unwrap_or_default().iter() {
+                self.delete_object(bucket, &item.name).await?;
+            }
+
+            page_token = listing.next_page_token;
+            if page_token.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn download_object(&self, bucket: &str, path: &str, dest: &Path) -> Result<()> {
+        let req = GetObjectRequest {
+            bucket: bucket.to_string(),
+            object: path.to_string(),
+            ..Default::default()
+        };
+        let mut stream = match self
+            .client
+            .download_streamed_object(&req, &Range::default())
+            .await
+        {
+            Ok(stream) => stream,
+            Err(e) => {
+                return Err(to_storage_error(
+                    e,
+                    "Failed to download object from cloud storage.",
+                ));
+            }
+        };
+
+        let Ok(mut file) = File::create(dest).await else {
+            return Err("Failed to write downloaded object.".into());
+        };
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    return Err(to_storage_error(
+                        e,
+                        "Failed to download object from cloud storage.",
+                    ));
+                }
+            };
+            if file.write_all(&chunk).await.is_err() {
+                return Err("Failed to write downloaded object.".into());
+            }
+        }
+
+        match file.flush().await {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Failed to write downloaded object.".into()),
+        }
+    }
+
+    async fn signed_url(&self, bucket: &str, path: &str) -> Result<String> {
+        let options = SignedURLOptions {
+            expires: Duration::from_secs(3600 * 12),
+            ..Default::default()
+        };
+
+        let res = self
+            .client
+            .signed_url(bucket, path, None, None, options)
+            .await;
+
+        match res {
+            Ok(url) => Ok(url),
+            Err(_) => Err("Failed to sign object URL.".into()),
+        }
+    }
+
+    /// The size limit is a signed header, the client must send it as is
+    async fn signed_upload_url(
+        &self,
+        bucket: &str,
+        path: &str,
+        max_size: u64,
+    ) -> Result<SignedUpload> {
+        let (name, value) = content_length_range(max_size);
+        let options = SignedURLOptions {
+            method: SignedURLMethod::PUT,
+            expires: Duration::from_secs(UPLOAD_URL_EXPIRY as u64),
+            headers: vec![format!("{}:{}", name, value)],
+            ..Default::default()
+        };
+
+        let res = self
+            .client
+            .signed_url(bucket, path, None, None, options)
+            .await;
+
+        match res {
+            Ok(url) => Ok(SignedUpload {
+                url,
+                headers: BTreeMap::from([(name.to_string(), value)]),
+            }),
+            Err(_) => Err("Failed to sign object upload URL.".into()),
+        }
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        let res = self
+            .client
+            .list_hmac_keys(&ListHmacKeysRequest {
+                project_id: self.project_id.clone(),
+                ..Default::default()
+            })
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(to_storage_error(
+                e,
+                "Failed to list buckets from cloud storage.",
+            )),
+        }
+    }
+}
+
+fn to_storage_error(e: CloudError, fallback: &str) -> Error {
+    match e {
+        CloudError::Response(gerr) => {
+            if gerr.code >= 400 && gerr.code < 500 {
+                Error::ValidationError(gerr.message)
+            } else {
+                format!("Google error: {}", gerr.message).as_str().into()
+            }
+        }
+        _ => fallback.into(),
+    }
+}
+
+/// Header making cloud storage reject uploads larger than `max_size`
+fn content_length_range(max_size: u64) -> (&'static str, String) {
+    ("x-goog-content-length-range", format!("0,{}", max_size))
+}
+
+/// Network failures, timeouts, throttling and server errors may succeed later
+fn is_retryable(e: &CloudError) -> bool {
+    match e {
+        CloudError::Response(gerr) => gerr.code == 408 || gerr.code == 429 || gerr.code >= 500,
+        CloudError::HttpClient(_) => true,
+        _ => false,
+    }
+}
+
+/// Next byte to send according to the upload status, none once complete
+fn resume_offset(status: &UploadStatus) -> Option<u64> {
+    match status {
+        UploadStatus::Ok(_) => None,
+        UploadStatus::NotStarted => Some(0),
+        UploadStatus::ResumeIncomplete(range) => Some(range.last_byte + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use google_cloud_storage::http::error::ErrorResponse;
+    use google_cloud_storage::http::objects::Object;
+    use google_cloud_storage::http::resumable_upload_client::UploadedRange;
+
+    fn response_error(code: u16) -> CloudError {
+        CloudError::Response(ErrorResponse {
+            code,
+            errors: vec![],
+            message: "error".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_resume_offset() {
+        assert_eq!(UPLOAD_CHUNK_SIZE % (256 * 1024), 0);
+        assert_eq!(resume_offset(&UploadStatus::NotStarted), Some(0));
+        assert_eq!(resume_offset(&UploadStatus::Ok(Object::default())), None);
+
+        // Server may keep only part of a chunk, always in 256 KiB blocks
+        let range = UploadedRange {
+            first_byte: 0,
+            last_byte: 256 * 1024 - 1,
+        };
+        let status = UploadStatus::ResumeIncomplete(range);
+        assert_eq!(resume_offset(&status), Some(256 * 1024));
+    }
+
+    #[test]
+    fn test_content_length_range() {
+        let (name, value) = content_length_range(8 * 1024 * 1024);
+        assert_eq!(name, "x-goog-content-length-range");
+        assert_eq!(value, "0,8388608");
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&response_error(503)));
+        assert!(is_retryable(&response_error(429)));
+        assert!(is_retryable(&response_error(408)));
+        assert!(!is_retryable(&response_error(403)));
+        assert!(!is_retryable(&response_error(404)));
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::fs;
+
+use crate::{Error, Result};
+
+use super::{
+    SIGNED_URL_EXPIRY, SignedUpload, StorageBackend, UPLOAD_URL_EXPIRY, create_signed_url,
+};
+
+/// Stores objects on the local filesystem under `{root}/{bucket}/{path}`
+/// and serves them through signed urls to the objects endpoint
+pub struct LocalBackend {
+    root: PathBuf,
+    base_url: String,
+    secret: String,
+}
+
+impl LocalBackend {
+    pub fn new(root: &Path, base_url: &str, secret: &str) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            base_url: base_url.to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    fn object_path(&self, bucket: &str, path: &str) -> PathBuf {
+        local_object_path(&self.root, bucket, path)
+    }
+}
+
+/// Resolves the object path inside the storage root
+pub fn local_object_path(root: &Path, bucket: &str, path: &str) -> PathBuf {
+    root.join(bucket).join(path)
+}
+
+async fn copy_file(source: &Path, dest: &Path) -> Result<()> {
+    let Some(parent) = dest.parent() else {
+        return Err("Invalid object path.".into());
+    };
+    if let Err(e) = fs::create_dir_all(parent).await {
+        return Err(format!("Failed to create object directory: {}", e).into());
+    }
+
+    match fs::copy(source, dest).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to copy object into local storage: {}", e).into()),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn read_bucket(&self, name: &str) -> Result<String> {
+        // Buckets are just directories, create them on demand
+        if let Err(e) = fs::create_dir_all(self.root.join(name)).await {
+            return Err(format!("Failed to create bucket directory: {}", e).into());
+        }
+        Ok(name.to_string())
+    }
+
+    async fn upload_object(
+        &self,
+        bucket: &str,
+        path: &str,
+        _content_type: &str,
+        source: &Path,
+    ) -> Result<()> {
+        copy_file(source, &self.object_path(bucket, path)).await
+    }
+
+    async fn copy_object(
+        &self,
+        source_bucket: &str,
+        source_path: &str,
+        dest_bucket: &str,
+        dest_path: &str,
+    ) -> Result<()> {
+        let source = self.object_path(source_bucket, source_path);
+        if !source.is_file() {
+            return Err(Error::ValidationError("Object not found".to_string()));
+        }
+        copy_file(&source, &self.object_path(dest_bucket, dest_path)).await
+    }
+
+    async fn delete_object(&self, bucket: &str, path: &str) -> Result<()> {
+        match fs::remove_file(self.object_path(bucket, path)).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Err(Error::ValidationError("Object not found".to_string()))
+            }
+            Err(e) => Err(format!("Failed to delete object from local storage: {}", e).into()),
+        }
+    }
+
+    async fn object_exists(&self, bucket: &str, path: &str) -> Result<bool> {
+        Ok(self.object_path(bucket, path).is_file())
+    }
+
+    async fn object_size(&self, bucket: &str, path: &str) -> Result<Option<u64>> {
+        match fs::metadata(self.object_path(bucket, path)).await {
+            Ok(meta) if meta.is_file() => Ok(Some(meta.len())),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read object from local storage: {}", e).into()),
+        }
+    }
+
+    /// Prefixes are expected to end at a directory, ie: `dir/`
+    async fn delete_prefix(&self, bucket: &str, prefix: &str) -> Result<()> {
+        match fs::remove_dir_all(self.object_path(bucket, prefix)).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete objects from local storage: {}", e).into()),
+        }
+    }
+
+    async fn download_object(&self, bucket: &str, path: &str, dest: &Path) -> Result<()> {
+        let source = self.object_path(bucket, path);
+        if !source.is_file() {
+            return Err(Error::ValidationError("Object not found".to_string()));
+        }
+        copy_file(&source, dest).await
+    }
+
+    async fn signed_url(&self, bucket: &str, path: &str) -> Result<String> {
+        let expires = chrono::Utc::now().timestamp() + SIGNED_URL_EXPIRY;
+        create_signed_url(&self.base_url, &self.secret, "GET", bucket, path, expires)
+    }
+
+    /// Uploads go to the objects endpoint which accepts a PUT with this signature,
+    /// it enforces the global file size limit by itself
+    async fn signed_upload_url(
+        &self,
+        bucket: &str,
+        path: &str,
+        _max_size: u64,
+    ) -> Result<SignedUpload> {
+        let expires = chrono::Utc::now().timestamp() + UPLOAD_URL_EXPIRY;
+        let url = create_signed_url(&self.base_url, &self.secret, "PUT", bucket, path, expires)?;
+        Ok(SignedUpload {
+            url,
+            headers: BTreeMap::new(),
+        })
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        match fs::metadata(&self.root).await {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            _ => Err("Local storage directory is not accessible.".into()),
+        }
+    }
+}
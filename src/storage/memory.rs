@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::{Error, Result};
+
+use super::{SignedUpload, StorageBackend};
+
+#[derive(Debug, Clone)]
+pub struct MemoryObject {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Keeps objects in memory, only compiled for tests
+#[derive(Default)]
+pub struct MemoryBackend {
+    objects: Mutex<HashMap<String, MemoryObject>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_object(&self, bucket: &str, path: &str) -> Option<MemoryObject> {
+        let objects = self.objects.lock().expect("Memory storage lock poisoned");
+        objects.get(&object_key(bucket, path)).cloned()
+    }
+
+    /// Stores the object as if a client uploaded it through a signed url
+    pub fn put_object(&self, bucket: &str, path: &str, content_type: &str, data: &[u8]) {
+        let mut objects = self.objects.lock().expect("Memory storage lock poisoned");
+        objects.insert(
+            object_key(bucket, path),
+            MemoryObject {
+                content_type: content_type.to_string(),
+                data: data.to_vec(),
+            },
+        );
+    }
+}
+
+fn object_key(bucket: &str, path: &str) -> String {
+    format!("{}/{}", bucket, path)
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn read_bucket(&self, name: &str) -> Result<String> {
+        Ok(name.to_string())
+    }
+
+    async fn upload_object(
+        &self,
+        bucket: &str,
+        path: &str,
+        content_type: &str,
+        source: &Path,
+    ) -> Result<()> {
+        let Ok(data) = tokio::fs::read(source).await else {
+            return Err("Failed to read file for upload.".into());
+        };
+
+        let mut objects = self.objects.lock().expect("Memory storage lock poisoned");
+        objects.insert(
+            object_key(bucket, path),
+            MemoryObject {
+                content_type: content_type.to_string(),
+                data,
+            },
+        );
+        Ok(())
+    }
+
+    async fn copy_object(
+        &self,
+        source_bucket: &str,
+        source_path: &str,
+        dest_bucket: &str,
+        dest_path: &str,
+    ) -> Result<()> {
+        let mut objects = self.objects.lock().expect("Memory storage lock poisoned");
+        let Some(object) = objects
+            .get(&object_key(source_bucket, source_path))
+            .cloned()
+        else {
+            return Err(Error::ValidationError("Object not found".to_string()));
+        };
+        objects.insert(object_key(dest_bucket, dest_path), object);
+        Ok(())
+    }
+
+    async fn delete_object(&self, bucket: &str, path: &str) -> Result<()> {
+        let mut objects = self.objects.lock().expect("Memory storage lock poisoned");
+        match objects.remove(&object_key(bucket, path)) {
+            Some(_) => Ok(()),
+            None => Err(Error::ValidationError("Object not found".to_string())),
+        }
+    }
+
+    async fn object_exists(&self, bucket: &str, path: &str) -> Result<bool> {
+        let objects = self.objects.lock().expect("Memory storage lock poisoned");
+        Ok(objects.contains_key(&object_key(bucket, path)))
+    }
+
+    async fn object_size(&self, bucket: &str, path: &str) -> Result<Option<u64>> {
+        let objects = self.objects.lock().expect("Memory storage lock poisoned");
+        let object = objects.get(&object_key(bucket, path));
+        Ok(object.map(|object| object.data.len() as u64))
+    }
+
+    async fn delete_prefix(&self, bucket: &str, prefix: &str) -> Result<()> {
+        let mut objects = self.objects.lock().expect("Memory storage lock poisoned");
+        let key_prefix = object_key(bucket, prefix);
+        objects.retain(|key, _| !key.starts_with(&key_prefix));
+        Ok(())
+    }
+
+    async fn download_object(&self, bucket: &str, path: &str, dest: &Path) -> Result<()> {
+        let data = {
+            let objects = self.objects.lock().expect("Memory storage lock poisoned");
+            match objects.get(&object_key(bucket, path)) {
+                Some(object) => object.data.clone(),
+                None => return Err(Error::ValidationError("Object not found".to_string())),
+            }
+        };
+
+        match tokio::fs::write(dest, data).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Failed to write downloaded object.".into()),
+        }
+    }
+
+    async fn signed_url(&self, bucket: &str, path: &str) -> Result<String> {
+        Ok(format!("memory://{}", object_key(bucket, path)))
+    }
+
+    async fn signed_upload_url(
+        &self,
+        bucket: &str,
+        path: &str,
+        _max_size: u64,
+    ) -> Result<SignedUpload> {
+        Ok(SignedUpload {
+            url: format!("memory://{}", object_key(bucket, path)),
+            headers: BTreeMap::new(),
+        })
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,3 +1,15 @@
+mod backend;
 mod client;
+mod gcs;
+mod local;
+#[cfg(test)]
+mod memory;
+mod signed_url;
 
+pub use backend::*;
 pub use client::*;
+pub use gcs::*;
+pub use local::*;
+#[cfg(test)]
+pub use memory::*;
+pub use signed_url::*;
@@ -0,0 +1,245 @@
+use hmac::{Hmac, Mac};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use sha2::Sha256;
+
+use crate::{Error, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Duration in seconds, same as the cloud storage signed urls
+pub const SIGNED_URL_EXPIRY: i64 = 3600 * 12;
+
+// Upload urls are short lived, the client is expected to upload right away
+pub const UPLOAD_URL_EXPIRY: i64 = 3600;
+
+// Transform urls are signed separately from download urls of the same object
+pub const TRANSFORM_METHOD: &str = "TRANSFORM";
+
+// Unreserved characters of RFC 3986 are kept as is in url paths
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Creates a url to the objects endpoint signed with the given secret
+pub fn create_signed_url(
+    base_url: &str,
+    secret: &str,
+    method: &str,
+    bucket: &str,
+    path: &str,
+    expires: i64,
+) -> Result<String> {
+    let signature = sign_object(secret, method, bucket, path, expires)?;
+    Ok(format!(
+        "{}/v1/objects/{}/{}?expires={}&signature={}",
+        base_url.trim_end_matches('/'),
+        encode_path(bucket),
+        encode_path(path),
+        expires,
+        signature
+    ))
+}
+
+/// Creates a url template to the transform endpoint for the original image,
+/// clients fill in the `{width}` and `{height}` placeholders
+pub fn create_transform_url(
+    base_url: &str,
+    secret: &str,
+    bucket: &str,
+    path: &str,
+    expires: i64,
+) -> Result<String> {
+    let signature = sign_object(secret, TRANSFORM_METHOD, bucket, path, expires)?;
+    Ok(format!(
+        "{}/v1/transform/{}/{}?expires={}&signature={}&width={{width}}&height={{height}}",
+        base_url.trim_end_matches('/'),
+        encode_path(bucket),
+        encode_path(path),
+        expires,
+        signature
+    ))
+}
+
+/// Verifies that the signature matches and the url is not yet expired
+pub fn verify_signed_object(
+    secret: &str,
+    method: &str,
+    bucket: &str,
+    path: &str,
+    expires: i64,
+    signature: &str,
+) -> Result<()> {
+    if expires < chrono::Utc::now().timestamp() {
+        return Err(Error::Forbidden("Signed url expired".to_string()));
+    }
+
+    let Ok(signature) = hex::decode(signature) else {
+        return Err(Error::Forbidden("Invalid signature".to_string()));
+    };
+
+    let mut mac = new_mac(secret)?;
+    mac.update(signed_payload(method, bucket, path, expires).as_bytes());
+    match mac.verify_slice(&signature) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::Forbidden("Invalid signature".to_string())),
+    }
+}
+
+/// Percent-encodes each segment of the object path, the signature covers
+/// the decoded path
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn sign_object(
+    secret: &str,
+    method: &str,
+    bucket: &str,
+    path: &str,
+    expires: i64,
+) -> Result<String> {
+    let mut mac = new_mac(secret)?;
+    mac.update(signed_payload(method, bucket, path, expires).as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+fn new_mac(secret: &str) -> Result<HmacSha256> {
+    match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => Ok(mac),
+        Err(_) => Err("Invalid url signing secret".into()),
+    }
+}
+
+fn signed_payload(method: &str, bucket: &str, path: &str, expires: i64) -> String {
+    format!("{}\n{}/{}\n{}", method, bucket, path, expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_url() {
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let url = create_signed_url(
+            "http://localhost:42000/",
+            "secret",
+            "GET",
+            "photos",
+            "album/orig/abc-photo.jpg",
+            expires,
+        )
+        .unwrap();
+        assert!(
+            url.starts_with("http://localhost:42000/v1/objects/photos/album/orig/abc-photo.jpg?")
+        );
+
+        let signature = url.split("signature=").last().unwrap();
+        let result = verify_signed_object(
+            "secret",
+            "GET",
+            "photos",
+            "album/orig/abc-photo.jpg",
+            expires,
+            signature,
+        );
+        assert!(result.is_ok());
+
+        // Tampered path
+        let result = verify_signed_object(
+            "secret",
+            "GET",
+            "photos",
+            "album/orig/other.jpg",
+            expires,
+            signature,
+        );
+        assert!(result.is_err());
+
+        // Wrong secret
+        let result = verify_signed_object(
+            "other",
+            "GET",
+            "photos",
+            "album/orig/abc-photo.jpg",
+            expires,
+            signature,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_signed_url_encoding() {
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let path = "album/orig/abc-my photo?#100%.jpg";
+        let url = create_signed_url(
+            "http://localhost:42000",
+            "secret",
+            "GET",
+            "photos",
+            path,
+            expires,
+        )
+        .unwrap();
+        assert!(url.starts_with(
+            "http://localhost:42000/v1/objects/photos/album/orig/abc-my%20photo%3F%23100%25.jpg?"
+        ));
+        assert_eq!(url.matches('?').count(), 1);
+        assert!(!url.contains('#'));
+
+        let signature = url.split("signature=").last().unwrap();
+        let result = verify_signed_object("secret", "GET", "photos", path, expires, signature);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_transform_url() {
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let path = "album/orig/abc-photo.jpg";
+        let url = create_transform_url("http://localhost:42000", "secret", "photos", path, expires)
+            .unwrap();
+        assert!(url.starts_with("http://localhost:42000/v1/transform/photos/album/orig/"));
+        assert!(url.ends_with("&width={width}&height={height}"));
+
+        let signature = url
+            .split("signature=")
+            .last()
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+        let result = verify_signed_object(
+            "secret",
+            TRANSFORM_METHOD,
+            "photos",
+            path,
+            expires,
+            signature,
+        );
+        assert!(result.is_ok());
+
+        // Not usable to download the original
+        let result = verify_signed_object("secret", "GET", "photos", path, expires, signature);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_expired_signed_url() {
+        let expires = chrono::Utc::now().timestamp() - 60;
+        let signature = sign_object("secret", "GET", "photos", "a/orig/b.jpg", expires).unwrap();
+        let result = verify_signed_object(
+            "secret",
+            "GET",
+            "photos",
+            "a/orig/b.jpg",
+            expires,
+            &signature,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,7 @@
+mod models;
+mod purge;
+mod queries;
+
+pub use models::*;
+pub use purge::*;
+pub use queries::*;
@@ -0,0 +1,11 @@
+use serde::Serialize;
+
+use crate::dirs::Dir;
+use crate::files::FileDto;
+
+/// Dirs and files of a bucket that are in the trash
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashListing {
+    pub dirs: Vec<Dir>,
+    pub files: Vec<FileDto>,
+}
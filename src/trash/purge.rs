@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use deadpool_diesel::sqlite::Pool;
+use tracing::{error, info};
+
+use crate::Result;
+use crate::buckets::{BucketDto, get_bucket};
+use crate::dirs::{Dir, delete_dir_recursive};
+use crate::files::{FileObject, delete_file, release_file_objects};
+use crate::storage::StorageBackend;
+
+use super::{list_expired_dirs, list_expired_files};
+
+const PURGE_BATCH_SIZE: i64 = 50;
+const PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Permanently deletes a trashed file record then its stored objects, which
+/// are kept when shared with linked files
+pub async fn purge_file(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket: &BucketDto,
+    dir: &Dir,
+    file: &FileObject,
+) -> Result<()> {
+    let deleted = delete_file(db_pool, &file.id).await?;
+
+    let file = deleted.as_ref().unwrap_or(file);
+    release_file_objects(db_pool, storage_client, &bucket.name, &dir.name, file).await
+}
+
+/// Permanently deletes trash entries that were deleted before the cutoff.
+/// Stops at the first batch with failures and leaves them for the next run.
+pub async fn purge_expired_trash(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    cutoff: i64,
+) -> Result<()> {
+    loop {
+        let files = list_expired_files(db_pool, cutoff, PURGE_BATCH_SIZE).await?;
+        if files.is_empty() {
+            break;
+        }
+
+        let mut failed = false;
+        for (file, dir) in files.iter() {
+            let Some(bucket) = get_bucket(db_pool, &dir.bucket_id).await? else {
+                failed = true;
+                continue;
+            };
+            if let Err(e) = purge_file(db_pool, storage_client, &bucket, dir, file).await {
+                error!("Purge file {}: {}", file.id, e);
+                failed = true;
+            }
+        }
+        if failed {
+            break;
+        }
+    }
+
+    loop {
+        let dirs = list_expired_dirs(db_pool, cutoff, PURGE_BATCH_SIZE).await?;
+        if dirs.is_empty() {
+            break;
+        }
+
+        let mut failed = false;
+        for dir in dirs.iter() {
+            let Some(bucket) = get_bucket(db_pool, &dir.bucket_id).await? else {
+                failed = true;
+                continue;
+            };
+            let res = delete_dir_recursive(db_pool, storage_client, &bucket, dir).await?;
+            if !res.dir_deleted || !res.failed.is_empty() {
+                error!("Purge directory {}: {} failures", dir.id, res.failed.len());
+                failed = true;
+            }
+        }
+        if failed {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Periodically purges trash entries older than the retention period
+pub fn spawn_trash_purge(
+    db_pool: Pool,
+    storage_client: Arc<dyn StorageBackend>,
+    retention_days: u32,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(PURGE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+
+            let retention = i64::from(retention_days) * 24 * 60 * 60;
+            let cutoff = chrono::Utc::now().timestamp() - retention;
+            info!("Purging trash deleted before {}", cutoff);
+            if let Err(e) = purge_expired_trash(&db_pool, storage_client.as_ref(), cutoff).await {
+                error!("Purge trash: {}", e);
+            }
+        }
+    });
+}
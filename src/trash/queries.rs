@@ -0,0 +1,474 @@
+use deadpool_diesel::sqlite::Pool;
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use diesel::{QueryDsl, SelectableHelper};
+use tracing::error;
+
+use crate::dirs::{Dir, get_dir, update_bucket_stats, update_dir_stats};
+use crate::files::{FileObject, MAX_FILES, find_dir_file};
+use crate::schema::dirs;
+use crate::schema::files;
+use crate::util::truncate_string;
+use crate::{Error, Result};
+
+use super::TrashListing;
+
+const MAX_TRASH_ITEMS: i64 = 1000;
+
+/// Lists dirs and files of the bucket that are in the trash, latest first
+pub async fn list_trash(db_pool: &Pool, bucket_id: &str) -> Result<TrashListing> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            let trashed_dirs = dirs::table
+                .filter(dirs::bucket_id.eq(bid.as_str()))
+                .filter(dirs::deleted_at.is_not_null())
+                .order(dirs::deleted_at.desc())
+                .limit(MAX_TRASH_ITEMS)
+                .select(Dir::as_select())
+                .load::<Dir>(conn)?;
+
+            let bucket_dirs = dirs::table
+                .filter(dirs::bucket_id.eq(bid.as_str()))
+                .select(dirs::id);
+
+            let trashed_files = files::table
+                .filter(files::dir_id.eq_any(bucket_dirs))
+                .filter(files::deleted_at.is_not_null())
+                .order(files::deleted_at.desc())
+                .limit(MAX_TRASH_ITEMS)
+                .select(FileObject::as_select())
+                .load::<FileObject>(conn)?;
+
+            QueryResult::Ok((trashed_dirs, trashed_files))
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok((dirs, files)) => Ok(TrashListing {
+                dirs,
+                files: files.into_iter().map(|x| x.into()).collect(),
+            }),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing trash".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Finds a trashed file of the bucket together with its dir, which may be
+/// in the trash as well
+pub async fn get_trashed_file(
+    db_pool: &Pool,
+    bucket_id: &str,
+    id: &str,
+) -> Result<Option<(FileObject, Dir)>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let fid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            let file = files::table
+                .find(fid)
+                .filter(files::deleted_at.is_not_null())
+                .select(FileObject::as_select())
+                .first::<FileObject>(conn)
+                .optional()?;
+
+            let Some(file) = file else {
+                return QueryResult::Ok(None);
+            };
+
+            let dir = dirs::table
+                .find(file.dir_id.as_str())
+                .filter(dirs::bucket_id.eq(bid.as_str()))
+                .select(Dir::as_select())
+                .first::<Dir>(conn)
+                .optional()?;
+
+            Ok(dir.map(|dir| (file, dir)))
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding file".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn get_trashed_dir(db_pool: &Pool, bucket_id: &str, id: &str) -> Result<Option<Dir>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let bid = bucket_id.to_string();
+    let did = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            dirs::table
+                .find(did)
+                .filter(dirs::bucket_id.eq(bid.as_str()))
+                .filter(dirs::deleted_at.is_not_null())
+                .select(Dir::as_select())
+                .first::<Dir>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding directory".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Moves the file into the trash, its objects are kept until purged
+pub async fn trash_file(db_pool: &Pool, id: &str) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let fid = id.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let file = files::table
+                    .find(&fid)
+                    .filter(files::deleted_at.is_null())
+                    .select(FileObject::as_select())
+                    .first::<FileObject>(conn)
+                    .optional()?;
+
+                if let Some(file) = file {
+                    diesel::update(files::table.find(&fid))
+                        .set(files::deleted_at.eq(today))
+                        .execute(conn)?;
+                    update_dir_stats(conn, &file.dir_id, -1, -file.size)?;
+                }
+                QueryResult::Ok(())
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{e}");
+                Err("Error deleting file".into())
+            }
+        },
+        Err(e) => {
+            error!("{e}");
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Restores a trashed file into its dir, which must not be in the trash
+pub async fn restore_file(db_pool: &Pool, file: &FileObject) -> Result<()> {
+    if get_dir(db_pool, &file.dir_id).await?.is_none() {
+        return Err(Error::Conflict(
+            "Restore the directory of the file first".to_string(),
+        ));
+    }
+
+    if find_dir_file(db_pool, &file.dir_id, &file.name)
+        .await?
+        .is_some()
+    {
+        let short_name = truncate_string(&file.name, 20);
+        return Err(Error::Conflict(format!("{} already exists", short_name)));
+    }
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let fid = file.id.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let file = files::table
+                    .find(&fid)
+                    .filter(files::deleted_at.is_not_null())
+                    .select(FileObject::as_select())
+                    .first::<FileObject>(conn)
+                    .optional()?;
+
+                if let Some(file) = file {
+                    // Trashed files don't count, the dir may have filled up since
+                    let count = files::table
+                        .filter(files::dir_id.eq(&file.dir_id))
+                        .filter(files::deleted_at.is_null())
+                        .select(count_star())
+                        .get_result::<i64>(conn)?;
+                    if count >= MAX_FILES as i64 {
+                        return Ok(false);
+                    }
+
+                    diesel::update(files::table.find(&fid))
+                        .set(files::deleted_at.eq(None::<i64>))
+                        .execute(conn)?;
+                    update_dir_stats(conn, &file.dir_id, 1, file.size)?;
+                }
+                QueryResult::Ok(true)
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::Conflict(
+                "Maximum number of files reached".to_string(),
+            )),
+            Err(e) => {
+                error!("{e}");
+                Err("Error restoring file".into())
+            }
+        },
+        Err(e) => {
+            error!("{e}");
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Moves the dir into the trash along with the files inside it
+pub async fn trash_dir(db_pool: &Pool, id: &str) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let did = id.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let dir = dirs::table
+                    .find(&did)
+                    .filter(dirs::deleted_at.is_null())
+                    .select(Dir::as_select())
+                    .first::<Dir>(conn)
+                    .optional()?;
+
+                if let Some(dir) = dir {
+                    diesel::update(dirs::table.find(&did))
+                        .set(dirs::deleted_at.eq(today))
+                        .execute(conn)?;
+                    update_bucket_stats(conn, &dir.bucket_id, -dir.file_count, -dir.total_size)?;
+                }
+                QueryResult::Ok(())
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{e}");
+                Err("Error deleting directory".into())
+            }
+        },
+        Err(e) => {
+            error!("{e}");
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Restores a trashed dir, its name and label must still be available
+pub async fn restore_dir(db_pool: &Pool, dir: &Dir) -> Result<()> {
+    if count_conflicting_dirs(db_pool, dir).await? > 0 {
+        return Err(Error::Conflict(
+            "Directory name or label already exists".to_string(),
+        ));
+    }
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let did = dir.id.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let dir = dirs::table
+                    .find(&did)
+                    .filter(dirs::deleted_at.is_not_null())
+                    .select(Dir::as_select())
+                    .first::<Dir>(conn)
+                    .optional()?;
+
+                if let Some(dir) = dir {
+                    diesel::update(dirs::table.find(&did))
+                        .set(dirs::deleted_at.eq(None::<i64>))
+                        .execute(conn)?;
+                    update_bucket_stats(conn, &dir.bucket_id, dir.file_count, dir.total_size)?;
+                }
+                QueryResult::Ok(())
+            })
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{e}");
+                Err("Error restoring directory".into())
+            }
+        },
+        Err(e) => {
+            error!("{e}");
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+async fn count_conflicting_dirs(db_pool: &Pool, dir: &Dir) -> Result<i64> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let dir_copy = dir.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            dirs::table
+                .filter(dirs::bucket_id.eq(dir_copy.bucket_id.as_str()))
+                .filter(dirs::deleted_at.is_null())
+                .filter(
+                    dirs::name
+                        .eq(dir_copy.name.as_str())
+                        .or(dirs::label.eq(dir_copy.label.as_str())),
+                )
+                .select(count_star())
+                .get_result::<i64>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(count_res) => match count_res {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error counting directories".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Lists trashed files deleted before the cutoff together with their dirs
+pub async fn list_expired_files(
+    db_pool: &Pool,
+    cutoff: i64,
+    limit: i64,
+) -> Result<Vec<(FileObject, Dir)>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            let expired = files::table
+                .filter(files::deleted_at.lt(cutoff))
+                .order(files::deleted_at.asc())
+                .limit(limit)
+                .select(FileObject::as_select())
+                .load::<FileObject>(conn)?;
+
+            let mut items: Vec<(FileObject, Dir)> = Vec::with_capacity(expired.len());
+            for file in expired.into_iter() {
+                let dir = dirs::table
+                    .find(file.dir_id.as_str())
+                    .select(Dir::as_select())
+                    .first::<Dir>(conn)?;
+                items.push((file, dir));
+            }
+            QueryResult::Ok(items)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing expired files".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Lists trashed dirs deleted before the cutoff
+pub async fn list_expired_dirs(db_pool: &Pool, cutoff: i64, limit: i64) -> Result<Vec<Dir>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            dirs::table
+                .filter(dirs::deleted_at.lt(cutoff))
+                .order(dirs::deleted_at.asc())
+                .limit(limit)
+                .select(Dir::as_select())
+                .load::<Dir>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing expired directories".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
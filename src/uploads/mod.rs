@@ -0,0 +1,9 @@
+mod models;
+mod pending;
+mod queries;
+mod sessions;
+
+pub use models::*;
+pub use pending::*;
+pub use queries::*;
+pub use sessions::*;
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// File uploaded in chunks across several requests
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = crate::schema::upload_sessions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UploadSession {
+    pub id: String,
+    pub bucket_id: String,
+    pub dir_id: String,
+    pub name: String,
+    pub size: i64,
+
+    // Bytes stored so far, the offset of the next chunk
+    pub received: i64,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct NewUploadSession {
+    #[validate(length(min = 1, max = 250))]
+    pub name: String,
+
+    #[validate(range(min = 1))]
+    pub size: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadChunkParams {
+    pub offset: i64,
+}
+
+/// File uploaded by the client straight to the storage through a signed url,
+/// waiting to be finalized
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = crate::schema::pending_uploads)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct PendingUpload {
+    pub id: String,
+    pub bucket_id: String,
+    pub dir_id: String,
+    pub name: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct NewPendingUpload {
+    #[validate(length(min = 1, max = 250))]
+    pub name: String,
+}
+
+/// Where and until when the client may upload the file, the upload request
+/// must carry `upload_headers`
+#[derive(Debug, Clone, Serialize)]
+pub struct PresignedUpload {
+    pub id: String,
+    pub name: String,
+    pub upload_url: String,
+    pub upload_headers: BTreeMap<String, String>,
+    pub expires_at: i64,
+}
@@ -0,0 +1,178 @@
+use deadpool_diesel::sqlite::Pool;
+use tokio::fs::{self, create_dir_all};
+use tracing::error;
+
+use crate::buckets::{BucketDto, get_bucket};
+use crate::config::Config;
+use crate::dirs::Dir;
+use crate::files::{FileDto, FilePayload, ImgVersion, create_file};
+use crate::storage::{StorageBackend, UPLOAD_URL_EXPIRY};
+use crate::util::slugify_prefixed;
+use crate::{Error, Result};
+
+use super::{
+    NewPendingUpload, PendingUpload, PresignedUpload, create_pending_upload, delete_pending_upload,
+    list_expired_pending_uploads,
+};
+
+// Bucket prefix where clients upload before the file is finalized,
+// dir names are sluggable so it never clashes with a dir
+const PENDING_PATH: &str = "_pending";
+
+/// Object path of the pending upload inside the bucket
+pub fn pending_object_path(id: &str) -> String {
+    format!("{}/{}", PENDING_PATH, id)
+}
+
+/// Reserves the file name and returns a signed url to upload the file to
+pub async fn presign_upload(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket: &BucketDto,
+    dir: &Dir,
+    data: &NewPendingUpload,
+    max_size: u64,
+) -> Result<PresignedUpload> {
+    let pending = create_pending_upload(db_pool, bucket, dir, data).await?;
+    let expires_at = chrono::Utc::now().timestamp() + UPLOAD_URL_EXPIRY;
+    let path = pending_object_path(&pending.id);
+    let signed = match storage_client
+        .signed_upload_url(&bucket.name, &path, max_size)
+        .await
+    {
+        Ok(signed) => signed,
+        Err(e) => {
+            delete_pending_upload(db_pool, &pending.id).await?;
+            return Err(e);
+        }
+    };
+
+    Ok(PresignedUpload {
+        id: pending.id,
+        name: pending.name,
+        upload_url: signed.url,
+        upload_headers: signed.headers,
+        expires_at,
+    })
+}
+
+/// Pulls the uploaded object and runs it through the regular upload pipeline.
+/// The pending upload is kept when the file is rejected so it can be finalized
+/// again, except for objects over the size limit.
+pub async fn finalize_pending_upload(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    config: &Config,
+    bucket: &BucketDto,
+    dir: &Dir,
+    pending: &PendingUpload,
+) -> Result<FileDto> {
+    let upload_dir = &config.upload_dir;
+    let max_size = bucket.upload_limit(config.upload.max_file_size);
+    let path = pending_object_path(&pending.id);
+
+    // Size is known from the storage, oversized objects are never downloaded
+    let Some(size) = storage_client.object_size(&bucket.name, &path).await? else {
+        return Err(Error::BadRequest("File has not been uploaded".to_string()));
+    };
+    if size > max_size {
+        discard_pending_upload(db_pool, storage_client, &bucket.name, pending).await?;
+        return Err(Error::PayloadTooLarge(format!(
+            "File exceeds the maximum size of {} bytes",
+            max_size
+        )));
+    }
+
+    let orig_dir = upload_dir.join(ImgVersion::Original.to_string());
+    if create_dir_all(&orig_dir).await.is_err() {
+        return Err("Unable to create upload dir".into());
+    }
+
+    let filename = slugify_prefixed(&pending.name);
+    let file_path = orig_dir.join(&filename);
+    if let Err(e) = storage_client
+        .download_object(&bucket.name, &path, &file_path)
+        .await
+    {
+        let _ = fs::remove_file(&file_path).await;
+        return match e {
+            Error::ValidationError(_) => {
+                Err(Error::BadRequest("File has not been uploaded".to_string()))
+            }
+            e => Err(e),
+        };
+    }
+
+    // Object may have been replaced since its size was read
+    let Ok(meta) = fs::metadata(&file_path).await else {
+        return Err("Unable to read uploaded file".into());
+    };
+    let size = meta.len();
+    if size > max_size {
+        let _ = fs::remove_file(&file_path).await;
+        discard_pending_upload(db_pool, storage_client, &bucket.name, pending).await?;
+        return Err(Error::PayloadTooLarge(format!(
+            "File exceeds the maximum size of {} bytes",
+            max_size
+        )));
+    }
+
+    let payload = FilePayload {
+        upload_dir: upload_dir.to_path_buf(),
+        name: pending.name.clone(),
+        filename,
+        path: file_path,
+        size: size as i64,
+        checksum: None,
+    };
+    let file_dto = create_file(
+        db_pool,
+        storage_client,
+        bucket,
+        dir,
+        &payload,
+        &config.image,
+    )
+    .await?;
+
+    discard_pending_upload(db_pool, storage_client, &bucket.name, pending).await?;
+    Ok(file_dto)
+}
+
+/// Forgets the pending upload and removes the object if it was uploaded
+pub async fn discard_pending_upload(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    bucket_name: &str,
+    pending: &PendingUpload,
+) -> Result<()> {
+    delete_pending_upload(db_pool, &pending.id).await?;
+
+    // Not found simply means the client never uploaded it
+    let path = pending_object_path(&pending.id);
+    match storage_client.delete_object(bucket_name, &path).await {
+        Ok(_) | Err(Error::ValidationError(_)) => Ok(()),
+        Err(e) => {
+            error!("Remove pending upload {}: {}", path, e);
+            Ok(())
+        }
+    }
+}
+
+/// Discards pending uploads created before the cutoff that were never finalized
+pub async fn cleanup_expired_pending_uploads(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    cutoff: i64,
+) -> Result<usize> {
+    let items = list_expired_pending_uploads(db_pool, cutoff).await?;
+    for pending in items.iter() {
+        match get_bucket(db_pool, &pending.bucket_id).await? {
+            Some(bucket) => {
+                discard_pending_upload(db_pool, storage_client, &bucket.name, pending).await?
+            }
+            None => delete_pending_upload(db_pool, &pending.id).await?,
+        }
+    }
+    Ok(items.len())
+}
@@ -0,0 +1,338 @@
+use deadpool_diesel::sqlite::Pool;
+
+use diesel::prelude::*;
+use diesel::{QueryDsl, SelectableHelper};
+use tracing::error;
+use validator::Validate;
+
+use crate::buckets::BucketDto;
+use crate::dirs::Dir;
+use crate::files::ensure_file_slot;
+use crate::schema::pending_uploads;
+use crate::schema::upload_sessions::{self, dsl};
+use crate::util::generate_id;
+use crate::validators::flatten_errors;
+use crate::{Error, Result};
+
+use super::{NewPendingUpload, NewUploadSession, PendingUpload, UploadSession};
+
+pub async fn create_upload_session(
+    db_pool: &Pool,
+    bucket: &BucketDto,
+    dir: &Dir,
+    data: &NewUploadSession,
+    max_size: u64,
+) -> Result<UploadSession> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+    if data.size as u64 > max_size {
+        return Err(Error::PayloadTooLarge(format!(
+            "File exceeds the maximum size of {} bytes",
+            max_size
+        )));
+    }
+    ensure_file_slot(db_pool, &dir.id, &data.name).await?;
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let today = chrono::Utc::now().timestamp();
+    let session = UploadSession {
+        id: generate_id(),
+        bucket_id: bucket.id.clone(),
+        dir_id: dir.id.clone(),
+        name: data.name.clone(),
+        size: data.size,
+        received: 0,
+        created_at: today,
+        updated_at: today,
+    };
+
+    let session_copy = session.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::insert_into(upload_sessions::table)
+                .values(&session_copy)
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(insert_res) => match insert_res {
+            Ok(_) => Ok(session),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error creating upload session".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn get_upload_session(db_pool: &Pool, id: &str) -> Result<Option<UploadSession>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let sid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::upload_sessions
+                .find(sid)
+                .select(UploadSession::as_select())
+                .first::<UploadSession>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding upload session".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Records the bytes stored so far, which also extends the session lifetime.
+/// Returns false when the progress no longer matches `from`.
+pub async fn update_upload_session_received(
+    db_pool: &Pool,
+    id: &str,
+    from: i64,
+    received: i64,
+) -> Result<bool> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let sid = id.to_string();
+    let today = chrono::Utc::now().timestamp();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::update(dsl::upload_sessions.find(sid))
+                .filter(dsl::received.eq(from))
+                .set((dsl::received.eq(received), dsl::updated_at.eq(today)))
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(update_res) => match update_res {
+            Ok(count) => Ok(count > 0),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error updating upload session".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn delete_upload_session(db_pool: &Pool, id: &str) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let sid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| diesel::delete(dsl::upload_sessions.find(sid)).execute(conn))
+        .await;
+
+    match conn_result {
+        Ok(delete_res) => match delete_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error deleting upload session".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Lists sessions that received nothing since the cutoff
+pub async fn list_expired_upload_sessions(
+    db_pool: &Pool,
+    cutoff: i64,
+) -> Result<Vec<UploadSession>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            dsl::upload_sessions
+                .filter(dsl::updated_at.lt(cutoff))
+                .select(UploadSession::as_select())
+                .load::<UploadSession>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing upload sessions".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn create_pending_upload(
+    db_pool: &Pool,
+    bucket: &BucketDto,
+    dir: &Dir,
+    data: &NewPendingUpload,
+) -> Result<PendingUpload> {
+    if let Err(errors) = data.validate() {
+        return Err(Error::ValidationError(flatten_errors(&errors)));
+    }
+    ensure_file_slot(db_pool, &dir.id, &data.name).await?;
+
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let pending = PendingUpload {
+        id: generate_id(),
+        bucket_id: bucket.id.clone(),
+        dir_id: dir.id.clone(),
+        name: data.name.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+
+    let pending_copy = pending.clone();
+    let conn_result = db
+        .interact(move |conn| {
+            diesel::insert_into(pending_uploads::table)
+                .values(&pending_copy)
+                .execute(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(insert_res) => match insert_res {
+            Ok(_) => Ok(pending),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error creating pending upload".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn get_pending_upload(db_pool: &Pool, id: &str) -> Result<Option<PendingUpload>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let pid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| {
+            pending_uploads::table
+                .find(pid)
+                .select(PendingUpload::as_select())
+                .first::<PendingUpload>(conn)
+                .optional()
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error finding pending upload".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+pub async fn delete_pending_upload(db_pool: &Pool, id: &str) -> Result<()> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let pid = id.to_string();
+    let conn_result = db
+        .interact(move |conn| diesel::delete(pending_uploads::table.find(pid)).execute(conn))
+        .await;
+
+    match conn_result {
+        Ok(delete_res) => match delete_res {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error deleting pending upload".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
+
+/// Lists pending uploads created before the cutoff that were never finalized
+pub async fn list_expired_pending_uploads(
+    db_pool: &Pool,
+    cutoff: i64,
+) -> Result<Vec<PendingUpload>> {
+    let Ok(db) = db_pool.get().await else {
+        return Err("Error getting db connection".into());
+    };
+
+    let conn_result = db
+        .interact(move |conn| {
+            pending_uploads::table
+                .filter(pending_uploads::created_at.lt(cutoff))
+                .select(PendingUpload::as_select())
+                .load::<PendingUpload>(conn)
+        })
+        .await;
+
+    match conn_result {
+        Ok(select_res) => match select_res {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                error!("{}", e);
+                Err("Error listing pending uploads".into())
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            Err("Error using the db connection".into())
+        }
+    }
+}
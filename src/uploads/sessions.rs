@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use axum::body::Bytes;
+use deadpool_diesel::sqlite::Pool;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, OpenOptions, create_dir_all};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+use crate::buckets::BucketDto;
+use crate::config::ImageConfig;
+use crate::dirs::Dir;
+use crate::files::{FileDto, FilePayload, ImgVersion, create_file};
+use crate::storage::StorageBackend;
+use crate::util::slugify_prefixed;
+use crate::{Error, Result};
+
+use super::{
+    UploadSession, cleanup_expired_pending_uploads, delete_upload_session, get_upload_session,
+    list_expired_upload_sessions, update_upload_session_received,
+};
+
+const CLEANUP_INTERVAL_SECS: u64 = 60 * 60;
+
+// Sessions receiving a chunk right now, concurrent writers would interleave bytes
+static RECEIVING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Marks the session as receiving until dropped
+struct ReceivingGuard(String);
+
+impl ReceivingGuard {
+    fn acquire(id: &str) -> Option<Self> {
+        let mut receiving = RECEIVING.lock().unwrap_or_else(|e| e.into_inner());
+        receiving
+            .insert(id.to_string())
+            .then(|| Self(id.to_string()))
+    }
+}
+
+impl Drop for ReceivingGuard {
+    fn drop(&mut self) {
+        let mut receiving = RECEIVING.lock().unwrap_or_else(|e| e.into_inner());
+        receiving.remove(&self.0);
+    }
+}
+
+/// Where the received bytes of the session are kept until completed
+pub fn upload_session_path(upload_dir: &Path, id: &str) -> PathBuf {
+    upload_dir.join("tmp").join(format!("{}.part", id))
+}
+
+/// Appends the chunk at the given offset, which must match the bytes
+/// received so far. Progress is saved even when the stream breaks midway,
+/// unless a SHA-256 checksum of the whole chunk is expected. Only one chunk
+/// of a session is received at a time.
+pub async fn append_upload_chunk<S, E>(
+    db_pool: &Pool,
+    upload_dir: &Path,
+    session: &UploadSession,
+    offset: i64,
+    checksum: Option<&[u8]>,
+    mut stream: S,
+) -> Result<UploadSession>
+where
+    S: Stream<Item = core::result::Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let Some(_guard) = ReceivingGuard::acquire(&session.id) else {
+        return Err(Error::Conflict(
+            "Another chunk is being received".to_string(),
+        ));
+    };
+
+    // Progress may have moved since the session was read by the caller
+    let Some(session) = get_upload_session(db_pool, &session.id).await? else {
+        return Err(Error::NotFound("Upload not found".to_string()));
+    };
+    if offset != session.received {
+        return Err(Error::Conflict(format!(
+            "Expected offset {}",
+            session.received
+        )));
+    }
+
+    let path = upload_session_path(upload_dir, &session.id);
+    let Ok(mut file) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+    else {
+        return Err("Unable to open upload part".into());
+    };
+
+    // Drop bytes written after the last recorded progress, ie: before a crash
+    let Ok(_) = file.set_len(session.received as u64).await else {
+        return Err("Unable to write upload part".into());
+    };
+
+    let mut hasher = Sha256::new();
+    let mut received = session.received;
+    let mut failure: Option<Error> = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                failure = Some(Error::UploadAborted(format!("Upload interrupted: {}", e)));
+                break;
+            }
+        };
+
+        if received + chunk.len() as i64 > session.size {
+            failure = Some(Error::PayloadTooLarge(
+                "Chunk exceeds the declared file size".to_string(),
+            ));
+            break;
+        }
+        if let Err(e) = file.write_all(&chunk).await {
+            failure = Some(Error::from_write(e, "Unable to write upload part"));
+            break;
+        }
+        hasher.update(&chunk);
+        received += chunk.len() as i64;
+    }
+
+    if let Err(e) = file.flush().await {
+        return Err(Error::from_write(e, "Unable to write upload part"));
+    }
+
+    // Chunk is only accepted as a whole when verified by a checksum
+    if let Some(expected) = checksum
+        && (failure.is_some() || hasher.finalize().as_slice() != expected)
+    {
+        let Ok(_) = file.set_len(session.received as u64).await else {
+            return Err("Unable to write upload part".into());
+        };
+        return Err(failure.unwrap_or(Error::ChecksumMismatch));
+    }
+
+    // Partial chunks count so the client can resume from there
+    if received != session.received
+        && !update_upload_session_received(db_pool, &session.id, session.received, received).await?
+    {
+        return Err(Error::Conflict(
+            "Upload progress changed, ask for the current offset".to_string(),
+        ));
+    }
+
+    if let Some(e) = failure {
+        return Err(e);
+    }
+
+    let mut updated = session;
+    updated.received = received;
+    Ok(updated)
+}
+
+/// Turns the fully received session into a file of the dir
+pub async fn complete_upload_session(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    upload_dir: &Path,
+    bucket: &BucketDto,
+    dir: &Dir,
+    session: &UploadSession,
+    img_config: &ImageConfig,
+) -> Result<FileDto> {
+    if session.received != session.size {
+        return Err(Error::BadRequest(format!(
+            "Upload is incomplete, received {} of {} bytes",
+            session.received, session.size
+        )));
+    }
+
+    let orig_dir = upload_dir.join(ImgVersion::Original.to_string());
+    if create_dir_all(&orig_dir).await.is_err() {
+        return Err("Unable to create upload dir".into());
+    }
+
+    // Part stays in place until the file is created so a rejected upload
+    // can be completed again, ie: after the conflicting file is removed
+    let filename = slugify_prefixed(&session.name);
+    let file_path = orig_dir.join(&filename);
+    let part_path = upload_session_path(upload_dir, &session.id);
+    if fs::hard_link(&part_path, &file_path).await.is_err()
+        && fs::copy(&part_path, &file_path).await.is_err()
+    {
+        let _ = fs::remove_file(&file_path).await;
+        return Err("Unable to copy upload part".into());
+    }
+
+    let payload = FilePayload {
+        upload_dir: upload_dir.to_path_buf(),
+        name: session.name.clone(),
+        filename,
+        path: file_path,
+        size: session.size,
+        checksum: None,
+    };
+    let file_dto = create_file(db_pool, storage_client, bucket, dir, &payload, img_config).await?;
+
+    abort_upload_session(db_pool, upload_dir, session).await?;
+    Ok(file_dto)
+}
+
+/// Discards the session and the bytes received so far
+pub async fn abort_upload_session(
+    db_pool: &Pool,
+    upload_dir: &Path,
+    session: &UploadSession,
+) -> Result<()> {
+    delete_upload_session(db_pool, &session.id).await?;
+
+    let path = upload_session_path(upload_dir, &session.id);
+    if let Err(e) = fs::remove_file(&path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        error!("Remove upload part {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// Aborts sessions that received nothing since the cutoff
+pub async fn cleanup_expired_upload_sessions(
+    db_pool: &Pool,
+    upload_dir: &Path,
+    cutoff: i64,
+) -> Result<usize> {
+    let sessions = list_expired_upload_sessions(db_pool, cutoff).await?;
+    for session in sessions.iter() {
+        abort_upload_session(db_pool, upload_dir, session).await?;
+    }
+    Ok(sessions.len())
+}
+
+/// Periodically removes upload sessions idle for longer than the ttl
+/// and pending uploads that were never finalized
+pub fn spawn_upload_cleanup(
+    db_pool: Pool,
+    storage_client: Arc<dyn StorageBackend>,
+    upload_dir: PathBuf,
+    ttl_hours: u32,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+
+            let ttl = i64::from(ttl_hours) * 60 * 60;
+            let cutoff = chrono::Utc::now().timestamp() - ttl;
+            match cleanup_expired_upload_sessions(&db_pool, &upload_dir, cutoff).await {
+                Ok(0) => {}
+                Ok(count) => info!("Removed {} expired upload sessions", count),
+                Err(e) => error!("Cleanup upload sessions: {}", e),
+            }
+
+            let storage = storage_client.as_ref();
+            match cleanup_expired_pending_uploads(&db_pool, storage, cutoff).await {
+                Ok(0) => {}
+                Ok(count) => info!("Removed {} expired pending uploads", count),
+                Err(e) => error!("Cleanup pending uploads: {}", e),
+            }
+        }
+    });
+}
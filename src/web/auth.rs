@@ -47,3 +47,48 @@ pub async fn user_permissions(Extension(actor): Extension<Actor>) -> Result<Json
 pub async fn user_authz(Extension(actor): Extension<Actor>) -> Result<JsonResponse> {
     Ok(JsonResponse::new(serde_json::to_string(&actor).unwrap()))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::web::test_helpers::{TEST_PASSWORD, TEST_USERNAME, TestApp};
+
+    #[tokio::test]
+    async fn test_authenticate_handler() {
+        let app = TestApp::new().await;
+
+        let body = json!({ "username": TEST_USERNAME, "password": TEST_PASSWORD });
+        let res = app.send(Method::POST, "/v1/auth/token", Some(body)).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(!res.body["token"].as_str().unwrap().is_empty());
+        assert_eq!(res.body["user"]["username"], TEST_USERNAME);
+
+        let body = json!({ "username": TEST_USERNAME, "password": "wrong-password" });
+        let res = app.send(Method::POST, "/v1/auth/token", Some(body)).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_user_routes() {
+        let mut app = TestApp::new().await;
+
+        let res = app.send(Method::GET, "/v1/user", None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["username"], TEST_USERNAME);
+
+        let res = app.send(Method::GET, "/v1/user/permissions", None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(
+            res.body
+                .as_array()
+                .unwrap()
+                .contains(&json!("files.create"))
+        );
+
+        app.token = "invalid-token".to_string();
+        let res = app.send(Method::GET, "/v1/user", None).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    }
+}
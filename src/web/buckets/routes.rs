@@ -1,9 +1,17 @@
-use axum::{Router, middleware, routing::get};
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
 
 use crate::web::{
     dirs::dir_routes,
+    duplicates::duplicates_routes,
+    jobs::{get_job_handler, retry_job_handler},
     middlewares::{bucket_middleware, require_auth_middleware},
     server::AppState,
+    similar::similar_routes,
+    trash::trash_routes,
+    versions::versions_routes,
 };
 
 use super::handlers::{get_bucket_handler, list_buckets_handler};
@@ -22,7 +30,13 @@ pub fn buckets_routes(state: AppState) -> Router<AppState> {
 fn inner_bucket_routes(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/", get(get_bucket_handler))
+        .route("/jobs/{job_id}", get(get_job_handler))
+        .route("/jobs/{job_id}/retry", post(retry_job_handler))
         .nest("/dirs", dir_routes(state.clone()))
+        .nest("/trash", trash_routes(state.clone()))
+        .nest("/duplicates", duplicates_routes(state.clone()))
+        .nest("/similar", similar_routes(state.clone()))
+        .nest("/versions", versions_routes(state.clone()))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             bucket_middleware,
@@ -7,11 +7,15 @@ use axum::{
 use crate::{
     Error, Result,
     auth::Actor,
+    buckets::BucketDto,
     dirs::{
-        Dir, ListDirsParams, NewDir, UpdateDir, create_dir, delete_dir, get_dir, list_dirs,
-        update_dir,
+        DeleteDirParams, Dir, ListDirsParams, NewDir, UpdateDir, create_dir, ensure_dir_idle,
+        get_dir, list_dirs, schedule_dir_rename, update_dir,
     },
+    files::count_dir_files,
+    jobs::spawn_job,
     roles::Permission,
+    trash::trash_dir,
     web::{params::Params, response::JsonResponse, server::AppState},
 };
 
@@ -59,6 +63,7 @@ pub async fn get_dir_handler(Extension(dir): Extension<Dir>) -> Result<JsonRespo
 pub async fn update_dir_handler(
     State(state): State<AppState>,
     Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
     Extension(dir): Extension<Dir>,
     Path(params): Path<Params>,
     payload: Json<UpdateDir>,
@@ -73,8 +78,23 @@ pub async fn update_dir_handler(
     //    return Err(Error::BadRequest("Invalid request payload".to_string()));
     //};
 
+    let job = schedule_dir_rename(&state.db_pool, &bucket, &dir, &payload).await?;
     let updated = update_dir(&state.db_pool, &dir_id, &payload).await?;
 
+    // Renaming continues in the background, respond with the job to track it
+    if let Some(job) = job {
+        spawn_job(
+            state.db_pool.clone(),
+            state.storage_client.clone(),
+            state.config.clone(),
+            job.clone(),
+        );
+        return Ok(JsonResponse::with_status(
+            StatusCode::ACCEPTED,
+            serde_json::to_string(&job).unwrap(),
+        ));
+    }
+
     // Either return the updated dir or the original one
     match updated {
         true => get_dir_as_response(&state, &dir_id).await,
@@ -98,17 +118,342 @@ async fn get_dir_as_response(state: &AppState, id: &str) -> Result<JsonResponse>
 pub async fn delete_dir_handler(
     State(state): State<AppState>,
     Extension(actor): Extension<Actor>,
-    Path(params): Path<Params>,
+    Extension(dir): Extension<Dir>,
+    query: Query<DeleteDirParams>,
 ) -> Result<JsonResponse> {
     let permissions = vec![Permission::DirsDelete];
     if !actor.has_permissions(&permissions) {
         return Err(Error::Forbidden("Insufficient permissions".to_string()));
     }
 
-    let dir_id = params.dir_id.clone().expect("dir_id is required");
-    let _ = delete_dir(&state.db_pool, &dir_id).await?;
+    // Trashing the dir along with its files is reserved for managers
+    if query.recursive.unwrap_or(false) {
+        if !actor.has_permissions(&vec![Permission::DirsManage]) {
+            return Err(Error::Forbidden("Insufficient permissions".to_string()));
+        }
+    } else if count_dir_files(&state.db_pool, &dir.id).await? > 0 {
+        return Err(Error::ValidationError(
+            "Cannot delete directory with files inside".to_string(),
+        ));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+    let _ = trash_dir(&state.db_pool, &dir.id).await?;
     Ok(JsonResponse::with_status(
         StatusCode::NO_CONTENT,
         "".to_string(),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::{
+        dirs::{
+            NewDir, UpdateDir, create_dir, dir_accepts_objects, run_dir_rename, schedule_dir_rename,
+        },
+        jobs::{JobStatus, execute_job, get_job, update_job_status},
+        storage::StorageBackend,
+        web::test_helpers::{TestApp, pdf_document, png_image},
+    };
+
+    #[tokio::test]
+    async fn test_rename_dir() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("photo.png", &png_image(64, 64)).await;
+        let image_filename = res.body["filename"].as_str().unwrap().to_string();
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        let doc_filename = res.body["filename"].as_str().unwrap().to_string();
+
+        // Files in the trash are moved too and count in the progress
+        let res = app.upload("old.pdf", &pdf_document()).await;
+        let trashed_filename = res.body["filename"].as_str().unwrap().to_string();
+        let file_uri = format!("{}/{}", app.files_uri(), res.body["id"].as_str().unwrap());
+        let res = app.send(Method::DELETE, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        // Transformed images are dropped along with the old name
+        let cache_paths = [
+            format!("_cache/album/{}/100x0-contain-q80.jpeg", image_filename),
+            "_cache/album/abc-trashed.png/100x0-contain-q80.jpeg".to_string(),
+        ];
+        for path in cache_paths.iter() {
+            app.storage
+                .put_object("photos", path, "image/jpeg", b"cached");
+        }
+
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+        let body = json!({ "name": "summer-trip", "label": "Summer Trip" });
+        let res = app.send(Method::PATCH, &dir_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert_eq!(res.body["kind"], "dir_rename");
+        assert_eq!(res.body["total"], 6);
+
+        let job_uri = format!(
+            "/v1/buckets/{}/jobs/{}",
+            app.bucket.id,
+            res.body["id"].as_str().unwrap()
+        );
+        let mut job = res.body;
+        for _ in 0..100 {
+            job = app.send(Method::GET, &job_uri, None).await.body;
+            if job["status"] == "completed" || job["status"] == "failed" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(job["status"], "completed");
+        assert_eq!(job["processed"], 6);
+
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.body["name"], "summer-trip");
+        assert_eq!(res.body["label"], "Summer Trip");
+
+        let paths = [
+            format!("orig/{}", image_filename),
+            format!("thumb/{}", image_filename.replace(".png", ".webp")),
+            format!("orig/{}", doc_filename),
+            format!("orig/{}", trashed_filename),
+        ];
+        for path in paths.iter() {
+            let old_path = format!("album/{}", path);
+            let new_path = format!("summer-trip/{}", path);
+            assert!(app.storage.get_object("photos", &old_path).is_none());
+            assert!(app.storage.get_object("photos", &new_path).is_some());
+        }
+        for path in cache_paths.iter() {
+            assert!(app.storage.get_object("photos", path).is_none());
+        }
+
+        // Uploads go under the new prefix
+        let res = app.upload("later.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let filename = res.body["filename"].as_str().unwrap();
+        let path = format!("summer-trip/orig/{}", filename);
+        assert!(app.storage.get_object("photos", &path).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_delete_dir_recursive() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("photo.png", &png_image(64, 64)).await;
+        let image_filename = res.body["filename"].as_str().unwrap().to_string();
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        let doc_filename = res.body["filename"].as_str().unwrap().to_string();
+
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+        let res = app.send(Method::DELETE, &dir_uri, None).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let uri = format!("{}?recursive=true", dir_uri);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        let bucket_uri = format!("/v1/buckets/{}", app.bucket.id);
+        let res = app.send(Method::GET, &bucket_uri, None).await;
+        assert_eq!(res.body["file_count"], 0);
+        assert_eq!(res.body["total_size"], 0);
+
+        // Object already missing is reported but does not stop the purge
+        let path = format!("album/thumb/{}", image_filename.replace(".png", ".webp"));
+        app.storage.delete_object("photos", &path).await.unwrap();
+        let cache_paths = [
+            format!("_cache/album/{}/100x0-contain-q80.jpeg", image_filename),
+            "_cache/album/abc-trashed.png/100x0-contain-q80.jpeg".to_string(),
+        ];
+        for path in cache_paths.iter() {
+            app.storage
+                .put_object("photos", path, "image/jpeg", b"cached");
+        }
+
+        let uri = format!("/v1/buckets/{}/trash/dirs/{}", app.bucket.id, app.dir.id);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["deleted_files"], 2);
+        assert_eq!(res.body["dir_deleted"], true);
+        assert_eq!(res.body["failed"].as_array().unwrap().len(), 1);
+        assert_eq!(res.body["failed"][0]["name"], "photo.png");
+
+        for path in [
+            format!("album/orig/{}", image_filename),
+            format!("album/orig/{}", doc_filename),
+        ]
+        .iter()
+        .chain(cache_paths.iter())
+        {
+            assert!(app.storage.get_object("photos", path).is_none());
+        }
+
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        // Cached images of a live dir that reused the name are kept
+        let trashed = app.create_dir(&app.bucket.id, "album").await;
+        let trashed_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, trashed.id);
+        let res = app.send(Method::DELETE, &trashed_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        app.create_dir(&app.bucket.id, "album").await;
+        let live_path = "_cache/album/abc-live.png/100x0-contain-q80.jpeg";
+        app.storage
+            .put_object("photos", live_path, "image/jpeg", b"cached");
+
+        let uri = format!("/v1/buckets/{}/trash/dirs/{}", app.bucket.id, trashed.id);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["dir_deleted"], true);
+        assert!(app.storage.get_object("photos", live_path).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resume_dir_rename() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let res = app.upload("one.pdf", &pdf_document()).await;
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        app.upload("two.pdf", &pdf_document()).await;
+
+        let data = UpdateDir {
+            name: Some("renamed".to_string()),
+            label: None,
+        };
+        let job = schedule_dir_rename(db_pool, &app.bucket, &app.dir, &data)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Dir is locked while the job is pending
+        let res = app.upload("three.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        let res = schedule_dir_rename(db_pool, &app.bucket, &app.dir, &data).await;
+        assert!(res.is_err());
+
+        run_dir_rename(db_pool, app.storage.as_ref(), &job)
+            .await
+            .unwrap();
+
+        // Running again from the saved progress is harmless
+        let job = get_job(db_pool, &job.id).await.unwrap().unwrap();
+        assert_eq!(job.processed, 4);
+        run_dir_rename(db_pool, app.storage.as_ref(), &job)
+            .await
+            .unwrap();
+        update_job_status(db_pool, &job.id, JobStatus::Completed, None)
+            .await
+            .unwrap();
+
+        let path = format!("renamed/orig/{}", filename);
+        assert!(app.storage.get_object("photos", &path).is_some());
+        let path = format!("album/orig/{}", filename);
+        assert!(app.storage.get_object("photos", &path).is_none());
+
+        let res = app.upload("three.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_failed_dir_rename() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let mut filenames: Vec<String> = Vec::new();
+        for name in ["one.pdf", "two.pdf", "three.pdf"] {
+            let res = app.upload(name, &pdf_document()).await;
+            filenames.push(res.body["filename"].as_str().unwrap().to_string());
+        }
+
+        // Last file can't be copied, the first two already are by then
+        let broken = format!("album/orig/{}", filenames[2]);
+        app.storage.delete_object("photos", &broken).await.unwrap();
+
+        let data = UpdateDir {
+            name: Some("renamed".to_string()),
+            label: None,
+        };
+        let job = schedule_dir_rename(db_pool, &app.bucket, &app.dir, &data)
+            .await
+            .unwrap()
+            .unwrap();
+        let config = app.state.config.as_ref();
+        let res = execute_job(db_pool, app.storage.as_ref(), config, &job).await;
+        assert!(res.is_err());
+
+        let job = get_job(db_pool, &job.id).await.unwrap().unwrap();
+        assert_eq!(job.status, "failed");
+        assert_eq!(job.processed, 0);
+        assert!(job.cursor.is_none());
+
+        // Copies are rolled back, the dir keeps its name and objects
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.body["name"], "album");
+        for filename in filenames.iter() {
+            let path = format!("renamed/orig/{}", filename);
+            assert!(app.storage.get_object("photos", &path).is_none());
+        }
+        let path = format!("album/orig/{}", filenames[0]);
+        assert!(app.storage.get_object("photos", &path).is_some());
+
+        // Rename can be requested again once the object is back
+        app.storage
+            .put_object("photos", &broken, "application/pdf", &pdf_document());
+        let job = schedule_dir_rename(db_pool, &app.bucket, &app.dir, &data)
+            .await
+            .unwrap()
+            .unwrap();
+        execute_job(db_pool, app.storage.as_ref(), config, &job)
+            .await
+            .unwrap();
+        for filename in filenames.iter() {
+            let path = format!("renamed/orig/{}", filename);
+            assert!(app.storage.get_object("photos", &path).is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_dir_renames() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let other = app.create_dir(&app.bucket.id, "other").await;
+
+        let data = UpdateDir {
+            name: Some("renamed".to_string()),
+            label: None,
+        };
+        schedule_dir_rename(db_pool, &app.bucket, &app.dir, &data)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Name is reserved until the first rename is done
+        let res = schedule_dir_rename(db_pool, &app.bucket, &other, &data).await;
+        assert!(res.is_err());
+        let new_dir = NewDir {
+            name: "renamed".to_string(),
+            label: "Renamed".to_string(),
+        };
+        assert!(create_dir(db_pool, &app.bucket.id, &new_dir).await.is_err());
+
+        // Saving a file into the dir is rejected even past the early checks
+        let db = db_pool.get().await.unwrap();
+        let (album_id, other_id) = (app.dir.id.clone(), other.id.clone());
+        let accepts = db
+            .interact(move |conn| {
+                (
+                    dir_accepts_objects(conn, &album_id, "album").unwrap(),
+                    dir_accepts_objects(conn, &other_id, "other").unwrap(),
+                    dir_accepts_objects(conn, &other_id, "old-name").unwrap(),
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(accepts, (false, true, false));
+    }
+}
@@ -1,6 +1,9 @@
 use axum::{Router, middleware, routing::get};
 
 use crate::web::files::files_routes;
+use crate::web::presigned::presigned_routes;
+use crate::web::tus::tus_routes;
+use crate::web::uploads::upload_routes;
 use crate::web::{middlewares::dir_middleware, server::AppState};
 
 use super::{
@@ -23,6 +26,9 @@ fn inner_dir_routes(state: AppState) -> Router<AppState> {
                 .delete(delete_dir_handler),
         )
         .nest("/files", files_routes(state.clone()))
+        .nest("/uploads", upload_routes(state.clone()))
+        .nest("/tus", tus_routes(state.clone()))
+        .nest("/presigned", presigned_routes(state.clone()))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             dir_middleware,
@@ -0,0 +1,128 @@
+use axum::{Extension, Router, extract::State, routing::get};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    files::list_duplicate_groups,
+    roles::Permission,
+    web::{response::JsonResponse, server::AppState},
+};
+
+pub fn duplicates_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_duplicates_handler))
+        .with_state(state)
+}
+
+/// Lists groups of files in the bucket that share the same content
+pub async fn list_duplicates_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesList];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let groups = list_duplicate_groups(&state.db_pool, &bucket.id).await?;
+    Ok(JsonResponse::new(serde_json::to_string(&groups).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use crate::{
+        buckets::{DedupePolicy, update_bucket_dedupe_policy},
+        web::test_helpers::{TestApp, pdf_document},
+    };
+
+    #[tokio::test]
+    async fn test_dedupe_policy_and_duplicates() {
+        let mut app = TestApp::new().await;
+        let db_pool = &app.state.db_pool.clone();
+        let duplicates_uri = format!("/v1/buckets/{}/duplicates", app.bucket.id);
+
+        let res = app.upload("one.pdf", &pdf_document()).await;
+        let one_id = res.body["id"].as_str().unwrap().to_string();
+        let one_filename = res.body["filename"].as_str().unwrap().to_string();
+
+        // Without a policy, the same content is accepted as is
+        let res = app.upload("two.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert!(res.body.get("duplicate_of").is_none());
+
+        let res = app.send(Method::GET, &duplicates_uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body.as_array().unwrap().len(), 1);
+        assert_eq!(res.body[0]["files"].as_array().unwrap().len(), 2);
+        assert_eq!(res.body[0]["files"][0]["id"], one_id.as_str());
+
+        let policy = Some(DedupePolicy::Reject);
+        update_bucket_dedupe_policy(db_pool, &app.bucket.id, policy)
+            .await
+            .unwrap();
+        let res = app.upload("three.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+
+        let policy = Some(DedupePolicy::Warn);
+        update_bucket_dedupe_policy(db_pool, &app.bucket.id, policy)
+            .await
+            .unwrap();
+        let res = app.upload("three.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["duplicate_of"], one_id.as_str());
+
+        // Linked upload is recorded in its dir and shares the oldest copy
+        let policy = Some(DedupePolicy::Link);
+        update_bucket_dedupe_policy(db_pool, &app.bucket.id, policy)
+            .await
+            .unwrap();
+        let album_uri = app.files_uri();
+        app.dir = app.create_dir(&app.bucket.id, "other").await;
+        let res = app.upload("four.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let four_id = res.body["id"].as_str().unwrap().to_string();
+        assert_ne!(four_id, one_id);
+        assert_eq!(res.body["name"], "four.pdf");
+        assert_eq!(res.body["dir_id"], app.dir.id.as_str());
+        assert_eq!(res.body["duplicate_of"], one_id.as_str());
+        let one_path = format!("album/orig/{}", one_filename);
+        let url = res.body["url"].as_str().unwrap();
+        assert!(url.ends_with(&one_path));
+
+        let four_path = format!("other/orig/{}", one_filename);
+        assert!(app.storage.get_object("photos", &four_path).is_none());
+
+        let res = app.send(Method::GET, &album_uri, None).await;
+        assert_eq!(res.body["meta"]["total_records"], 3);
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 1);
+        assert_eq!(res.body["data"][0]["id"], four_id.as_str());
+        assert_eq!(res.body["data"][0]["duplicate_of"], one_id.as_str());
+        let url = res.body["data"][0]["url"].as_str().unwrap();
+        assert!(url.ends_with(&one_path));
+
+        let res = app.send(Method::GET, &duplicates_uri, None).await;
+        assert_eq!(res.body[0]["files"].as_array().unwrap().len(), 4);
+
+        // Purging the oldest copy hands its objects over to the link
+        let uri = format!("{}/{}", album_uri, one_id);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        let uri = format!("/v1/buckets/{}/trash/files/{}", app.bucket.id, one_id);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        assert!(app.storage.get_object("photos", &one_path).is_none());
+        assert!(app.storage.get_object("photos", &four_path).is_some());
+        let uri = format!("{}/{}", app.files_uri(), four_id);
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(res.body.get("duplicate_of").is_none());
+        let url = res.body["url"].as_str().unwrap();
+        assert!(url.ends_with(&four_path));
+    }
+}
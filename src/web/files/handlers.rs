@@ -1,23 +1,36 @@
 use axum::{
-    Extension,
-    extract::{Multipart, Query, State},
+    Extension, Json,
+    extract::{
+        Multipart, Query, State,
+        multipart::{Field, MultipartError, MultipartRejection},
+    },
     http::StatusCode,
 };
-use tokio::{fs::File, fs::create_dir_all, io::AsyncWriteExt};
+use deadpool_diesel::sqlite::Pool;
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, fs::create_dir_all, fs::remove_file, io::AsyncWriteExt};
+use tracing::error;
 
 use crate::{
     Error, Result,
     auth::Actor,
-    buckets::BucketDto,
-    dirs::Dir,
+    buckets::{BucketDto, get_bucket},
+    config::Config,
+    dirs::{Dir, ensure_dir_idle, get_dir},
     files::{
-        FileDto, FileObject, FilePayload, ImgVersion, ListFilesParams, create_file, delete_file,
-        list_files,
+        FileDestination, FileDto, FileLocation, FileObject, FilePayload, FileUploadResult,
+        ImgVersion, ListFilesParams, UpdateFile, copy_file, create_file, get_file, list_files,
+        move_file, resolve_link, resolve_links, update_file, valid_checksum,
     },
     roles::Permission,
-    storage::{delete_file_object, format_file, format_files},
-    util::slugify_prefixed,
-    web::{pagination::Paginated, response::JsonResponse, server::AppState},
+    storage::{StorageBackend, format_file, format_files},
+    trash::trash_file,
+    util::{slugify_prefixed, valid_id},
+    web::{
+        pagination::Paginated,
+        response::{JsonResponse, to_error_body},
+        server::AppState,
+    },
 };
 
 #[axum::debug_handler]
@@ -41,7 +54,15 @@ pub async fn list_files_handler(
 
     // Generate download urls for each files
     let items: Vec<FileDto> = files.data.into_iter().map(|f| f.into()).collect();
-    let items = format_files(&storage_client, &bucket.name, &dir.name, items).await?;
+    let items = resolve_links(&state.db_pool, items).await?;
+    let items = format_files(
+        &storage_client,
+        &state.config,
+        &bucket.name,
+        &dir.name,
+        items,
+    )
+    .await?;
     let listing = Paginated::new(
         items,
         files.meta.page,
@@ -51,85 +72,312 @@ pub async fn list_files_handler(
     Ok(JsonResponse::new(serde_json::to_string(&listing).unwrap()))
 }
 
+/// Most files accepted in a single upload request
+pub const MAX_UPLOAD_FILES: usize = 50;
+
+/// Uploads every `file` field of the request. A single file responds with the
+/// created file, several files respond with a result per file.
 #[axum::debug_handler]
 pub async fn create_file_handler(
     State(state): State<AppState>,
     Extension(actor): Extension<Actor>,
     Extension(bucket): Extension<BucketDto>,
     Extension(dir): Extension<Dir>,
-    mut multipart: Multipart,
+    multipart: core::result::Result<Multipart, MultipartRejection>,
 ) -> Result<JsonResponse> {
     let permissions = vec![Permission::FilesCreate];
     if !actor.has_permissions(&permissions) {
         return Err(Error::Forbidden("Insufficient permissions".to_string()));
     }
 
-    let mut payload: Option<FilePayload> = None;
+    let mut multipart = match multipart {
+        Ok(multipart) => multipart,
+        Err(e) => return Err(Error::InvalidMultipart(e.body_text())),
+    };
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    // Ensure upload dir exists
+    let orig_dir = state
+        .config
+        .upload_dir
+        .clone()
+        .join(ImgVersion::Original.to_string());
+    let dir_res = create_dir_all(orig_dir.clone()).await;
+    if let Err(_) = dir_res {
+        return Err("Unable to create upload dir".into());
+    }
+
+    // Receive every file before processing so a broken request creates nothing
+    let max_size = bucket.upload_limit(state.config.upload.max_file_size) as usize;
+    let mut received: Vec<(String, Result<FilePayload>)> = Vec::new();
+
+    // Expected checksum of the file field that follows
+    let mut checksum: Option<String> = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                cleanup_received_files(&received).await;
+                return Err(multipart_error(e));
+            }
+        };
 
-    while let Some(mut field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        if name != "file" {
+        if field.name() == Some("checksum") {
+            let value = match field.text().await {
+                Ok(value) => value.trim().to_lowercase(),
+                Err(e) => {
+                    cleanup_received_files(&received).await;
+                    return Err(multipart_error(e));
+                }
+            };
+            if !valid_checksum(&value) {
+                cleanup_received_files(&received).await;
+                return Err(Error::InvalidMultipart(
+                    "Checksum must be a hex encoded SHA-256".to_string(),
+                ));
+            }
+            checksum = Some(value);
+            continue;
+        }
+        if field.name() != Some("file") {
             continue;
         }
+        if received.len() >= MAX_UPLOAD_FILES {
+            cleanup_received_files(&received).await;
+            return Err(Error::BadRequest(format!(
+                "Upload at most {} files per request",
+                MAX_UPLOAD_FILES
+            )));
+        }
 
-        let original_filename = field.file_name().unwrap().to_string();
-
-        // Low chance of collision but higher than the full uuid v7 string
-        // Prefer a shorter filename for better readability
-        let filename = slugify_prefixed(&original_filename);
-
-        // Ensure upload dir exists
-        let orig_dir = state
-            .config
-            .upload_dir
-            .clone()
-            .join(ImgVersion::Original.to_string());
-        let dir_res = create_dir_all(orig_dir.clone()).await;
-        if let Err(_) = dir_res {
-            return Err("Unable to create upload dir".into());
+        let original_filename = match field.file_name() {
+            Some(name) if !name.trim().is_empty() => name.to_string(),
+            _ => {
+                cleanup_received_files(&received).await;
+                return Err(Error::MissingFileName);
+            }
+        };
+        let expected = checksum.take();
+        let res = receive_file(
+            &state,
+            field,
+            &original_filename,
+            max_size,
+            expected.as_deref(),
+        )
+        .await;
+        match res {
+            // Only this file is rejected, the rest of the request is still readable
+            Err(Error::PayloadTooLarge(message)) => {
+                received.push((original_filename, Err(Error::PayloadTooLarge(message))));
+            }
+            Err(Error::ChecksumMismatch) => {
+                received.push((original_filename, Err(Error::ChecksumMismatch)));
+            }
+            Err(e) => {
+                cleanup_received_files(&received).await;
+                return Err(e);
+            }
+            Ok(payload) => received.push((original_filename, Ok(payload))),
         }
+    }
 
-        // Prepare to save to file
-        let file_path = orig_dir.as_path().join(&filename);
-        let Ok(mut file) = File::create(&file_path).await else {
-            return Err("Unable to create file".into());
+    let db_pool = state.db_pool.clone();
+    let storage_client = state.storage_client;
+    let mut results: Vec<(String, Result<FileDto>)> = Vec::with_capacity(received.len());
+    for (name, payload) in received.into_iter() {
+        let res = match payload {
+            Ok(payload) => {
+                let res = create_file(
+                    &db_pool,
+                    storage_client.as_ref(),
+                    &bucket,
+                    &dir,
+                    &payload,
+                    &state.config.image,
+                )
+                .await;
+                match res {
+                    Ok(file_dto) => {
+                        format_dir_file(
+                            &db_pool,
+                            storage_client.as_ref(),
+                            &state.config,
+                            &bucket,
+                            &dir,
+                            file_dto,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
         };
+        results.push((name, res));
+    }
 
-        // Stream contents to file
-        let mut size: usize = 0;
-        while let Some(chunk) = field.chunk().await.unwrap() {
-            size += chunk.len();
-            file.write_all(&chunk).await.unwrap();
-        }
+    if results.len() <= 1 {
+        let Some((_, res)) = results.pop() else {
+            return Err(Error::MissingUploadFile("Missing upload file".to_string()));
+        };
+        let file_dto = res?;
+        return Ok(JsonResponse::with_status(
+            StatusCode::CREATED,
+            serde_json::to_string(&file_dto).unwrap(),
+        ));
+    }
 
-        payload = Some({
-            FilePayload {
-                upload_dir: state.config.upload_dir.clone(),
-                name: original_filename,
-                filename: filename.clone(),
-                path: orig_dir.clone().join(&filename),
-                size: size as i64,
+    let items: Vec<FileUploadResult> = results
+        .into_iter()
+        .map(|(name, res)| match res {
+            Ok(file_dto) => FileUploadResult {
+                name,
+                status_code: StatusCode::CREATED.as_u16(),
+                file: Some(file_dto),
+                error: None,
+            },
+            Err(e) => {
+                // Details of server errors stay in the logs
+                let body = to_error_body(e);
+                let message = match body.status_code >= 500 {
+                    true => {
+                        error!("Upload {}: {}", name, body.message);
+                        body.error
+                    }
+                    false => body.message,
+                };
+                FileUploadResult {
+                    name,
+                    status_code: body.status_code,
+                    file: None,
+                    error: Some(message),
+                }
             }
         })
-    }
+        .collect();
+
+    // Multi-status when at least one file failed
+    let status = match items.iter().all(|item| item.file.is_some()) {
+        true => StatusCode::CREATED,
+        false => StatusCode::MULTI_STATUS,
+    };
+    Ok(JsonResponse::with_status(
+        status,
+        serde_json::to_string(&items).unwrap(),
+    ))
+}
 
-    let Some(payload) = payload else {
-        return Err(Error::MissingUploadFile("Missing upload file".to_string()));
+/// Generates the urls of a file of the dir, which are those of the file it
+/// is linked to if any
+pub async fn format_dir_file(
+    db_pool: &Pool,
+    storage_client: &dyn StorageBackend,
+    config: &Config,
+    bucket: &BucketDto,
+    dir: &Dir,
+    file: FileDto,
+) -> Result<FileDto> {
+    let file = resolve_link(db_pool, file).await?;
+    format_file(storage_client, config, &bucket.name, &dir.name, file).await
+}
+
+/// Streams the multipart field into the upload dir, up to the size limit,
+/// and rejects it when it does not match the expected checksum
+async fn receive_file(
+    state: &AppState,
+    mut field: Field<'_>,
+    original_filename: &str,
+    max_size: usize,
+    expected_checksum: Option<&str>,
+) -> Result<FilePayload> {
+    // Low chance of collision but higher than the full uuid v7 string
+    // Prefer a shorter filename for better readability
+    let filename = slugify_prefixed(original_filename);
+
+    // Prepare to save to file
+    let orig_dir = state
+        .config
+        .upload_dir
+        .clone()
+        .join(ImgVersion::Original.to_string());
+    let file_path = orig_dir.as_path().join(&filename);
+    let mut file = match File::create(&file_path).await {
+        Ok(file) => file,
+        Err(e) => return Err(Error::from_write(e, "Unable to create file")),
     };
 
-    let db_pool = state.db_pool.clone();
-    let storage_client = state.storage_client;
-    let res = create_file(&db_pool, &storage_client, &bucket, &dir, &payload).await;
-    match res {
-        Ok(file) => {
-            let file_dto: FileDto = file.into();
-            let file_dto = format_file(&storage_client, &bucket.name, &dir.name, file_dto).await?;
-            Ok(JsonResponse::with_status(
-                StatusCode::CREATED,
-                serde_json::to_string(&file_dto).unwrap(),
-            ))
+    // Stream contents to file, stop as soon as the limit is exceeded
+    let mut hasher = Sha256::new();
+    let mut size: usize = 0;
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = remove_file(&file_path).await;
+                return Err(multipart_error(e));
+            }
+        };
+
+        size += chunk.len();
+        if size > max_size {
+            let _ = remove_file(&file_path).await;
+            return Err(Error::PayloadTooLarge(format!(
+                "File exceeds the maximum size of {} bytes",
+                max_size
+            )));
+        }
+        if let Err(e) = file.write_all(&chunk).await {
+            let _ = remove_file(&file_path).await;
+            return Err(Error::from_write(e, "Unable to write file"));
+        }
+        hasher.update(&chunk);
+    }
+
+    // Buffered writes must land on disk before the file is inspected
+    if let Err(e) = file.flush().await {
+        let _ = remove_file(&file_path).await;
+        return Err(Error::from_write(e, "Unable to write file"));
+    }
+
+    let checksum = hex::encode(hasher.finalize());
+    if let Some(expected) = expected_checksum
+        && expected != checksum
+    {
+        let _ = remove_file(&file_path).await;
+        return Err(Error::ChecksumMismatch);
+    }
+
+    Ok(FilePayload {
+        upload_dir: state.config.upload_dir.clone(),
+        name: original_filename.to_string(),
+        filename,
+        path: file_path,
+        size: size as i64,
+        checksum: Some(checksum),
+    })
+}
+
+/// Removes the files saved so far when the request is aborted
+async fn cleanup_received_files(received: &[(String, Result<FilePayload>)]) {
+    for (_, payload) in received.iter() {
+        if let Ok(payload) = payload {
+            let _ = remove_file(&payload.path).await;
         }
-        Err(e) => Err(e),
+    }
+}
+
+fn multipart_error(e: MultipartError) -> Error {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => {
+            Error::PayloadTooLarge("Upload exceeds the maximum size".to_string())
+        }
+        StatusCode::BAD_REQUEST => Error::InvalidMultipart(e.body_text()),
+        // Body stream failed midway, ie: the client went away
+        _ => Error::UploadAborted("Upload was interrupted, please try again".to_string()),
     }
 }
 
@@ -142,33 +390,942 @@ pub async fn get_file_handler(
     let storage_client = state.storage_client;
     // Extract dir from the middleware extension
     let file_dto: FileDto = file.clone().into();
-    let file_dto = format_file(&storage_client, &bucket.name, &dir.name, file_dto).await?;
+    let file_dto = format_dir_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &bucket,
+        &dir,
+        file_dto,
+    )
+    .await?;
     Ok(JsonResponse::new(serde_json::to_string(&file_dto).unwrap()))
 }
 
-pub async fn delete_file_handler(
+pub async fn update_file_handler(
     State(state): State<AppState>,
     Extension(actor): Extension<Actor>,
     Extension(bucket): Extension<BucketDto>,
     Extension(dir): Extension<Dir>,
     Extension(file): Extension<FileObject>,
+    payload: Json<UpdateFile>,
 ) -> Result<JsonResponse> {
-    let permissions = vec![Permission::FilesDelete];
+    let permissions = vec![Permission::FilesEdit];
     if !actor.has_permissions(&permissions) {
         return Err(Error::Forbidden("Insufficient permissions".to_string()));
     }
 
-    // Delete record
-    let db_pool = state.db_pool.clone();
-    let _ = delete_file(&db_pool, &file.id).await?;
+    let updated = update_file(&state.db_pool, &file, &payload).await?;
+
+    // Either return the updated file or the original one
+    let file = match updated {
+        true => match get_file(&state.db_pool, &file.id).await? {
+            Some(updated_file) => updated_file,
+            None => return Err("Error getting file".into()),
+        },
+        false => file,
+    };
 
-    // Delete file(s) from storage
     let storage_client = state.storage_client;
-    let dto: FileDto = file.into();
-    let _ = delete_file_object(&storage_client, &bucket.name, &dir.name, &dto).await?;
+    let file_dto: FileDto = file.into();
+    let file_dto = format_dir_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &bucket,
+        &dir,
+        file_dto,
+    )
+    .await?;
+    Ok(JsonResponse::new(serde_json::to_string(&file_dto).unwrap()))
+}
+
+pub async fn move_file_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    Extension(file): Extension<FileObject>,
+    payload: Json<FileDestination>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesEdit];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let target = get_file_destination(&state, &actor, &bucket, &payload).await?;
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+    ensure_dir_idle(&state.db_pool, &target.dir).await?;
+    let source = FileLocation { bucket, dir };
+    let storage_client = state.storage_client;
+    let moved = move_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &source,
+        &file,
+        &target,
+        &payload,
+    )
+    .await?;
+
+    let file_dto: FileDto = moved.into();
+    let file_dto = format_dir_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &target.bucket,
+        &target.dir,
+        file_dto,
+    )
+    .await?;
+    Ok(JsonResponse::new(serde_json::to_string(&file_dto).unwrap()))
+}
+
+pub async fn copy_file_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    Extension(file): Extension<FileObject>,
+    payload: Json<FileDestination>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let target = get_file_destination(&state, &actor, &bucket, &payload).await?;
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+    ensure_dir_idle(&state.db_pool, &target.dir).await?;
+    let source = FileLocation { bucket, dir };
+    let storage_client = state.storage_client;
+    let copy = copy_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &source,
+        &file,
+        &target,
+        &payload,
+    )
+    .await?;
+
+    let file_dto: FileDto = copy.into();
+    let file_dto = format_dir_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &target.bucket,
+        &target.dir,
+        file_dto,
+    )
+    .await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::CREATED,
+        serde_json::to_string(&file_dto).unwrap(),
+    ))
+}
+
+/// Resolves the target bucket and dir, only buckets of the same client are allowed
+async fn get_file_destination(
+    state: &AppState,
+    actor: &Actor,
+    bucket: &BucketDto,
+    data: &FileDestination,
+) -> Result<FileLocation> {
+    let target_bucket = match &data.bucket_id {
+        Some(bucket_id) if bucket_id != &bucket.id => {
+            if !valid_id(bucket_id) {
+                return Err(Error::BadRequest("Invalid bucket id".to_string()));
+            }
+            match get_bucket(&state.db_pool, bucket_id).await? {
+                Some(target) if target.client_id == actor.client_id => target,
+                _ => return Err(Error::NotFound("Bucket not found".to_string())),
+            }
+        }
+        _ => bucket.clone(),
+    };
+
+    if !valid_id(&data.dir_id) {
+        return Err(Error::BadRequest("Invalid directory id".to_string()));
+    }
+    let target_dir = match get_dir(&state.db_pool, &data.dir_id).await? {
+        Some(target) if target.bucket_id == target_bucket.id => target,
+        _ => return Err(Error::NotFound("Directory not found".to_string())),
+    };
+
+    Ok(FileLocation {
+        bucket: target_bucket,
+        dir: target_dir,
+    })
+}
+
+pub async fn delete_file_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(dir): Extension<Dir>,
+    Extension(file): Extension<FileObject>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesDelete];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    // Objects are kept until the file is purged from the trash
+    let _ = trash_file(&state.db_pool, &file.id).await?;
 
     Ok(JsonResponse::with_status(
         StatusCode::NO_CONTENT,
         "".to_string(),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        Error,
+        buckets::{
+            NewImgProfile, recount_bucket_stats, set_img_profile, update_bucket_max_file_size,
+        },
+        web::{
+            response::to_error_response,
+            test_helpers::{TestApp, multipart_body, pdf_document, png_image},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_upload_image() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("Sunset Photo.png", &png_image(300, 200)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["name"], "Sunset Photo.png");
+        assert_eq!(res.body["content_type"], "image/png");
+        assert_eq!(res.body["is_image"], true);
+
+        // Small images only get the original and thumbnail versions
+        let versions = res.body["img_versions"].as_array().unwrap();
+        let names: Vec<&str> = versions
+            .iter()
+            .map(|v| v["version"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["orig", "thumb"]);
+        assert!(versions.iter().all(|v| v["url"].is_string()));
+
+        let filename = res.body["filename"].as_str().unwrap();
+        let orig = app
+            .storage
+            .get_object("photos", &format!("album/orig/{}", filename))
+            .unwrap();
+        assert_eq!(orig.content_type, "image/png");
+        assert_eq!(versions[0]["content_type"], "image/png");
+
+        // Derived versions are encoded as webp by default, named after it
+        assert_eq!(versions[1]["content_type"], "image/webp");
+        let thumb_path = format!("album/thumb/{}", filename.replace(".png", ".webp"));
+        let thumb = app.storage.get_object("photos", &thumb_path).unwrap();
+        assert_eq!(thumb.content_type, "image/webp");
+        assert_eq!(&thumb.data[8..12], b"WEBP");
+        let url = versions[1]["url"].as_str().unwrap();
+        assert!(url.ends_with(&thumb_path));
+    }
+
+    #[tokio::test]
+    async fn test_upload_version_quality() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        // Noise keeps the png large, like a photo saved as png
+        let mut seed: u32 = 42;
+        let img = image::RgbImage::from_fn(1200, 800, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let v = (seed >> 16) as u8;
+            image::Rgb([v, v / 2, 255 - v])
+        });
+        let mut data: Vec<u8> = Vec::new();
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(
+                &mut std::io::Cursor::new(&mut data),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+
+        let res = app.upload("noise.png", &data).await;
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        let object = |version: &str, filename: &str, format: &str| {
+            let filename = filename.replace(".png", &format!(".{}", format));
+            app.storage
+                .get_object("photos", &format!("album/{}/{}", version, filename))
+                .unwrap()
+        };
+        let prev = object("prev", &filename, "webp");
+        assert_eq!(prev.content_type, "image/webp");
+        assert!(prev.data.len() < data.len() / 2);
+
+        for (name, format, quality) in [
+            ("low", None, 20),
+            ("high", None, 95),
+            ("flat", Some("jpeg"), 80),
+        ] {
+            let profile = NewImgProfile {
+                name: name.to_string(),
+                max_width: 600,
+                max_height: 600,
+                fit: None,
+                format: format.map(|f| f.to_string()),
+                quality: Some(quality),
+            };
+            set_img_profile(db_pool, &app.bucket.id, &profile)
+                .await
+                .unwrap();
+        }
+        let res = app.upload("other.png", &data).await;
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        let low = object("low", &filename, "webp");
+        let high = object("high", &filename, "webp");
+        assert!(low.data.len() * 2 < high.data.len());
+
+        // Transparent pixels turn white rather than black in jpeg
+        let img = image::RgbaImage::from_pixel(50, 50, image::Rgba([0, 0, 0, 0]));
+        let mut data: Vec<u8> = Vec::new();
+        image::DynamicImage::ImageRgba8(img)
+            .write_to(
+                &mut std::io::Cursor::new(&mut data),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let res = app.upload("clear.png", &data).await;
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        let flat = image::load_from_memory(&object("flat", &filename, "jpeg").data).unwrap();
+        assert!(flat.to_rgb8().get_pixel(25, 25)[0] > 250);
+        let low = image::load_from_memory(&object("low", &filename, "webp").data).unwrap();
+        assert_eq!(low.to_rgba8().get_pixel(25, 25)[3], 0);
+    }
+
+    #[tokio::test]
+    async fn test_upload_webp_and_tiff_images() {
+        let app = TestApp::new().await;
+
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            80,
+            60,
+            image::Rgb([20, 120, 220]),
+        ));
+        for (name, format, content_type) in [
+            ("photo.webp", image::ImageFormat::WebP, "image/webp"),
+            ("scan.tiff", image::ImageFormat::Tiff, "image/tiff"),
+        ] {
+            let mut bytes: Vec<u8> = Vec::new();
+            img.write_to(&mut std::io::Cursor::new(&mut bytes), format)
+                .unwrap();
+
+            let res = app.upload(name, &bytes).await;
+            assert_eq!(res.status, StatusCode::CREATED);
+            assert_eq!(res.body["content_type"], content_type);
+            assert_eq!(res.body["is_image"], true);
+
+            // Original is kept as is, versions use the web format
+            let versions = res.body["img_versions"].as_array().unwrap();
+            assert_eq!(versions[0]["version"], "orig");
+            assert_eq!(versions[0]["content_type"], content_type);
+            assert_eq!(versions[1]["version"], "thumb");
+            assert_eq!(versions[1]["content_type"], "image/webp");
+            assert_eq!(versions[1]["dimension"]["width"], 80);
+        }
+    }
+
+    #[cfg(not(feature = "heif"))]
+    #[tokio::test]
+    async fn test_upload_heif_without_feature() {
+        let app = TestApp::new().await;
+
+        let mut heic: Vec<u8> = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic".to_vec();
+        heic.extend_from_slice(&[0; 64]);
+        let res = app.upload("IMG_0001.HEIC", &heic).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.body["message"], "Uploaded image type not allowed");
+    }
+
+    #[cfg(feature = "heif")]
+    #[tokio::test]
+    async fn test_upload_avif_image() {
+        let app = TestApp::new().await;
+
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            80,
+            60,
+            image::Rgb([20, 120, 220]),
+        ));
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Avif,
+        )
+        .unwrap();
+
+        let res = app.upload("photo.avif", &bytes).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["content_type"], "image/avif");
+        assert_eq!(res.body["is_image"], true);
+
+        let versions = res.body["img_versions"].as_array().unwrap();
+        assert_eq!(versions[0]["content_type"], "image/avif");
+        assert_eq!(versions[1]["version"], "thumb");
+        assert_eq!(versions[1]["content_type"], "image/jpeg");
+        assert_eq!(versions[1]["dimension"]["width"], 80);
+    }
+
+    #[tokio::test]
+    async fn test_upload_image_profiles() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let medium = NewImgProfile {
+            name: "medium".to_string(),
+            max_width: 600,
+            max_height: 600,
+            fit: Some("cover".to_string()),
+            format: Some("jpeg".to_string()),
+            quality: Some(80),
+        };
+        set_img_profile(db_pool, &app.bucket.id, &medium)
+            .await
+            .unwrap();
+        let thumb = NewImgProfile {
+            name: "thumb".to_string(),
+            max_width: 100,
+            max_height: 100,
+            fit: None,
+            format: None,
+            quality: None,
+        };
+        set_img_profile(db_pool, &app.bucket.id, &thumb)
+            .await
+            .unwrap();
+        let tiny = NewImgProfile {
+            name: "tiny".to_string(),
+            max_width: 32,
+            max_height: 32,
+            fit: None,
+            format: Some("avif".to_string()),
+            quality: Some(50),
+        };
+        set_img_profile(db_pool, &app.bucket.id, &tiny)
+            .await
+            .unwrap();
+
+        // Declared profiles replace the default preview
+        let res = app.upload("wide.png", &png_image(1200, 800)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let versions = res.body["img_versions"].as_array().unwrap();
+        let names: Vec<&str> = versions
+            .iter()
+            .map(|v| v["version"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["orig", "medium", "thumb", "tiny"]);
+        assert_eq!(
+            versions[1]["dimension"],
+            json!({ "width": 600, "height": 600 })
+        );
+        assert_eq!(versions[1]["content_type"], "image/jpeg");
+        assert_eq!(
+            versions[2]["dimension"],
+            json!({ "width": 100, "height": 67 })
+        );
+        assert_eq!(versions[2]["content_type"], "image/webp");
+        assert_eq!(versions[3]["content_type"], "image/avif");
+
+        let filename = res.body["filename"].as_str().unwrap();
+        let medium_path = format!("album/medium/{}", filename.replace(".png", ".jpeg"));
+        let medium = app.storage.get_object("photos", &medium_path).unwrap();
+        assert_eq!(medium.content_type, "image/jpeg");
+        assert!(medium.data.starts_with(&[0xff, 0xd8]));
+        let tiny_path = format!("album/tiny/{}", filename.replace(".png", ".avif"));
+        let tiny = app.storage.get_object("photos", &tiny_path).unwrap();
+        assert_eq!(tiny.content_type, "image/avif");
+        assert_eq!(&tiny.data[4..12], b"ftypavif");
+
+        // Versions survive a round trip through the database
+        let uri = format!("{}/{}", app.files_uri(), res.body["id"].as_str().unwrap());
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.body["img_versions"][1]["version"], "medium");
+        assert_eq!(res.body["img_versions"][1]["content_type"], "image/jpeg");
+
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        // A preview smaller than the default threshold is created past its own size
+        let prev = NewImgProfile {
+            name: "prev".to_string(),
+            max_width: 400,
+            max_height: 400,
+            fit: None,
+            format: None,
+            quality: None,
+        };
+        set_img_profile(db_pool, &app.bucket.id, &prev)
+            .await
+            .unwrap();
+        let res = app.upload("small.png", &png_image(600, 300)).await;
+        let versions = res.body["img_versions"].as_array().unwrap();
+        let prev = versions.iter().find(|v| v["version"] == "prev").unwrap();
+        assert_eq!(prev["dimension"], json!({ "width": 400, "height": 200 }));
+
+        let res = app.upload("tiny.png", &png_image(300, 200)).await;
+        let versions = res.body["img_versions"].as_array().unwrap();
+        assert!(versions.iter().all(|v| v["version"] != "prev"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_document() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("report.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["content_type"], "application/pdf");
+        assert_eq!(res.body["is_image"], false);
+        assert!(res.body["url"].is_string());
+        assert!(res.body["img_versions"].is_null());
+
+        let filename = res.body["filename"].as_str().unwrap();
+        let orig = app
+            .storage
+            .get_object("photos", &format!("album/orig/{}", filename))
+            .unwrap();
+        assert_eq!(orig.data, pdf_document());
+
+        // Same name on the same dir is rejected
+        let res = app.upload("report.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_list_get_and_delete_files() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("photo.png", &png_image(64, 64)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["meta"]["total_records"], 2);
+        assert_eq!(res.body["data"].as_array().unwrap().len(), 2);
+
+        let file_uri = format!("{}/{}", app.files_uri(), file_id);
+        let res = app.send(Method::GET, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["id"], file_id.as_str());
+
+        // Deleted files go to the trash, objects are kept until purged
+        let res = app.send(Method::DELETE, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(
+            app.storage
+                .get_object("photos", &format!("album/orig/{}", filename))
+                .is_some()
+        );
+
+        let res = app.send(Method::GET, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_size_limit() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        update_bucket_max_file_size(db_pool, &app.bucket.id, Some(1000))
+            .await
+            .unwrap();
+
+        let res = app.upload("large.pdf", &vec![0u8; 5000]).await;
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(res.body["status_code"], 413);
+
+        // Partial upload is not left behind
+        let orig_dir = app.state.config.upload_dir.join("orig");
+        assert_eq!(std::fs::read_dir(&orig_dir).unwrap().count(), 0);
+
+        update_bucket_max_file_size(db_pool, &app.bucket.id, None)
+            .await
+            .unwrap();
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_batch_upload() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let image = png_image(64, 48);
+        let document = pdf_document();
+
+        let files: Vec<(&str, &[u8])> = vec![("photo.png", &image), ("notes.pdf", &document)];
+        let res = app.upload_many(&files).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let items = res.body.as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["name"], "photo.png");
+        assert_eq!(items[0]["status_code"], 201);
+        assert_eq!(items[0]["file"]["content_type"], "image/png");
+        assert_eq!(items[1]["file"]["name"], "notes.pdf");
+
+        update_bucket_max_file_size(db_pool, &app.bucket.id, Some(100_000))
+            .await
+            .unwrap();
+
+        // Failed files are reported without stopping the rest
+        let large = vec![0u8; 200_000];
+        let files: Vec<(&str, &[u8])> = vec![
+            ("notes.pdf", &document),
+            ("large.pdf", &large),
+            ("other.pdf", &document),
+        ];
+        let res = app.upload_many(&files).await;
+        assert_eq!(res.status, StatusCode::MULTI_STATUS);
+        let items = res.body.as_array().unwrap();
+        assert_eq!(items[0]["status_code"], 400);
+        assert!(
+            items[0]["error"]
+                .as_str()
+                .unwrap()
+                .contains("already exists")
+        );
+        assert_eq!(items[1]["status_code"], 413);
+        assert!(items[1].get("file").is_none());
+        assert_eq!(items[2]["status_code"], 201);
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 3);
+
+        let orig_dir = app.state.config.upload_dir.join("orig");
+        assert_eq!(std::fs::read_dir(&orig_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_upload_request_limit() {
+        let mut app = TestApp::new().await;
+        let mut config = app.state.config.as_ref().clone();
+        config.upload.max_file_size = 100_000;
+        config.upload.max_request_size = 150_000;
+        app.state.config = std::sync::Arc::new(config);
+
+        // Files within their own limit still add up to the request limit
+        let data = vec![0u8; 90_000];
+        let files: Vec<(&str, &[u8])> = vec![("a.pdf", &data), ("b.pdf", &data)];
+        let res = app.upload_many(&files).await;
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 0);
+
+        // A single file up to the file limit always fits
+        let mut config = app.state.config.as_ref().clone();
+        config.upload.max_request_size = 1000;
+        app.state.config = std::sync::Arc::new(config);
+        let mut document = pdf_document();
+        document.resize(90_000, b' ');
+        let res = app.upload("notes.pdf", &document).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_uploads() {
+        let app = TestApp::new().await;
+        let uri = app.files_uri();
+        let document = pdf_document();
+        let orig_dir = app.state.config.upload_dir.join("orig");
+        let content_type = "multipart/form-data; boundary=files-rs-test-boundary";
+        let headers = [("Content-Type", content_type)];
+
+        // Files received before the broken field are cleaned up
+        let parts: Vec<(&str, &str, &[u8])> =
+            vec![("file", "notes.pdf", &document), ("file", "", &document)];
+        let body = multipart_body("files-rs-test-boundary", &parts);
+        let res = app.send_bytes(Method::POST, &uri, &headers, &body).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.body["message"], "Upload file name is missing");
+        assert_eq!(std::fs::read_dir(&orig_dir).unwrap().count(), 0);
+
+        // Body cut before the closing boundary
+        let parts: Vec<(&str, &str, &[u8])> = vec![("file", "notes.pdf", &document)];
+        let body = multipart_body("files-rs-test-boundary", &parts);
+        let truncated = &body[..body.len() - 40];
+        let res = app
+            .send_bytes(Method::POST, &uri, &headers, truncated)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.body["status_code"], 400);
+        assert_eq!(std::fs::read_dir(&orig_dir).unwrap().count(), 0);
+
+        let headers = [("Content-Type", "application/pdf")];
+        let res = app
+            .send_bytes(Method::POST, &uri, &headers, &document)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.body["status_code"], 400);
+
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.body["meta"]["total_records"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_upload_checksum() {
+        let app = TestApp::new().await;
+        let uri = app.files_uri();
+        let document = pdf_document();
+        let checksum = hex::encode(Sha256::digest(&document));
+        let other = hex::encode(Sha256::digest(b"other"));
+        let headers = [(
+            "Content-Type",
+            "multipart/form-data; boundary=files-rs-test-boundary",
+        )];
+
+        let res = app.upload("notes.pdf", &document).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["checksum"], checksum.as_str());
+
+        // Expected checksum applies to the file field that follows it
+        let parts: Vec<(&str, &str, &[u8])> = vec![
+            ("checksum", "", checksum.as_bytes()),
+            ("file", "one.pdf", &document),
+            ("checksum", "", other.as_bytes()),
+            ("file", "two.pdf", &document),
+            ("file", "three.pdf", &document),
+        ];
+        let body = multipart_body("files-rs-test-boundary", &parts);
+        let res = app.send_bytes(Method::POST, &uri, &headers, &body).await;
+        assert_eq!(res.status, StatusCode::MULTI_STATUS);
+        assert_eq!(res.body[0]["status_code"], 201);
+        assert_eq!(res.body[1]["status_code"], 460);
+        assert_eq!(res.body[2]["status_code"], 201);
+
+        let parts: Vec<(&str, &str, &[u8])> = vec![
+            ("checksum", "", b"not-a-checksum"),
+            ("file", "four.pdf", &document),
+        ];
+        let body = multipart_body("files-rs-test-boundary", &parts);
+        let res = app.send_bytes(Method::POST, &uri, &headers, &body).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.body["meta"]["total_records"], 3);
+        let orig_dir = app.state.config.upload_dir.join("orig");
+        assert_eq!(std::fs::read_dir(&orig_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_disk_full_error() {
+        let e = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        let res = to_error_response(Error::from_write(e, "Unable to write file"));
+        assert_eq!(res.status(), StatusCode::INSUFFICIENT_STORAGE);
+
+        let e = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let res = to_error_response(Error::from_write(e, "Unable to write file"));
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_update_file() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("typo.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].clone();
+
+        let res = app.upload("other.pdf", &pdf_document()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+
+        let file_uri = format!("{}/{}", app.files_uri(), file_id);
+        let body = json!({ "name": "fixed.pdf", "img_taken_at": 1700000000 });
+        let res = app.send(Method::PATCH, &file_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["name"], "fixed.pdf");
+        assert_eq!(res.body["img_taken_at"], 1700000000);
+
+        // Stored object is untouched
+        assert_eq!(res.body["filename"], filename);
+
+        // Keeping the same name is fine
+        let body = json!({ "name": "fixed.pdf" });
+        let res = app.send(Method::PATCH, &file_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let body = json!({ "name": "other.pdf" });
+        let res = app.send(Method::PATCH, &file_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let body = json!({ "name": "" });
+        let res = app.send(Method::PATCH, &file_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_move_file() {
+        let app = TestApp::new().await;
+        let target = app.create_dir(&app.bucket.id, "archive").await;
+
+        let res = app.upload("photo.png", &png_image(64, 64)).await;
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+
+        let move_uri = format!("{}/{}/move", app.files_uri(), file_id);
+        let body = json!({ "dir_id": app.dir.id });
+        let res = app.send(Method::POST, &move_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let body = json!({ "dir_id": target.id, "name": "moved.png" });
+        let res = app.send(Method::POST, &move_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["id"], file_id.as_str());
+        assert_eq!(res.body["dir_id"], target.id.as_str());
+        assert_eq!(res.body["name"], "moved.png");
+
+        let thumb_filename = filename.replace(".png", ".webp");
+        for path in [
+            format!("orig/{}", filename),
+            format!("thumb/{}", thumb_filename),
+        ] {
+            let old_path = format!("album/{}", path);
+            let new_path = format!("archive/{}", path);
+            assert!(app.storage.get_object("photos", &old_path).is_none());
+            assert!(app.storage.get_object("photos", &new_path).is_some());
+        }
+
+        let res = app
+            .send(
+                Method::GET,
+                &format!("{}/{}", app.files_uri(), file_id),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        let uri = format!(
+            "/v1/buckets/{}/dirs/{}/files/{}",
+            app.bucket.id, target.id, file_id
+        );
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.body["file_count"], 0);
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, target.id);
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.body["file_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_across_buckets() {
+        let app = TestApp::new().await;
+        let docs = app.create_bucket("docs", false).await;
+        let docs_dir = app.create_dir(&docs.id, "reports").await;
+        let images = app.create_bucket("images", true).await;
+        let images_dir = app.create_dir(&images.id, "misc").await;
+
+        let res = app.upload("report.pdf", &pdf_document()).await;
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+
+        let copy_uri = format!("{}/{}/copy", app.files_uri(), file_id);
+        let body = json!({ "bucket_id": docs.id, "dir_id": docs_dir.id });
+        let res = app.send(Method::POST, &copy_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_ne!(res.body["id"], file_id.as_str());
+        assert_eq!(res.body["name"], "report.pdf");
+
+        let copy_filename = res.body["filename"].as_str().unwrap();
+        assert_ne!(copy_filename, filename);
+        let copied = app
+            .storage
+            .get_object("docs", &format!("reports/orig/{}", copy_filename))
+            .unwrap();
+        assert_eq!(copied.data, pdf_document());
+        assert!(
+            app.storage
+                .get_object("photos", &format!("album/orig/{}", filename))
+                .is_some()
+        );
+
+        // Same dir requires a different name
+        let body = json!({ "dir_id": app.dir.id });
+        let res = app.send(Method::POST, &copy_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        let body = json!({ "dir_id": app.dir.id, "name": "report copy.pdf" });
+        let res = app.send(Method::POST, &copy_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+
+        // Target bucket only accepts images
+        let body = json!({ "bucket_id": images.id, "dir_id": images_dir.id });
+        let res = app.send(Method::POST, &copy_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        // Dir must belong to the target bucket
+        let body = json!({ "bucket_id": docs.id, "dir_id": images_dir.id });
+        let res = app.send(Method::POST, &copy_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_dir_and_bucket_stats() {
+        let app = TestApp::new().await;
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+        let bucket_uri = format!("/v1/buckets/{}", app.bucket.id);
+
+        let pdf = pdf_document();
+        let res = app.upload("one.pdf", &pdf).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let res = app.upload("two.pdf", &pdf).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.body["file_count"], 2);
+        assert_eq!(res.body["total_size"], pdf.len() * 2);
+        let res = app.send(Method::GET, &bucket_uri, None).await;
+        assert_eq!(res.body["file_count"], 2);
+        assert_eq!(res.body["total_size"], pdf.len() * 2);
+
+        let file_uri = format!("{}/{}", app.files_uri(), file_id);
+        let res = app.send(Method::DELETE, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let res = app.send(Method::GET, &dir_uri, None).await;
+        assert_eq!(res.body["file_count"], 1);
+        assert_eq!(res.body["total_size"], pdf.len());
+
+        // Recounting from the files table yields the same numbers
+        recount_bucket_stats(&app.state.db_pool, &app.bucket.id)
+            .await
+            .unwrap();
+        let res = app.send(Method::GET, &bucket_uri, None).await;
+        assert_eq!(res.body["file_count"], 1);
+        assert_eq!(res.body["total_size"], pdf.len());
+    }
+
+    #[tokio::test]
+    async fn test_files_require_auth() {
+        let mut app = TestApp::new().await;
+        app.token = "invalid-token".to_string();
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+        let res = app.upload("photo.png", &png_image(32, 32)).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,25 +1,45 @@
 use axum::extract::DefaultBodyLimit;
 use axum::middleware;
-use axum::{Router, routing::get};
-use tower_http::limit::RequestBodyLimitLayer;
+use axum::{
+    Router,
+    routing::{get, post},
+};
 
 use crate::web::middlewares::file_middleware;
 use crate::web::server::AppState;
 
-use super::{create_file_handler, delete_file_handler, get_file_handler, list_files_handler};
+use super::{
+    copy_file_handler, create_file_handler, delete_file_handler, get_file_handler,
+    list_files_handler, move_file_handler, update_file_handler,
+};
 
 pub fn files_routes(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/", get(list_files_handler).post(create_file_handler))
         .nest("/{file_id}", inner_file_routes(state.clone()))
-        .layer(DefaultBodyLimit::max(8000000))
-        .layer(RequestBodyLimitLayer::new(8000000))
+        .layer(DefaultBodyLimit::max(body_limit(&state)))
         .with_state(state)
 }
 
+/// Room for the multipart boundaries and headers around a file
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+
+fn body_limit(state: &AppState) -> usize {
+    let upload = &state.config.upload;
+    let single_file = upload.max_file_size + MULTIPART_OVERHEAD;
+    upload.max_request_size.max(single_file) as usize
+}
+
 fn inner_file_routes(state: AppState) -> Router<AppState> {
     Router::new()
-        .route("/", get(get_file_handler).delete(delete_file_handler))
+        .route(
+            "/",
+            get(get_file_handler)
+                .patch(update_file_handler)
+                .delete(delete_file_handler),
+        )
+        .route("/move", post(move_file_handler))
+        .route("/copy", post(copy_file_handler))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             file_middleware,
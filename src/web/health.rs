@@ -13,7 +13,7 @@ pub async fn health_live_handler() -> Result<JsonResponse> {
 }
 
 pub async fn health_ready_handler(State(state): State<AppState>) -> Result<JsonResponse> {
-    let health = check_readiness(&state.config, &state.db_pool).await?;
+    let health = check_readiness(state.storage_client.as_ref(), &state.db_pool).await?;
     let status = if health.is_healthy() {
         StatusCode::OK
     } else {
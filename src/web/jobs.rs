@@ -0,0 +1,67 @@
+use axum::{
+    Extension,
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    files::retry_regenerate_versions,
+    jobs::{get_job, spawn_job},
+    roles::Permission,
+    util::valid_id,
+    web::{response::JsonResponse, server::AppState},
+};
+
+/// Shows the status and progress of a background job of the bucket
+pub async fn get_job_handler(
+    State(state): State<AppState>,
+    Extension(bucket): Extension<BucketDto>,
+    Path((_, job_id)): Path<(String, String)>,
+) -> Result<JsonResponse> {
+    if !valid_id(&job_id) {
+        return Err(Error::BadRequest("Invalid job id".to_string()));
+    }
+
+    match get_job(&state.db_pool, &job_id).await? {
+        Some(job) if job.bucket_id == bucket.id => {
+            Ok(JsonResponse::new(serde_json::to_string(&job).unwrap()))
+        }
+        _ => Err(Error::NotFound("Job not found".to_string())),
+    }
+}
+
+/// Resumes a failed job from its saved progress, only regenerate versions
+/// jobs can be retried, a failed rename is rolled back instead
+pub async fn retry_job_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Path((_, job_id)): Path<(String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesManage];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+    if !valid_id(&job_id) {
+        return Err(Error::BadRequest("Invalid job id".to_string()));
+    }
+
+    let job = match get_job(&state.db_pool, &job_id).await? {
+        Some(job) if job.bucket_id == bucket.id => job,
+        _ => return Err(Error::NotFound("Job not found".to_string())),
+    };
+    let job = retry_regenerate_versions(&state.db_pool, &job).await?;
+    spawn_job(
+        state.db_pool.clone(),
+        state.storage_client.clone(),
+        state.config.clone(),
+        job.clone(),
+    );
+    Ok(JsonResponse::with_status(
+        StatusCode::ACCEPTED,
+        serde_json::to_string(&job).unwrap(),
+    ))
+}
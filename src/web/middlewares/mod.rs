@@ -7,3 +7,34 @@ pub use auth::*;
 pub use bucket::*;
 pub use dir::*;
 pub use file::*;
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use crate::{util::generate_id, web::test_helpers::TestApp};
+
+    #[tokio::test]
+    async fn test_resource_middlewares() {
+        let app = TestApp::new().await;
+
+        let res = app
+            .send(Method::GET, "/v1/buckets/not-an-id/dirs", None)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.body["message"], "Invalid bucket id");
+
+        let uri = format!("/v1/buckets/{}/dirs", generate_id());
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(res.body["message"], "Bucket not found");
+
+        let uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, generate_id());
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        let uri = format!("{}/{}", app.files_uri(), generate_id());
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+}
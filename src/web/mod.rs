@@ -1,14 +1,26 @@
 pub mod auth;
 pub mod buckets;
 pub mod dirs;
+pub mod duplicates;
 pub mod error;
 pub mod files;
 pub mod health;
 pub mod home;
+pub mod jobs;
 pub mod middlewares;
 pub mod not_found;
+pub mod objects;
 pub mod pagination;
 pub mod params;
+pub mod presigned;
 pub mod response;
 pub mod routes;
 pub mod server;
+pub mod similar;
+pub mod trash;
+pub mod tus;
+pub mod uploads;
+pub mod versions;
+
+#[cfg(test)]
+pub mod test_helpers;
@@ -0,0 +1,473 @@
+use std::path::{Component, Path as FsPath};
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tower_http::services::ServeFile;
+use tracing::error;
+
+use crate::{
+    Error, Result,
+    config::StorageKind,
+    files::{ImgTransform, TransformParams, transform_object},
+    storage::{TRANSFORM_METHOD, local_object_path, verify_signed_object},
+};
+
+use super::server::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct SignedParams {
+    pub expires: Option<i64>,
+    pub signature: Option<String>,
+}
+
+/// Serves objects of the local storage backend through signed urls
+pub async fn object_handler(
+    State(state): State<AppState>,
+    Path((bucket, path)): Path<(String, String)>,
+    Query(params): Query<SignedParams>,
+    request: Request,
+) -> Result<Response<Body>> {
+    let config = &state.config;
+    let Some(root) = &config.storage.dir else {
+        return Err(Error::NotFound("Object not found".to_string()));
+    };
+    if config.storage.backend != StorageKind::Local {
+        return Err(Error::NotFound("Object not found".to_string()));
+    }
+
+    let (Some(expires), Some(signature)) = (params.expires, params.signature) else {
+        return Err(Error::Forbidden("Missing signature".to_string()));
+    };
+    verify_signed_object(
+        config.url_secret(),
+        "GET",
+        &bucket,
+        &path,
+        expires,
+        &signature,
+    )?;
+
+    // Signed paths are generated by us but never allow escaping the storage root
+    if !is_safe_path(&bucket) || !is_safe_path(&path) {
+        return Err(Error::BadRequest("Invalid object path".to_string()));
+    }
+
+    let file_path = local_object_path(root, &bucket, &path);
+    if !file_path.is_file() {
+        return Err(Error::NotFound("Object not found".to_string()));
+    }
+
+    // ServeFile takes care of Content-Length, Range and conditional requests
+    let res = ServeFile::new(&file_path).try_call(request).await;
+    let mut response = match res {
+        Ok(response) => response.map(Body::new),
+        Err(e) => {
+            error!("{}", e);
+            return Err("Unable to read object".into());
+        }
+    };
+
+    // Prefer the sniffed content type over the extension based guess
+    if response.status().is_success() {
+        let content_type = infer::get_from_path(&file_path)
+            .ok()
+            .flatten()
+            .and_then(|kind| HeaderValue::from_str(kind.mime_type()).ok());
+        if let Some(value) = content_type {
+            response.headers_mut().insert(header::CONTENT_TYPE, value);
+        }
+    }
+
+    Ok(response)
+}
+
+/// Receives objects uploaded by clients through signed upload urls
+pub async fn object_upload_handler(
+    State(state): State<AppState>,
+    Path((bucket, path)): Path<(String, String)>,
+    Query(params): Query<SignedParams>,
+    body: Body,
+) -> Result<Response<Body>> {
+    let config = &state.config;
+    let Some(root) = &config.storage.dir else {
+        return Err(Error::NotFound("Object not found".to_string()));
+    };
+    if config.storage.backend != StorageKind::Local {
+        return Err(Error::NotFound("Object not found".to_string()));
+    }
+
+    let (Some(expires), Some(signature)) = (params.expires, params.signature) else {
+        return Err(Error::Forbidden("Missing signature".to_string()));
+    };
+    verify_signed_object(
+        config.url_secret(),
+        "PUT",
+        &bucket,
+        &path,
+        expires,
+        &signature,
+    )?;
+
+    if !is_safe_path(&bucket) || !is_safe_path(&path) {
+        return Err(Error::BadRequest("Invalid object path".to_string()));
+    }
+
+    let file_path = local_object_path(root, &bucket, &path);
+    if let Some(parent) = file_path.parent()
+        && fs::create_dir_all(parent).await.is_err()
+    {
+        return Err("Unable to create object directory".into());
+    }
+    let Ok(mut file) = fs::File::create(&file_path).await else {
+        return Err("Unable to write object".into());
+    };
+
+    // Bucket limits are checked once the upload is finalized
+    let max_size = config.upload.max_file_size;
+    let mut size: u64 = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let failure = match chunk {
+            Ok(chunk) => {
+                size += chunk.len() as u64;
+                if size > max_size {
+                    Some(Error::PayloadTooLarge(format!(
+                        "File exceeds the maximum size of {} bytes",
+                        max_size
+                    )))
+                } else if let Err(e) = file.write_all(&chunk).await {
+                    Some(Error::from_write(e, "Unable to write object"))
+                } else {
+                    None
+                }
+            }
+            Err(e) => Some(Error::UploadAborted(format!("Upload interrupted: {}", e))),
+        };
+
+        if let Some(e) = failure {
+            let _ = fs::remove_file(&file_path).await;
+            return Err(e);
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        let _ = fs::remove_file(&file_path).await;
+        return Err(Error::from_write(e, "Unable to write object"));
+    }
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Redirects to the original image resized and converted as requested.
+/// Each transformation is created once then served from the storage.
+pub async fn transform_handler(
+    State(state): State<AppState>,
+    Path((bucket, path)): Path<(String, String)>,
+    Query(params): Query<SignedParams>,
+    Query(transform_params): Query<TransformParams>,
+) -> Result<Response<Body>> {
+    let config = &state.config;
+    let (Some(expires), Some(signature)) = (params.expires, params.signature) else {
+        return Err(Error::Forbidden("Missing signature".to_string()));
+    };
+    verify_signed_object(
+        config.url_secret(),
+        TRANSFORM_METHOD,
+        &bucket,
+        &path,
+        expires,
+        &signature,
+    )?;
+
+    if !is_safe_path(&bucket) || !is_safe_path(&path) {
+        return Err(Error::BadRequest("Invalid object path".to_string()));
+    }
+
+    let transform = ImgTransform::new(&transform_params, &config.image)?;
+    let storage_client = state.storage_client.as_ref();
+    let cache_path = transform_object(
+        storage_client,
+        &config.upload_dir,
+        &bucket,
+        &path,
+        &transform,
+    )
+    .await?;
+
+    let url = storage_client.signed_url(&bucket, &cache_path).await?;
+    Ok(Redirect::temporary(&url).into_response())
+}
+
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && FsPath::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use axum::{
+        body::{Body, to_bytes},
+        http::{HeaderMap, Method, Request, StatusCode, header},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use crate::{
+        config::StorageKind,
+        storage::create_signed_url,
+        web::{
+            routes::all_routes,
+            server::AppState,
+            test_helpers::{TestApp, pdf_document, png_image},
+        },
+    };
+
+    // Same state as the test app but objects are served from a local root
+    fn local_state(app: &TestApp, root: &Path) -> AppState {
+        let mut config = app.state.config.as_ref().clone();
+        config.storage.backend = StorageKind::Local;
+        config.storage.dir = Some(root.to_path_buf());
+        AppState {
+            config: Arc::new(config),
+            ..app.state.clone()
+        }
+    }
+
+    fn signed_uri(state: &AppState, method: &str, path: &str, expires: i64) -> String {
+        let config = &state.config;
+        create_signed_url("", config.url_secret(), method, "photos", path, expires).unwrap()
+    }
+
+    async fn call_raw(
+        state: &AppState,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        data: Vec<u8>,
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers.iter() {
+            builder = builder.header(*name, *value);
+        }
+        let request = builder.body(Body::from(data)).unwrap();
+        let response = all_routes(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_object_handler() {
+        let app = TestApp::new().await;
+        let root = tempfile::TempDir::new().unwrap();
+        let state = local_state(&app, root.path());
+
+        let path = "album/orig/abc-my photo #1.png";
+        let data = png_image(20, 10);
+        let file_path = root.path().join("photos").join(path);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        std::fs::write(&file_path, &data).unwrap();
+
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let uri = signed_uri(&state, "GET", path, expires);
+        let (status, headers, body) = call_raw(&state, Method::GET, &uri, &[], vec![]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+        assert_eq!(body, data);
+
+        let range = [("range", "bytes=0-3")];
+        let (status, headers, body) = call_raw(&state, Method::GET, &uri, &range, vec![]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            headers[header::CONTENT_RANGE],
+            format!("bytes 0-3/{}", data.len()).as_str()
+        );
+        assert_eq!(body, &data[0..4]);
+
+        // Expired urls are rejected even with a valid signature
+        let expired = signed_uri(&state, "GET", path, expires - 120);
+        let (status, _, _) = call_raw(&state, Method::GET, &expired, &[], vec![]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Signature of another object, or of an upload url
+        let other = signed_uri(&state, "GET", "album/orig/other.png", expires);
+        let signature = other.split("signature=").last().unwrap();
+        let tampered = format!("{}{}", uri.split("signature=").next().unwrap(), signature);
+        let (status, _, _) = call_raw(&state, Method::GET, &tampered, &[], vec![]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let upload = signed_uri(&state, "PUT", path, expires);
+        let (status, _, _) = call_raw(&state, Method::GET, &upload, &[], vec![]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let unsigned = uri.split('?').next().unwrap();
+        let (status, _, _) = call_raw(&state, Method::GET, unsigned, &[], vec![]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let missing = signed_uri(&state, "GET", "album/orig/missing.png", expires);
+        let (status, _, _) = call_raw(&state, Method::GET, &missing, &[], vec![]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_object_upload_handler() {
+        let app = TestApp::new().await;
+        let root = tempfile::TempDir::new().unwrap();
+        let mut state = local_state(&app, root.path());
+        let mut config = state.config.as_ref().clone();
+        config.upload.max_file_size = 1024;
+        state.config = Arc::new(config);
+
+        let path = "_pending/abc";
+        let file_path = root.path().join("photos").join(path);
+        let data = vec![7u8; 1024];
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let uri = signed_uri(&state, "PUT", path, expires);
+        let (status, _, _) = call_raw(&state, Method::PUT, &uri, &[], data.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(std::fs::read(&file_path).unwrap(), data);
+
+        let expired = signed_uri(&state, "PUT", path, expires - 120);
+        let (status, _, _) = call_raw(&state, Method::PUT, &expired, &[], vec![1]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Download urls cannot be used to upload
+        let download = signed_uri(&state, "GET", path, expires);
+        let (status, _, _) = call_raw(&state, Method::PUT, &download, &[], vec![1]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let tampered = uri.replace("_pending/abc", "_pending/other");
+        let (status, _, _) = call_raw(&state, Method::PUT, &tampered, &[], vec![1]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!root.path().join("photos/_pending/other").exists());
+
+        // Signed or not, paths never leave the bucket directory
+        let escape = signed_uri(&state, "PUT", "_pending/../../escaped", expires);
+        let (status, _, _) = call_raw(&state, Method::PUT, &escape, &[], vec![1]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!root.path().join("escaped").exists());
+
+        let (status, _, _) = call_raw(&state, Method::PUT, &uri, &[], vec![7u8; 1025]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!file_path.exists());
+
+        // Objects are served by the local backend only
+        let (status, _, _) = call_raw(&app.state, Method::PUT, &uri, &[], vec![1]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    // Transform urls point to the default base url of the test config
+    fn transform_uri(template: &str, width: u32, height: u32) -> String {
+        template
+            .trim_start_matches("http://127.0.0.1:42000")
+            .replace("{width}", &width.to_string())
+            .replace("{height}", &height.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_transform_image() {
+        let app = TestApp::new().await;
+
+        let res = app.upload("photo.png", &png_image(300, 200)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        let template = res.body["transform_url"].as_str().unwrap().to_string();
+        assert!(template.contains(&format!("/v1/transform/photos/album/orig/{}?", filename)));
+
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        assert!(res.body["transform_url"].is_null());
+
+        // No transform url without the public url of the server
+        let mut state = app.state.clone();
+        let mut config = state.config.as_ref().clone();
+        config.storage.base_url = None;
+        state.config = Arc::new(config);
+        let file_uri = format!("{}/{}", app.files_uri(), file_id);
+        let request = Request::builder()
+            .uri(&file_uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", app.token))
+            .body(Body::empty())
+            .unwrap();
+        let res = all_routes(state).oneshot(request).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["transform_url"].is_null());
+
+        // Images are never upscaled, the aspect ratio is kept
+        let uri = transform_uri(&template, 200, 2000);
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::TEMPORARY_REDIRECT);
+        let cache_path = format!("_cache/album/{}/200x2000-contain-q80.webp", filename);
+        assert_eq!(
+            res.headers[header::LOCATION],
+            format!("memory://photos/{}", cache_path).as_str()
+        );
+        let cached = app.storage.get_object("photos", &cache_path).unwrap();
+        assert_eq!(cached.content_type, "image/webp");
+        let img = image::load_from_memory(&cached.data).unwrap();
+        assert_eq!((img.width(), img.height()), (200, 133));
+
+        let uri = format!(
+            "{}&fit=cover&format=jpeg&quality=60",
+            transform_uri(&template, 100, 100)
+        );
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::TEMPORARY_REDIRECT);
+        let cover_path = format!("_cache/album/{}/100x100-cover-q60.jpeg", filename);
+        let cached = app.storage.get_object("photos", &cover_path).unwrap();
+        assert_eq!(cached.content_type, "image/jpeg");
+        let img = image::load_from_memory(&cached.data).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 100));
+
+        // Repeated requests are served from the cache
+        app.storage
+            .put_object("photos", &cover_path, "image/jpeg", b"cached");
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::TEMPORARY_REDIRECT);
+        let cached = app.storage.get_object("photos", &cover_path).unwrap();
+        assert_eq!(cached.data, b"cached");
+
+        let res = app
+            .send(Method::GET, &transform_uri(&template, 0, 100), None)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        // Sizes, formats and qualities outside of the config are rejected
+        for uri in [
+            transform_uri(&template, 150, 100),
+            format!("{}&format=avif", transform_uri(&template, 100, 100)),
+            format!("{}&quality=70", transform_uri(&template, 100, 100)),
+        ] {
+            let res = app.send(Method::GET, &uri, None).await;
+            assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        }
+
+        let tampered = transform_uri(&template, 100, 100).replace("signature=", "signature=00");
+        let res = app.send(Method::GET, &tampered, None).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+        // Moving the file drops its cached transformations
+        let target = app.create_dir(&app.bucket.id, "archive").await;
+        let move_uri = format!("{}/{}/move", app.files_uri(), file_id);
+        let body = json!({ "dir_id": target.id });
+        let res = app.send(Method::POST, &move_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(app.storage.get_object("photos", &cache_path).is_none());
+        assert!(app.storage.get_object("photos", &cover_path).is_none());
+    }
+}
@@ -0,0 +1,271 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, post},
+};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    dirs::{Dir, ensure_dir_idle},
+    roles::Permission,
+    uploads::{
+        NewPendingUpload, PendingUpload, discard_pending_upload, finalize_pending_upload,
+        get_pending_upload, presign_upload,
+    },
+    util::valid_id,
+    web::{files::format_dir_file, response::JsonResponse, server::AppState},
+};
+
+pub fn presigned_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", post(create_presigned_handler))
+        .route("/{upload_id}", delete(discard_presigned_handler))
+        .route("/{upload_id}/finalize", post(finalize_presigned_handler))
+        .with_state(state)
+}
+
+/// Returns a signed url where the client uploads the file directly to the storage
+pub async fn create_presigned_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    payload: Json<NewPendingUpload>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    let storage_client = state.storage_client;
+    let max_size = bucket.upload_limit(state.config.upload.max_file_size);
+    let presigned = presign_upload(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &bucket,
+        &dir,
+        &payload,
+        max_size,
+    )
+    .await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::CREATED,
+        serde_json::to_string(&presigned).unwrap(),
+    ))
+}
+
+/// Creates the file from the object uploaded through the signed url
+pub async fn finalize_presigned_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    let pending = find_pending_upload(&state, &dir, &upload_id).await?;
+    let storage_client = state.storage_client;
+    let file_dto = finalize_pending_upload(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &bucket,
+        &dir,
+        &pending,
+    )
+    .await?;
+
+    let file_dto = format_dir_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &bucket,
+        &dir,
+        file_dto,
+    )
+    .await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::CREATED,
+        serde_json::to_string(&file_dto).unwrap(),
+    ))
+}
+
+pub async fn discard_presigned_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let pending = find_pending_upload(&state, &dir, &upload_id).await?;
+    let storage_client = state.storage_client;
+    discard_pending_upload(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &bucket.name,
+        &pending,
+    )
+    .await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::NO_CONTENT,
+        "".to_string(),
+    ))
+}
+
+async fn find_pending_upload(state: &AppState, dir: &Dir, id: &str) -> Result<PendingUpload> {
+    if !valid_id(id) {
+        return Err(Error::BadRequest("Invalid upload id".to_string()));
+    }
+    match get_pending_upload(&state.db_pool, id).await? {
+        Some(pending) if pending.dir_id == dir.id => Ok(pending),
+        _ => Err(Error::NotFound("Upload not found".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::{
+        uploads::{cleanup_expired_pending_uploads, get_pending_upload, pending_object_path},
+        web::test_helpers::{TestApp, pdf_document, png_image},
+    };
+
+    #[tokio::test]
+    async fn test_presigned_upload() {
+        let app = TestApp::new().await;
+        let presigned_uri = format!(
+            "/v1/buckets/{}/dirs/{}/presigned",
+            app.bucket.id, app.dir.id
+        );
+
+        let body = json!({ "name": "photo.png" });
+        let res = app.send(Method::POST, &presigned_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let staged_path = pending_object_path(&upload_id);
+        assert_eq!(
+            res.body["upload_url"],
+            format!("memory://photos/{}", staged_path)
+        );
+        assert_eq!(res.body["upload_headers"], json!({}));
+
+        // Nothing was uploaded yet
+        let finalize_uri = format!("{}/{}/finalize", presigned_uri, upload_id);
+        let res = app.send(Method::POST, &finalize_uri, None).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        app.storage
+            .put_object("photos", &staged_path, "image/png", &png_image(64, 48));
+        let res = app.send(Method::POST, &finalize_uri, None).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["name"], "photo.png");
+        assert_eq!(res.body["content_type"], "image/png");
+        assert!(!res.body["img_versions"].as_array().unwrap().is_empty());
+
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        let path = format!("album/orig/{}", filename);
+        assert!(app.storage.get_object("photos", &path).is_some());
+        assert!(app.storage.get_object("photos", &staged_path).is_none());
+
+        let res = app.send(Method::POST, &finalize_uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        // Name is checked before handing out the url
+        let body = json!({ "name": "photo.png" });
+        let res = app.send(Method::POST, &presigned_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_presigned_upload_limits_and_cleanup() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let storage = app.storage.as_ref();
+        let presigned_uri = format!(
+            "/v1/buckets/{}/dirs/{}/presigned",
+            app.bucket.id, app.dir.id
+        );
+
+        let body = json!({ "name": "large.pdf" });
+        let res = app.send(Method::POST, &presigned_uri, Some(body)).await;
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let staged_path = pending_object_path(&upload_id);
+        let data = vec![0u8; 9_000_000];
+        storage.put_object("photos", &staged_path, "application/pdf", &data);
+
+        let finalize_uri = format!("{}/{}/finalize", presigned_uri, upload_id);
+        let res = app.send(Method::POST, &finalize_uri, None).await;
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(storage.get_object("photos", &staged_path).is_none());
+
+        let body = json!({ "name": "notes.pdf" });
+        let res = app.send(Method::POST, &presigned_uri, Some(body)).await;
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let staged_path = pending_object_path(&upload_id);
+        storage.put_object("photos", &staged_path, "application/pdf", &pdf_document());
+
+        let cutoff = chrono::Utc::now().timestamp() - 60;
+        let count = cleanup_expired_pending_uploads(db_pool, storage, cutoff)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let cutoff = chrono::Utc::now().timestamp() + 60;
+        let count = cleanup_expired_pending_uploads(db_pool, storage, cutoff)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(storage.get_object("photos", &staged_path).is_none());
+        let pending = get_pending_upload(db_pool, &upload_id).await.unwrap();
+        assert!(pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_presigned_upload_retry() {
+        let app = TestApp::new().await;
+        let presigned_uri = format!(
+            "/v1/buckets/{}/dirs/{}/presigned",
+            app.bucket.id, app.dir.id
+        );
+
+        let body = json!({ "name": "notes.pdf" });
+        let res = app.send(Method::POST, &presigned_uri, Some(body)).await;
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let staged_path = pending_object_path(&upload_id);
+        app.storage
+            .put_object("photos", &staged_path, "application/pdf", &pdf_document());
+
+        // Name got taken meanwhile, the uploaded object is kept
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        let file_uri = format!("{}/{}", app.files_uri(), res.body["id"].as_str().unwrap());
+        let finalize_uri = format!("{}/{}/finalize", presigned_uri, upload_id);
+        let res = app.send(Method::POST, &finalize_uri, None).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert!(app.storage.get_object("photos", &staged_path).is_some());
+
+        let res = app.send(Method::DELETE, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let res = app.send(Method::POST, &finalize_uri, None).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert!(app.storage.get_object("photos", &staged_path).is_none());
+    }
+}
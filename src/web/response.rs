@@ -42,93 +42,133 @@ pub fn create_response(status: StatusCode, body: String) -> Response<Body> {
 }
 
 pub fn create_error_response(status: StatusCode, message: String, error: String) -> Response<Body> {
-    let body = ErrorResponse {
+    let body = error_body(status, message, error);
+    return create_response(status, serde_json::to_string(&body).unwrap());
+}
+
+fn error_body(status: StatusCode, message: String, error: String) -> ErrorResponse {
+    ErrorResponse {
         status_code: status.as_u16(),
         message,
         error,
-    };
-
-    return create_response(status, serde_json::to_string(&body).unwrap());
+    }
 }
 
 pub fn to_error_response(error: Error) -> Response<Body> {
+    let body = to_error_body(error);
+    let status = StatusCode::from_u16(body.status_code).unwrap();
+    create_response(status, serde_json::to_string(&body).unwrap())
+}
+
+/// Status and messages sent to clients for the error
+pub fn to_error_body(error: Error) -> ErrorResponse {
     match error {
-        Error::AnyError(message) => create_error_response(
+        Error::AnyError(message) => error_body(
             StatusCode::INTERNAL_SERVER_ERROR,
             message,
             "Internal Server Error".to_string(),
         ),
         Error::BadRequest(message) => {
-            create_error_response(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
+            error_body(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
         }
         Error::Forbidden(message) => {
-            create_error_response(StatusCode::FORBIDDEN, message, "Forbidden".to_string())
+            error_body(StatusCode::FORBIDDEN, message, "Forbidden".to_string())
         }
         Error::ValidationError(message) => {
-            create_error_response(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
+            error_body(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
         }
         Error::MissingUploadFile(message) => {
-            create_error_response(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
+            error_body(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
+        }
+        Error::MissingFileName => error_body(
+            StatusCode::BAD_REQUEST,
+            "Upload file name is missing".to_string(),
+            "Bad Request".to_string(),
+        ),
+        Error::InvalidMultipart(message) => {
+            error_body(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
         }
-        Error::FileTypeNotAllowed => create_error_response(
+        Error::UploadAborted(message) => {
+            error_body(StatusCode::BAD_REQUEST, message, "Bad Request".to_string())
+        }
+        Error::InsufficientStorage => error_body(
+            StatusCode::INSUFFICIENT_STORAGE,
+            "Not enough storage space for the upload".to_string(),
+            "Insufficient Storage".to_string(),
+        ),
+        Error::PayloadTooLarge(message) => error_body(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            message,
+            "Payload Too Large".to_string(),
+        ),
+        // Status used by the tus checksum extension
+        Error::ChecksumMismatch => error_body(
+            StatusCode::from_u16(460).unwrap(),
+            "Checksum mismatch".to_string(),
+            "Checksum Mismatch".to_string(),
+        ),
+        Error::FileTypeNotAllowed => error_body(
             StatusCode::BAD_REQUEST,
             "File type not allowed".to_string(),
             "Bad Request".to_string(),
         ),
         Error::NotFound(message) => {
-            create_error_response(StatusCode::NOT_FOUND, message, "Not Found".to_string())
+            error_body(StatusCode::NOT_FOUND, message, "Not Found".to_string())
+        }
+        Error::Conflict(message) => {
+            error_body(StatusCode::CONFLICT, message, "Conflict".to_string())
         }
-        Error::InvalidAuthToken => create_error_response(
+        Error::InvalidAuthToken => error_body(
             StatusCode::UNAUTHORIZED,
             "Unauthorized".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::InsufficientAuthScope => create_error_response(
+        Error::InsufficientAuthScope => error_body(
             StatusCode::UNAUTHORIZED,
             "Unauthorized".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::NoAuthToken => create_error_response(
+        Error::NoAuthToken => error_body(
             StatusCode::UNAUTHORIZED,
             "Unauthorized".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::InvalidClient => create_error_response(
+        Error::InvalidClient => error_body(
             StatusCode::UNAUTHORIZED,
             "Unauthorized".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::RequiresAuth => create_error_response(
+        Error::RequiresAuth => error_body(
             StatusCode::UNAUTHORIZED,
             "Unauthorized".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::HashPasswordError(message) => create_error_response(
+        Error::HashPasswordError(message) => error_body(
             StatusCode::INTERNAL_SERVER_ERROR,
             message,
             "Internal Server Error".to_string(),
         ),
-        Error::VerifyPasswordHashError(message) => create_error_response(
+        Error::VerifyPasswordHashError(message) => error_body(
             StatusCode::INTERNAL_SERVER_ERROR,
             message,
             "Internal Server Error".to_string(),
         ),
-        Error::InvalidPassword => create_error_response(
+        Error::InvalidPassword => error_body(
             StatusCode::UNAUTHORIZED,
             "Invalid username or password".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::InactiveUser => create_error_response(
+        Error::InactiveUser => error_body(
             StatusCode::UNAUTHORIZED,
             "Inactive user".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::UserNotFound => create_error_response(
+        Error::UserNotFound => error_body(
             StatusCode::UNAUTHORIZED,
             "Unauthorized".to_string(),
             "Unauthorized".to_string(),
         ),
-        Error::ConfigError(message) => create_error_response(
+        Error::ConfigError(message) => error_body(
             StatusCode::INTERNAL_SERVER_ERROR,
             message,
             "Internal Server Error".to_string(),
@@ -12,6 +12,7 @@ use super::{
     home::home_handler,
     middlewares::auth_middleware,
     not_found::not_found_handler,
+    objects::{object_handler, object_upload_handler, transform_handler},
 };
 
 pub fn all_routes(state: AppState) -> Router {
@@ -28,6 +29,11 @@ fn public_routes(state: AppState) -> Router<AppState> {
         .route("/health/liveness", get(health_live_handler))
         .route("/health/readiness", get(health_ready_handler))
         .route("/v1/auth/token", post(authenticate_handler))
+        .route(
+            "/v1/objects/{bucket}/{*path}",
+            get(object_handler).put(object_upload_handler),
+        )
+        .route("/v1/transform/{bucket}/{*path}", get(transform_handler))
         .with_state(state)
 }
 
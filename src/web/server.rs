@@ -3,7 +3,6 @@ use std::sync::Arc;
 use axum::Router;
 use axum::extract::FromRef;
 use deadpool_diesel::sqlite::Pool;
-use google_cloud_storage::client::Client;
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
@@ -11,28 +10,54 @@ use tracing::{Level, info};
 
 use crate::Result;
 use crate::config::Config;
-use crate::db::create_db_pool;
-use crate::storage::create_storage_client;
+use crate::db::{create_db_pool, run_pending_migrations};
+use crate::jobs::resume_jobs;
+use crate::storage::{StorageBackend, create_storage_client};
+use crate::trash::spawn_trash_purge;
+use crate::uploads::spawn_upload_cleanup;
 use crate::web::routes::all_routes;
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub config: Arc<Config>,
-    pub storage_client: Arc<Client>,
+    pub storage_client: Arc<dyn StorageBackend>,
     pub db_pool: Pool,
 }
 
 pub async fn run_web_server(config: &Config) -> Result<()> {
     let port = config.server.port;
 
-    let storage_client = create_storage_client(config.cloud.credentials.as_str()).await?;
+    let storage_client = create_storage_client(config).await?;
     let pool = create_db_pool(config.db.url.as_str());
+
+    if config.db.auto_migrate {
+        let versions = run_pending_migrations(&pool).await?;
+        for version in versions.iter() {
+            info!("Applied migration {}", version);
+        }
+    }
+
     let state = AppState {
         config: Arc::new(config.clone()),
-        storage_client: Arc::new(storage_client),
+        storage_client,
         db_pool: pool,
     };
 
+    // Continue jobs interrupted by the last shutdown
+    resume_jobs(&state.db_pool, &state.storage_client, &state.config).await?;
+
+    spawn_trash_purge(
+        state.db_pool.clone(),
+        state.storage_client.clone(),
+        config.trash.retention_days,
+    );
+    spawn_upload_cleanup(
+        state.db_pool.clone(),
+        state.storage_client.clone(),
+        config.upload_dir.clone(),
+        config.upload.session_ttl_hours,
+    );
+
     let mut routes_all = Router::new().merge(all_routes(state));
 
     routes_all = routes_all.layer(
@@ -0,0 +1,79 @@
+use axum::{
+    Extension, Router,
+    extract::{Query, State},
+    routing::get,
+};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    files::{ListSimilarParams, list_similar_groups},
+    roles::Permission,
+    web::{response::JsonResponse, server::AppState},
+};
+
+pub fn similar_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_similar_handler))
+        .with_state(state)
+}
+
+/// Lists clusters of images in the bucket that look alike, ie: resized or
+/// re-encoded copies of the same photo
+pub async fn list_similar_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    query: Query<ListSimilarParams>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesList];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let groups = list_similar_groups(&state.db_pool, &bucket.id, &query).await?;
+    Ok(JsonResponse::new(serde_json::to_string(&groups).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use crate::web::test_helpers::{TestApp, gradient_png, pdf_document};
+
+    #[tokio::test]
+    async fn test_similar_images() {
+        let app = TestApp::new().await;
+        let similar_uri = format!("/v1/buckets/{}/similar", app.bucket.id);
+
+        let res = app
+            .upload("large.png", &gradient_png(1200, 800, false))
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["phash"].as_str().unwrap().len(), 16);
+        let large_id = res.body["id"].as_str().unwrap().to_string();
+
+        let res = app
+            .upload("small.png", &gradient_png(300, 200, false))
+            .await;
+        let small_id = res.body["id"].as_str().unwrap().to_string();
+        app.upload("flipped.png", &gradient_png(300, 200, true))
+            .await;
+
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        assert!(res.body["phash"].is_null());
+
+        let res = app.send(Method::GET, &similar_uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body.as_array().unwrap().len(), 1);
+        let files = res.body[0]["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["id"], large_id.as_str());
+        assert_eq!(files[1]["id"], small_id.as_str());
+
+        let uri = format!("{}?threshold=64", similar_uri);
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+}
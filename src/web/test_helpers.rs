@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{HeaderMap, Method, Request, StatusCode, header},
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use crate::{
+    auth::{Credentials, authenticate},
+    buckets::{BucketDto, NewBucket, create_bucket},
+    clients::{NewClient, create_client},
+    config::{
+        Config, DbConfig, ImageConfig, ServerConfig, StorageConfig, StorageKind, TrashConfig,
+        UploadConfig,
+    },
+    db::{create_db_pool, run_pending_migrations},
+    dirs::{Dir, NewDir, create_dir},
+    storage::MemoryBackend,
+    users::{NewUser, create_user},
+};
+
+use super::{routes::all_routes, server::AppState};
+
+pub const TEST_USERNAME: &str = "admin";
+pub const TEST_PASSWORD: &str = "password123";
+
+/// Fully wired application backed by a temp database and in-memory storage
+pub struct TestApp {
+    pub state: AppState,
+    pub storage: Arc<MemoryBackend>,
+    pub token: String,
+    pub bucket: BucketDto,
+    pub dir: Dir,
+
+    // Removed when the test app is dropped
+    _temp_dir: TempDir,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: serde_json::Value,
+}
+
+impl TestApp {
+    /// Builds the app then seeds a client, an admin user, a bucket and a dir
+    pub async fn new() -> Self {
+        let temp_dir = TempDir::new().expect("Unable to create temp dir");
+        let upload_dir = temp_dir.path().join("uploads");
+        std::fs::create_dir_all(upload_dir.join("tmp")).expect("Unable to create upload dir");
+
+        let db_url = temp_dir.path().join("db.sqlite3");
+        let config = Config {
+            jwt_secret: "secret".to_string(),
+            upload_dir,
+            storage: StorageConfig {
+                backend: StorageKind::Memory,
+                base_url: Some("http://127.0.0.1:42000".to_string()),
+                ..Default::default()
+            },
+            cloud: None,
+            server: ServerConfig { port: 42000 },
+            db: DbConfig {
+                url: db_url.to_string_lossy().to_string(),
+                auto_migrate: false,
+            },
+            trash: TrashConfig::default(),
+            upload: UploadConfig::default(),
+            image: ImageConfig::default(),
+        };
+
+        let db_pool = create_db_pool(&config.db.url);
+        run_pending_migrations(&db_pool)
+            .await
+            .expect("Unable to run migrations");
+
+        let storage = Arc::new(MemoryBackend::new());
+        let state = AppState {
+            config: Arc::new(config),
+            storage_client: storage.clone(),
+            db_pool: db_pool.clone(),
+        };
+
+        let client = create_client(
+            &db_pool,
+            &NewClient {
+                name: "Test Client".to_string(),
+            },
+        )
+        .await
+        .expect("Unable to create client");
+
+        let _ = create_user(
+            &db_pool,
+            &client.id,
+            &NewUser {
+                username: TEST_USERNAME.to_string(),
+                password: TEST_PASSWORD.to_string(),
+                roles: "Admin".to_string(),
+            },
+        )
+        .await
+        .expect("Unable to create user");
+
+        let bucket = create_bucket(
+            &db_pool,
+            storage.as_ref(),
+            &client.id,
+            &NewBucket {
+                name: "photos".to_string(),
+                images_only: false,
+            },
+        )
+        .await
+        .expect("Unable to create bucket");
+
+        let dir = create_dir(
+            &db_pool,
+            &bucket.id,
+            &NewDir {
+                name: "album".to_string(),
+                label: "Album".to_string(),
+            },
+        )
+        .await
+        .expect("Unable to create dir");
+
+        let credentials = Credentials {
+            username: TEST_USERNAME.to_string(),
+            password: TEST_PASSWORD.to_string(),
+        };
+        let auth = authenticate(&state, &credentials)
+            .await
+            .expect("Unable to authenticate");
+
+        Self {
+            state,
+            storage,
+            token: auth.token,
+            bucket,
+            dir,
+            _temp_dir: temp_dir,
+        }
+    }
+
+    /// Creates another bucket for the seeded client
+    pub async fn create_bucket(&self, name: &str, images_only: bool) -> BucketDto {
+        let data = NewBucket {
+            name: name.to_string(),
+            images_only,
+        };
+        create_bucket(
+            &self.state.db_pool,
+            self.storage.as_ref(),
+            &self.bucket.client_id,
+            &data,
+        )
+        .await
+        .expect("Unable to create bucket")
+    }
+
+    pub async fn create_dir(&self, bucket_id: &str, name: &str) -> Dir {
+        let data = NewDir {
+            name: name.to_string(),
+            label: name.to_string(),
+        };
+        create_dir(&self.state.db_pool, bucket_id, &data)
+            .await
+            .expect("Unable to create dir")
+    }
+
+    pub fn router(&self) -> Router {
+        all_routes(self.state.clone())
+    }
+
+    pub fn files_uri(&self) -> String {
+        format!("/v1/buckets/{}/dirs/{}/files", self.bucket.id, self.dir.id)
+    }
+
+    /// Sends a request authenticated as the seeded admin user
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> TestResponse {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token));
+
+        let body = match body {
+            Some(value) => {
+                builder = builder.header(header::CONTENT_TYPE, "application/json");
+                Body::from(value.to_string())
+            }
+            None => Body::empty(),
+        };
+
+        self.call(builder.body(body).unwrap()).await
+    }
+
+    /// Sends raw bytes with extra headers, authenticated as the seeded admin user
+    pub async fn send_bytes(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        data: &[u8],
+    ) -> TestResponse {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token));
+        for (name, value) in headers.iter() {
+            builder = builder.header(*name, *value);
+        }
+
+        self.call(builder.body(Body::from(data.to_vec())).unwrap())
+            .await
+    }
+
+    /// Uploads a single file as multipart form data into the seeded dir
+    pub async fn upload(&self, filename: &str, data: &[u8]) -> TestResponse {
+        self.upload_many(&[(filename, data)]).await
+    }
+
+    /// Uploads several files in a single multipart request
+    pub async fn upload_many(&self, files: &[(&str, &[u8])]) -> TestResponse {
+        let boundary = "files-rs-test-boundary";
+        let parts: Vec<(&str, &str, &[u8])> = files
+            .iter()
+            .map(|(filename, data)| ("file", *filename, *data))
+            .collect();
+        let body = multipart_body(boundary, &parts);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.files_uri())
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(Body::from(body))
+            .unwrap();
+
+        self.call(request).await
+    }
+
+    pub async fn call(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = match bytes.is_empty() {
+            true => serde_json::Value::Null,
+            false => serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+        };
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+}
+
+/// Builds a multipart body from (field name, filename, data) parts
+pub fn multipart_body(boundary: &str, parts: &[(&str, &str, &[u8])]) -> Vec<u8> {
+    let mut body: Vec<u8> = Vec::new();
+    for (name, filename, data) in parts.iter() {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(
+            format!(
+                "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                name, filename
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+/// Encodes a solid color PNG image of the given dimension
+pub fn png_image(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]));
+    let mut bytes: Vec<u8> = Vec::new();
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .expect("Unable to encode png");
+    bytes
+}
+
+/// Horizontal gradient, flipped it has a very different perceptual hash
+pub fn gradient_image(width: u32, height: u32, flip: bool) -> image::DynamicImage {
+    let img = image::RgbImage::from_fn(width, height, |x, _| {
+        let v = (x * 255 / width) as u8;
+        let v = if flip { 255 - v } else { v };
+        image::Rgb([v, v / 2, 255 - v])
+    });
+    image::DynamicImage::ImageRgb8(img)
+}
+
+/// Encodes the gradient image as PNG
+pub fn gradient_png(width: u32, height: u32, flip: bool) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    gradient_image(width, height, flip)
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .expect("Unable to encode png");
+    bytes
+}
+
+/// Minimal document detected as application/pdf
+pub fn pdf_document() -> Vec<u8> {
+    b"%PDF-1.4\n1 0 obj << /Type /Catalog >> endobj\ntrailer << /Root 1 0 R >>\n%%EOF\n".to_vec()
+}
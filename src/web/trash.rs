@@ -0,0 +1,340 @@
+use axum::{
+    Extension, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    dirs::{Dir, delete_dir_recursive},
+    files::FileObject,
+    roles::Permission,
+    trash::{get_trashed_dir, get_trashed_file, list_trash, purge_file, restore_dir, restore_file},
+    util::valid_id,
+    web::{response::JsonResponse, server::AppState},
+};
+
+pub fn trash_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_trash_handler))
+        .route("/files/{file_id}", delete(purge_file_handler))
+        .route("/files/{file_id}/restore", post(restore_file_handler))
+        .route("/dirs/{dir_id}", delete(purge_dir_handler))
+        .route("/dirs/{dir_id}/restore", post(restore_dir_handler))
+        .with_state(state)
+}
+
+/// Lists dirs and files of the bucket that are in the trash
+pub async fn list_trash_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesList];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let items = list_trash(&state.db_pool, &bucket.id).await?;
+    Ok(JsonResponse::new(serde_json::to_string(&items).unwrap()))
+}
+
+pub async fn restore_file_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Path((_, file_id)): Path<(String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesDelete];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let (file, _) = find_trashed_file(&state, &bucket, &file_id).await?;
+    restore_file(&state.db_pool, &file).await?;
+
+    Ok(JsonResponse::with_status(
+        StatusCode::NO_CONTENT,
+        "".to_string(),
+    ))
+}
+
+/// Permanently deletes a trashed file and its stored objects
+pub async fn purge_file_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Path((_, file_id)): Path<(String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesManage];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let (file, dir) = find_trashed_file(&state, &bucket, &file_id).await?;
+    let storage_client = state.storage_client;
+    purge_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &bucket,
+        &dir,
+        &file,
+    )
+    .await?;
+
+    Ok(JsonResponse::with_status(
+        StatusCode::NO_CONTENT,
+        "".to_string(),
+    ))
+}
+
+pub async fn restore_dir_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Path((_, dir_id)): Path<(String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::DirsDelete];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    if !valid_id(&dir_id) {
+        return Err(Error::BadRequest("Invalid directory id".to_string()));
+    }
+    let Some(dir) = get_trashed_dir(&state.db_pool, &bucket.id, &dir_id).await? else {
+        return Err(Error::NotFound("Directory not found".to_string()));
+    };
+    restore_dir(&state.db_pool, &dir).await?;
+
+    Ok(JsonResponse::with_status(
+        StatusCode::NO_CONTENT,
+        "".to_string(),
+    ))
+}
+
+/// Permanently deletes a trashed dir, its files and their stored objects
+pub async fn purge_dir_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Path((_, dir_id)): Path<(String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::DirsManage];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    if !valid_id(&dir_id) {
+        return Err(Error::BadRequest("Invalid directory id".to_string()));
+    }
+    let Some(dir) = get_trashed_dir(&state.db_pool, &bucket.id, &dir_id).await? else {
+        return Err(Error::NotFound("Directory not found".to_string()));
+    };
+
+    let storage_client = state.storage_client;
+    let res = delete_dir_recursive(&state.db_pool, storage_client.as_ref(), &bucket, &dir).await?;
+    Ok(JsonResponse::new(serde_json::to_string(&res).unwrap()))
+}
+
+async fn find_trashed_file(
+    state: &AppState,
+    bucket: &BucketDto,
+    file_id: &str,
+) -> Result<(FileObject, Dir)> {
+    if !valid_id(file_id) {
+        return Err(Error::BadRequest("Invalid file id".to_string()));
+    }
+    match get_trashed_file(&state.db_pool, &bucket.id, file_id).await? {
+        Some(item) => Ok(item),
+        None => Err(Error::NotFound("File not found".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use diesel::prelude::*;
+
+    use crate::{
+        files::{FileObject, MAX_FILES, get_file},
+        schema::files,
+        trash::purge_expired_trash,
+        util::generate_id,
+        web::test_helpers::{TestApp, pdf_document},
+    };
+
+    #[tokio::test]
+    async fn test_trash_restore_and_purge_files() {
+        let app = TestApp::new().await;
+        let trash_uri = format!("/v1/buckets/{}/trash", app.bucket.id);
+        let bucket_uri = format!("/v1/buckets/{}", app.bucket.id);
+
+        let res = app.upload("one.pdf", &pdf_document()).await;
+        let one_id = res.body["id"].as_str().unwrap().to_string();
+        let one_filename = res.body["filename"].as_str().unwrap().to_string();
+        let res = app.upload("two.pdf", &pdf_document()).await;
+        let two_id = res.body["id"].as_str().unwrap().to_string();
+
+        for id in [&one_id, &two_id] {
+            let uri = format!("{}/{}", app.files_uri(), id);
+            let res = app.send(Method::DELETE, &uri, None).await;
+            assert_eq!(res.status, StatusCode::NO_CONTENT);
+        }
+
+        let res = app.send(Method::GET, &trash_uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["files"].as_array().unwrap().len(), 2);
+        assert_eq!(res.body["dirs"].as_array().unwrap().len(), 0);
+
+        let res = app.send(Method::GET, &bucket_uri, None).await;
+        assert_eq!(res.body["file_count"], 0);
+
+        // Name taken by a new upload blocks the restore
+        let res = app.upload("one.pdf", &pdf_document()).await;
+        let new_id = res.body["id"].as_str().unwrap().to_string();
+        let restore_uri = format!("{}/files/{}/restore", trash_uri, one_id);
+        let res = app.send(Method::POST, &restore_uri, None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+
+        let uri = format!("{}/{}", app.files_uri(), new_id);
+        app.send(Method::DELETE, &uri, None).await;
+        let res = app.send(Method::POST, &restore_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let uri = format!("{}/{}", app.files_uri(), one_id);
+        let res = app.send(Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let res = app.send(Method::GET, &bucket_uri, None).await;
+        assert_eq!(res.body["file_count"], 1);
+
+        // Restored file can no longer be purged from the trash
+        let purge_uri = format!("{}/files/{}", trash_uri, one_id);
+        let res = app.send(Method::DELETE, &purge_uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+        let purge_uri = format!("{}/files/{}", trash_uri, two_id);
+        let res = app.send(Method::DELETE, &purge_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        // Only the newer upload is left, the restored file keeps its objects
+        let res = app.send(Method::GET, &trash_uri, None).await;
+        assert_eq!(res.body["files"].as_array().unwrap().len(), 1);
+        assert_eq!(res.body["files"][0]["id"], new_id.as_str());
+        let path = format!("album/orig/{}", one_filename);
+        assert!(app.storage.get_object("photos", &path).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_dir_and_purge_expired() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let trash_uri = format!("/v1/buckets/{}/trash", app.bucket.id);
+        let bucket_uri = format!("/v1/buckets/{}", app.bucket.id);
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+
+        let uri = format!("{}?recursive=true", dir_uri);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let res = app.send(Method::GET, &trash_uri, None).await;
+        assert_eq!(res.body["dirs"].as_array().unwrap().len(), 1);
+
+        // Name is free again while the dir is in the trash
+        let dirs_uri = format!("/v1/buckets/{}/dirs", app.bucket.id);
+        let body = json!({ "name": "album", "label": "Album Again" });
+        let res = app.send(Method::POST, &dirs_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let new_dir_id = res.body["id"].as_str().unwrap().to_string();
+
+        let restore_uri = format!("{}/dirs/{}/restore", trash_uri, app.dir.id);
+        let res = app.send(Method::POST, &restore_uri, None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+
+        let uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, new_dir_id);
+        app.send(Method::DELETE, &uri, None).await;
+        let res = app.send(Method::POST, &restore_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 1);
+        let res = app.send(Method::GET, &bucket_uri, None).await;
+        assert_eq!(res.body["file_count"], 1);
+
+        // Entries deleted before the cutoff are purged, newer ones are kept
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        let uri = format!("{}?recursive=true", dir_uri);
+        app.send(Method::DELETE, &uri, None).await;
+
+        let cutoff = chrono::Utc::now().timestamp() - 60;
+        purge_expired_trash(db_pool, app.storage.as_ref(), cutoff)
+            .await
+            .unwrap();
+        let res = app.send(Method::GET, &trash_uri, None).await;
+        assert_eq!(res.body["dirs"].as_array().unwrap().len(), 2);
+
+        let cutoff = chrono::Utc::now().timestamp() + 60;
+        purge_expired_trash(db_pool, app.storage.as_ref(), cutoff)
+            .await
+            .unwrap();
+        let res = app.send(Method::GET, &trash_uri, None).await;
+        assert_eq!(res.body["dirs"].as_array().unwrap().len(), 0);
+
+        let path = format!("album/orig/{}", filename);
+        assert!(app.storage.get_object("photos", &path).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_into_full_dir() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let res = app.upload("one.pdf", &pdf_document()).await;
+        let one_id = res.body["id"].as_str().unwrap().to_string();
+        let uri = format!("{}/{}", app.files_uri(), one_id);
+        let res = app.send(Method::DELETE, &uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        // Fill the dir up to the limit while the file is in the trash
+        let db = db_pool.get().await.unwrap();
+        let trashed_id = one_id.clone();
+        db.interact(move |conn| {
+            let template = files::table
+                .find(&trashed_id)
+                .select(FileObject::as_select())
+                .first::<FileObject>(conn)
+                .unwrap();
+            let items: Vec<FileObject> = (0..MAX_FILES)
+                .map(|i| FileObject {
+                    id: generate_id(),
+                    name: format!("filler-{}.pdf", i),
+                    filename: format!("filler-{}.pdf", i),
+                    deleted_at: None,
+                    ..template.clone()
+                })
+                .collect();
+            diesel::insert_into(files::table)
+                .values(&items)
+                .execute(conn)
+                .unwrap();
+        })
+        .await
+        .unwrap();
+
+        let trash_uri = format!("/v1/buckets/{}/trash", app.bucket.id);
+        let restore_uri = format!("{}/files/{}/restore", trash_uri, one_id);
+        let res = app.send(Method::POST, &restore_uri, None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert!(get_file(db_pool, &one_id).await.unwrap().is_none());
+    }
+}
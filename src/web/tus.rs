@@ -0,0 +1,459 @@
+use axum::{
+    Extension, Router,
+    body::Body,
+    extract::{OriginalUri, Path, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{head, post},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    dirs::{Dir, ensure_dir_idle},
+    roles::Permission,
+    uploads::{
+        NewUploadSession, UploadSession, abort_upload_session, append_upload_chunk,
+        complete_upload_session, create_upload_session, get_upload_session,
+    },
+    util::valid_id,
+    web::server::AppState,
+};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,checksum";
+const TUS_CHECKSUM_ALGORITHMS: &str = "sha256";
+const TUS_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Endpoint speaking the tus resumable upload protocol, see https://tus.io
+pub fn tus_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", post(tus_create_handler).options(tus_options_handler))
+        .route(
+            "/{upload_id}",
+            head(tus_head_handler)
+                .patch(tus_patch_handler)
+                .delete(tus_delete_handler),
+        )
+        .layer(middleware::from_fn(tus_version_middleware))
+        .with_state(state)
+}
+
+/// Rejects unsupported protocol versions and tags every response
+async fn tus_version_middleware(req: Request, next: Next) -> Response {
+    let version = req.headers().get("Tus-Resumable");
+    if req.method() != Method::OPTIONS && version != Some(&HeaderValue::from_static(TUS_VERSION)) {
+        let mut res = StatusCode::PRECONDITION_FAILED.into_response();
+        res.headers_mut()
+            .insert("Tus-Version", HeaderValue::from_static(TUS_VERSION));
+        return res;
+    }
+
+    let mut res = next.run(req).await;
+    res.headers_mut()
+        .insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+    res
+}
+
+/// Advertises the supported protocol version, extensions and limits
+pub async fn tus_options_handler(
+    State(state): State<AppState>,
+    Extension(bucket): Extension<BucketDto>,
+) -> Result<Response> {
+    let max_size = bucket.upload_limit(state.config.upload.max_file_size);
+    let res = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Checksum-Algorithm", TUS_CHECKSUM_ALGORITHMS)
+        .header("Tus-Max-Size", max_size.to_string())
+        .body(Body::empty())
+        .unwrap();
+    Ok(res)
+}
+
+/// Creation extension, the file name is taken from the `filename` metadata
+pub async fn tus_create_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    let Some(size) = header_i64(&headers, "Upload-Length") else {
+        return Err(Error::BadRequest("Invalid Upload-Length".to_string()));
+    };
+    let Some(name) = parse_metadata(&headers, "filename") else {
+        return Err(Error::BadRequest("Missing filename metadata".to_string()));
+    };
+
+    let max_size = bucket.upload_limit(state.config.upload.max_file_size);
+    let data = NewUploadSession { name, size };
+    let session = create_upload_session(&state.db_pool, &bucket, &dir, &data, max_size).await?;
+
+    let location = format!("{}/{}", uri.path().trim_end_matches('/'), session.id);
+    let res = Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .unwrap();
+    Ok(res)
+}
+
+/// Reports the offset to resume from
+pub async fn tus_head_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<Response> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let session = find_tus_session(&state, &dir, &upload_id).await?;
+    let res = Response::builder()
+        .status(StatusCode::OK)
+        .header("Upload-Offset", session.received.to_string())
+        .header("Upload-Length", session.size.to_string())
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .unwrap();
+    Ok(res)
+}
+
+/// Appends the chunk, the file is created once the last byte arrives
+pub async fn tus_patch_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    if headers.get(header::CONTENT_TYPE) != Some(&HeaderValue::from_static(TUS_CONTENT_TYPE)) {
+        let res = StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+        return Ok(res);
+    }
+    let Some(offset) = header_i64(&headers, "Upload-Offset") else {
+        return Err(Error::BadRequest("Invalid Upload-Offset".to_string()));
+    };
+    let checksum = parse_checksum(&headers)?;
+
+    let session = find_tus_session(&state, &dir, &upload_id).await?;
+    let session = append_upload_chunk(
+        &state.db_pool,
+        &state.config.upload_dir,
+        &session,
+        offset,
+        checksum.as_deref(),
+        body.into_data_stream(),
+    )
+    .await?;
+
+    // Feed the completed upload into the regular upload pipeline, a rejected
+    // upload is kept and an empty PATCH at the final offset retries it
+    if session.received == session.size {
+        ensure_dir_idle(&state.db_pool, &dir).await?;
+        let storage_client = state.storage_client;
+        let _ = complete_upload_session(
+            &state.db_pool,
+            storage_client.as_ref(),
+            &state.config.upload_dir,
+            &bucket,
+            &dir,
+            &session,
+            &state.config.image,
+        )
+        .await?;
+    }
+
+    let res = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Upload-Offset", session.received.to_string())
+        .body(Body::empty())
+        .unwrap();
+    Ok(res)
+}
+
+/// Termination extension
+pub async fn tus_delete_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<Response> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let session = find_tus_session(&state, &dir, &upload_id).await?;
+    abort_upload_session(&state.db_pool, &state.config.upload_dir, &session).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn find_tus_session(state: &AppState, dir: &Dir, id: &str) -> Result<UploadSession> {
+    if !valid_id(id) {
+        return Err(Error::NotFound("Upload not found".to_string()));
+    }
+    match get_upload_session(&state.db_pool, id).await? {
+        Some(session) if session.dir_id == dir.id => Ok(session),
+        _ => Err(Error::NotFound("Upload not found".to_string())),
+    }
+}
+
+fn header_i64(headers: &HeaderMap, name: &str) -> Option<i64> {
+    let value = headers.get(name)?.to_str().ok()?;
+    value.parse::<i64>().ok().filter(|v| *v >= 0)
+}
+
+/// Decodes a value of the `Upload-Metadata` header, ie: `filename bm90ZXMucGRm`
+fn parse_metadata(headers: &HeaderMap, key: &str) -> Option<String> {
+    let metadata = headers.get("Upload-Metadata")?.to_str().ok()?;
+    for pair in metadata.split(',') {
+        let mut parts = pair.trim().splitn(2, ' ');
+        if parts.next() != Some(key) {
+            continue;
+        }
+        let decoded = STANDARD.decode(parts.next()?.trim()).ok()?;
+        return String::from_utf8(decoded).ok();
+    }
+    None
+}
+
+/// Decodes the `Upload-Checksum` header, only SHA-256 is supported
+fn parse_checksum(headers: &HeaderMap) -> Result<Option<Vec<u8>>> {
+    let Some(value) = headers.get("Upload-Checksum") else {
+        return Ok(None);
+    };
+    let Ok(value) = value.to_str() else {
+        return Err(Error::BadRequest("Invalid Upload-Checksum".to_string()));
+    };
+
+    match value.split_once(' ') {
+        Some(("sha256", digest)) => match STANDARD.decode(digest.trim()) {
+            Ok(digest) => Ok(Some(digest)),
+            Err(_) => Err(Error::BadRequest("Invalid Upload-Checksum".to_string())),
+        },
+        _ => Err(Error::BadRequest(
+            "Unsupported checksum algorithm".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use sha2::{Digest, Sha256};
+
+    use crate::web::test_helpers::{TestApp, pdf_document, png_image};
+
+    fn header(res: &crate::web::test_helpers::TestResponse, name: &str) -> String {
+        res.headers.get(name).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_tus_upload() {
+        let app = TestApp::new().await;
+        let tus_uri = format!("/v1/buckets/{}/dirs/{}/tus", app.bucket.id, app.dir.id);
+        let data = pdf_document();
+        let (first, second) = data.split_at(data.len() / 2);
+
+        let res = app.send_bytes(Method::OPTIONS, &tus_uri, &[], b"").await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "Tus-Version"), "1.0.0");
+        assert!(header(&res, "Tus-Extension").contains("checksum"));
+
+        let length = data.len().to_string();
+        let metadata = format!("filename {}", STANDARD.encode("notes.pdf"));
+        let create_headers = [
+            ("Upload-Length", length.as_str()),
+            ("Upload-Metadata", metadata.as_str()),
+        ];
+        let res = app
+            .send_bytes(Method::POST, &tus_uri, &create_headers, b"")
+            .await;
+        assert_eq!(res.status, StatusCode::PRECONDITION_FAILED);
+
+        let mut headers = create_headers.to_vec();
+        headers.push(("Tus-Resumable", "1.0.0"));
+        let res = app.send_bytes(Method::POST, &tus_uri, &headers, b"").await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(header(&res, "Tus-Resumable"), "1.0.0");
+        let location = header(&res, "Location");
+        assert!(location.starts_with(&tus_uri));
+
+        // Corrupted chunk is discarded
+        let bad_checksum = format!("sha256 {}", STANDARD.encode(Sha256::digest(b"other")));
+        let patch_headers = [
+            ("Tus-Resumable", "1.0.0"),
+            ("Content-Type", "application/offset+octet-stream"),
+            ("Upload-Offset", "0"),
+        ];
+        let mut headers = patch_headers.to_vec();
+        headers.push(("Upload-Checksum", bad_checksum.as_str()));
+        let res = app
+            .send_bytes(Method::PATCH, &location, &headers, first)
+            .await;
+        assert_eq!(res.status.as_u16(), 460);
+
+        let res = app
+            .send_bytes(Method::HEAD, &location, &patch_headers[..1], b"")
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(header(&res, "Upload-Offset"), "0");
+        assert_eq!(header(&res, "Upload-Length"), length);
+
+        let checksum = format!("sha256 {}", STANDARD.encode(Sha256::digest(first)));
+        let mut headers = patch_headers.to_vec();
+        headers.push(("Upload-Checksum", checksum.as_str()));
+        let res = app
+            .send_bytes(Method::PATCH, &location, &headers, first)
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "Upload-Offset"), first.len().to_string());
+
+        let offset = first.len().to_string();
+        let mut headers = patch_headers.to_vec();
+        headers[2] = ("Upload-Offset", offset.as_str());
+        let res = app
+            .send_bytes(Method::PATCH, &location, &headers, second)
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "Upload-Offset"), length);
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 1);
+        assert_eq!(res.body["data"][0]["name"], "notes.pdf");
+
+        // Same name is rejected when the upload is created
+        let mut headers = create_headers.to_vec();
+        headers.push(("Tus-Resumable", "1.0.0"));
+        let res = app.send_bytes(Method::POST, &tus_uri, &headers, b"").await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_tus_images_only_and_termination() {
+        let app = TestApp::new().await;
+        let bucket = app.create_bucket("gallery", true).await;
+        let dir = app.create_dir(&bucket.id, "shots").await;
+        let tus_uri = format!("/v1/buckets/{}/dirs/{}/tus", bucket.id, dir.id);
+
+        let data = pdf_document();
+        let length = data.len().to_string();
+        let metadata = format!("filename {}", STANDARD.encode("notes.pdf"));
+        let headers = [
+            ("Tus-Resumable", "1.0.0"),
+            ("Upload-Length", length.as_str()),
+            ("Upload-Metadata", metadata.as_str()),
+        ];
+        let res = app.send_bytes(Method::POST, &tus_uri, &headers, b"").await;
+        let location = header(&res, "Location");
+
+        let patch_headers = [
+            ("Tus-Resumable", "1.0.0"),
+            ("Content-Type", "application/offset+octet-stream"),
+            ("Upload-Offset", "0"),
+        ];
+        let res = app
+            .send_bytes(Method::PATCH, &location, &patch_headers, &data)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let image = png_image(32, 32);
+        let length = image.len().to_string();
+        let metadata = format!("filename {}", STANDARD.encode("photo.png"));
+        let headers = [
+            ("Tus-Resumable", "1.0.0"),
+            ("Upload-Length", length.as_str()),
+            ("Upload-Metadata", metadata.as_str()),
+        ];
+        let res = app.send_bytes(Method::POST, &tus_uri, &headers, b"").await;
+        let location = header(&res, "Location");
+
+        let res = app
+            .send_bytes(Method::DELETE, &location, &headers[..1], b"")
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        let res = app
+            .send_bytes(Method::HEAD, &location, &headers[..1], b"")
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_tus_rejected_completion() {
+        let app = TestApp::new().await;
+        let tus_uri = format!("/v1/buckets/{}/dirs/{}/tus", app.bucket.id, app.dir.id);
+        let data = pdf_document();
+
+        let length = data.len().to_string();
+        let metadata = format!("filename {}", STANDARD.encode("notes.pdf"));
+        let headers = [
+            ("Tus-Resumable", "1.0.0"),
+            ("Upload-Length", length.as_str()),
+            ("Upload-Metadata", metadata.as_str()),
+        ];
+        let res = app.send_bytes(Method::POST, &tus_uri, &headers, b"").await;
+        let location = header(&res, "Location");
+
+        // Name gets taken before the last byte arrives
+        let res = app.upload("notes.pdf", &data).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let file_uri = format!("{}/{}", app.files_uri(), res.body["id"].as_str().unwrap());
+
+        let mut patch_headers = vec![
+            ("Tus-Resumable", "1.0.0"),
+            ("Content-Type", "application/offset+octet-stream"),
+            ("Upload-Offset", "0"),
+        ];
+        let res = app
+            .send_bytes(Method::PATCH, &location, &patch_headers, &data)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let res = app
+            .send_bytes(Method::HEAD, &location, &patch_headers[..1], b"")
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(header(&res, "Upload-Offset"), length);
+
+        let res = app.send(Method::DELETE, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        patch_headers[2] = ("Upload-Offset", length.as_str());
+        let res = app
+            .send_bytes(Method::PATCH, &location, &patch_headers, b"")
+            .await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "Upload-Offset"), length);
+
+        let res = app.send(Method::GET, &app.files_uri(), None).await;
+        assert_eq!(res.body["meta"]["total_records"], 1);
+        let res = app
+            .send_bytes(Method::HEAD, &location, &patch_headers[..1], b"")
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+}
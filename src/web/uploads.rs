@@ -0,0 +1,348 @@
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{post, put},
+};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    dirs::{Dir, ensure_dir_idle},
+    roles::Permission,
+    uploads::{
+        NewUploadSession, UploadChunkParams, UploadSession, abort_upload_session,
+        append_upload_chunk, complete_upload_session, create_upload_session, get_upload_session,
+    },
+    util::valid_id,
+    web::{files::format_dir_file, response::JsonResponse, server::AppState},
+};
+
+pub fn upload_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", post(create_upload_handler))
+        .route(
+            "/{upload_id}",
+            put(upload_chunk_handler)
+                .get(get_upload_handler)
+                .delete(abort_upload_handler),
+        )
+        .route("/{upload_id}/complete", post(complete_upload_handler))
+        .with_state(state)
+}
+
+/// Starts a resumable upload session for a file of the declared size
+pub async fn create_upload_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    payload: Json<NewUploadSession>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    let max_size = bucket.upload_limit(state.config.upload.max_file_size);
+    let session = create_upload_session(&state.db_pool, &bucket, &dir, &payload, max_size).await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::CREATED,
+        serde_json::to_string(&session).unwrap(),
+    ))
+}
+
+/// Shows how many bytes were received, ie: where to resume from
+pub async fn get_upload_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let session = find_upload_session(&state, &dir, &upload_id).await?;
+    Ok(JsonResponse::new(serde_json::to_string(&session).unwrap()))
+}
+
+/// Appends the request body to the session at the `offset` query parameter
+pub async fn upload_chunk_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+    query: Query<UploadChunkParams>,
+    body: Body,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let session = find_upload_session(&state, &dir, &upload_id).await?;
+    let session = append_upload_chunk(
+        &state.db_pool,
+        &state.config.upload_dir,
+        &session,
+        query.offset,
+        None,
+        body.into_data_stream(),
+    )
+    .await?;
+    Ok(JsonResponse::new(serde_json::to_string(&session).unwrap()))
+}
+
+/// Creates the file once every byte of the session is received
+pub async fn complete_upload_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    ensure_dir_idle(&state.db_pool, &dir).await?;
+
+    let session = find_upload_session(&state, &dir, &upload_id).await?;
+    let storage_client = state.storage_client;
+    let file_dto = complete_upload_session(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config.upload_dir,
+        &bucket,
+        &dir,
+        &session,
+        &state.config.image,
+    )
+    .await?;
+
+    let file_dto = format_dir_file(
+        &state.db_pool,
+        storage_client.as_ref(),
+        &state.config,
+        &bucket,
+        &dir,
+        file_dto,
+    )
+    .await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::CREATED,
+        serde_json::to_string(&file_dto).unwrap(),
+    ))
+}
+
+pub async fn abort_upload_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(dir): Extension<Dir>,
+    Path((_, _, upload_id)): Path<(String, String, String)>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesCreate];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let session = find_upload_session(&state, &dir, &upload_id).await?;
+    abort_upload_session(&state.db_pool, &state.config.upload_dir, &session).await?;
+    Ok(JsonResponse::with_status(
+        StatusCode::NO_CONTENT,
+        "".to_string(),
+    ))
+}
+
+async fn find_upload_session(state: &AppState, dir: &Dir, id: &str) -> Result<UploadSession> {
+    if !valid_id(id) {
+        return Err(Error::BadRequest("Invalid upload id".to_string()));
+    }
+    match get_upload_session(&state.db_pool, id).await? {
+        Some(session) if session.dir_id == dir.id => Ok(session),
+        _ => Err(Error::NotFound("Upload not found".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Bytes;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::{
+        Error,
+        uploads::{
+            append_upload_chunk, cleanup_expired_upload_sessions, get_upload_session,
+            upload_session_path,
+        },
+        web::test_helpers::{TestApp, pdf_document},
+    };
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let app = TestApp::new().await;
+        let uploads_uri = format!("/v1/buckets/{}/dirs/{}/uploads", app.bucket.id, app.dir.id);
+        let data = pdf_document();
+        let (first, second) = data.split_at(data.len() / 2);
+
+        let body = json!({ "name": "notes.pdf", "size": data.len() });
+        let res = app.send(Method::POST, &uploads_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["received"], 0);
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let upload_uri = format!("{}/{}", uploads_uri, upload_id);
+
+        let res = app
+            .send(Method::POST, &format!("{}/complete", upload_uri), None)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let uri = format!("{}?offset=0", upload_uri);
+        let res = app.send_bytes(Method::PUT, &uri, &[], first).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body["received"], first.len());
+
+        // Resending the same chunk is rejected, the client must ask for progress
+        let res = app.send_bytes(Method::PUT, &uri, &[], first).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+
+        let res = app.send(Method::GET, &upload_uri, None).await;
+        assert_eq!(res.body["received"], first.len());
+
+        let uri = format!("{}?offset={}", upload_uri, first.len());
+        let res = app.send_bytes(Method::PUT, &uri, &[], second).await;
+        assert_eq!(res.body["received"], data.len());
+
+        // Bytes past the declared size are refused
+        let uri = format!("{}?offset={}", upload_uri, data.len());
+        let res = app.send_bytes(Method::PUT, &uri, &[], b"extra").await;
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let res = app
+            .send(Method::POST, &format!("{}/complete", upload_uri), None)
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["name"], "notes.pdf");
+        assert_eq!(res.body["size"], data.len());
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+
+        let path = format!("album/orig/{}", filename);
+        let object = app.storage.get_object("photos", &path).unwrap();
+        assert_eq!(object.data, data);
+
+        let res = app.send(Method::GET, &upload_uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_expired_upload_sessions() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let upload_dir = &app.state.config.upload_dir;
+        let uploads_uri = format!("/v1/buckets/{}/dirs/{}/uploads", app.bucket.id, app.dir.id);
+
+        let body = json!({ "name": "large.pdf", "size": 100_000_000 });
+        let res = app.send(Method::POST, &uploads_uri, Some(body)).await;
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body = json!({ "name": "notes.pdf", "size": 10 });
+        let res = app.send(Method::POST, &uploads_uri, Some(body)).await;
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let uri = format!("{}/{}?offset=0", uploads_uri, upload_id);
+        app.send_bytes(Method::PUT, &uri, &[], b"hello").await;
+        assert!(upload_session_path(upload_dir, &upload_id).exists());
+
+        let cutoff = chrono::Utc::now().timestamp() - 60;
+        let count = cleanup_expired_upload_sessions(db_pool, upload_dir, cutoff)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let cutoff = chrono::Utc::now().timestamp() + 60;
+        let count = cleanup_expired_upload_sessions(db_pool, upload_dir, cutoff)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(!upload_session_path(upload_dir, &upload_id).exists());
+        let session = get_upload_session(db_pool, &upload_id).await.unwrap();
+        assert!(session.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_upload_completion() {
+        let app = TestApp::new().await;
+        let upload_dir = &app.state.config.upload_dir;
+        let uploads_uri = format!("/v1/buckets/{}/dirs/{}/uploads", app.bucket.id, app.dir.id);
+        let data = pdf_document();
+
+        let body = json!({ "name": "notes.pdf", "size": data.len() });
+        let res = app.send(Method::POST, &uploads_uri, Some(body)).await;
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let upload_uri = format!("{}/{}", uploads_uri, upload_id);
+        let uri = format!("{}?offset=0", upload_uri);
+        app.send_bytes(Method::PUT, &uri, &[], &data).await;
+
+        // Name got taken meanwhile, the received bytes are kept for another attempt
+        let res = app.upload("notes.pdf", &data).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let file_uri = format!("{}/{}", app.files_uri(), res.body["id"].as_str().unwrap());
+
+        let complete_uri = format!("{}/complete", upload_uri);
+        let res = app.send(Method::POST, &complete_uri, None).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        let res = app.send(Method::GET, &upload_uri, None).await;
+        assert_eq!(res.body["received"], data.len());
+        assert!(upload_session_path(upload_dir, &upload_id).exists());
+
+        let res = app.send(Method::DELETE, &file_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        let res = app.send(Method::POST, &complete_uri, None).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body["size"], data.len());
+        assert!(!upload_session_path(upload_dir, &upload_id).exists());
+        let res = app.send(Method::GET, &upload_uri, None).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_stale_upload_chunk() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let upload_dir = &app.state.config.upload_dir;
+        let uploads_uri = format!("/v1/buckets/{}/dirs/{}/uploads", app.bucket.id, app.dir.id);
+
+        let body = json!({ "name": "notes.pdf", "size": 10 });
+        let res = app.send(Method::POST, &uploads_uri, Some(body)).await;
+        let upload_id = res.body["id"].as_str().unwrap().to_string();
+        let stale = get_upload_session(db_pool, &upload_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let uri = format!("{}/{}?offset=0", uploads_uri, upload_id);
+        let res = app.send_bytes(Method::PUT, &uri, &[], b"hello").await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        // A writer holding outdated progress must not overwrite received bytes
+        let stream = futures_util::stream::iter(vec![Ok::<Bytes, Error>(Bytes::from("world"))]);
+        let result = append_upload_chunk(db_pool, upload_dir, &stale, 0, None, stream).await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+
+        let path = upload_session_path(upload_dir, &upload_id);
+        assert_eq!(std::fs::read(path).unwrap(), b"hello");
+        let session = get_upload_session(db_pool, &upload_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.received, 5);
+    }
+}
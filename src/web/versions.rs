@@ -0,0 +1,332 @@
+use axum::{Extension, Json, Router, extract::State, http::StatusCode, routing::post};
+
+use crate::{
+    Error, Result,
+    auth::Actor,
+    buckets::BucketDto,
+    files::{RegenerateVersions, schedule_regenerate_versions},
+    jobs::spawn_job,
+    roles::Permission,
+    web::{response::JsonResponse, server::AppState},
+};
+
+pub fn versions_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/regenerate", post(regenerate_versions_handler))
+        .with_state(state)
+}
+
+/// Recreates the image versions of the bucket, a dir or a single file with
+/// the current settings. Runs in the background, responds with the job.
+pub async fn regenerate_versions_handler(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(bucket): Extension<BucketDto>,
+    payload: Json<RegenerateVersions>,
+) -> Result<JsonResponse> {
+    let permissions = vec![Permission::FilesManage];
+    if !actor.has_permissions(&permissions) {
+        return Err(Error::Forbidden("Insufficient permissions".to_string()));
+    }
+
+    let job = schedule_regenerate_versions(&state.db_pool, &bucket, &payload).await?;
+    spawn_job(
+        state.db_pool.clone(),
+        state.storage_client.clone(),
+        state.config.clone(),
+        job.clone(),
+    );
+    Ok(JsonResponse::with_status(
+        StatusCode::ACCEPTED,
+        serde_json::to_string(&job).unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::{
+        Error,
+        buckets::{NewImgProfile, set_img_profile},
+        files::{RegenerateVersions, run_regenerate_versions, schedule_regenerate_versions},
+        jobs::{JobStatus, get_job, update_job_progress, update_job_status},
+        web::test_helpers::{TestApp, pdf_document, png_image},
+    };
+
+    fn profile(name: &str, max_side: i32, format: Option<&str>) -> NewImgProfile {
+        NewImgProfile {
+            name: name.to_string(),
+            max_width: max_side,
+            max_height: max_side,
+            fit: None,
+            format: format.map(|f| f.to_string()),
+            quality: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_versions() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let res = app.upload("wide.png", &png_image(1200, 800)).await;
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        assert_eq!(res.body["img_versions"].as_array().unwrap().len(), 3);
+        app.upload("notes.pdf", &pdf_document()).await;
+
+        // Existing images keep the default versions until regenerated
+        for (name, max_side, format) in [("medium", 300, Some("jpeg")), ("thumb", 100, Some("png"))]
+        {
+            set_img_profile(db_pool, &app.bucket.id, &profile(name, max_side, format))
+                .await
+                .unwrap();
+        }
+
+        let regenerate_uri = format!("/v1/buckets/{}/versions/regenerate", app.bucket.id);
+        let res = app
+            .send(Method::POST, &regenerate_uri, Some(json!({})))
+            .await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert_eq!(res.body["kind"], "regenerate_versions");
+        assert_eq!(res.body["total"], 1);
+
+        let job_uri = format!(
+            "/v1/buckets/{}/jobs/{}",
+            app.bucket.id,
+            res.body["id"].as_str().unwrap()
+        );
+        let mut job = res.body;
+        for _ in 0..200 {
+            job = app.send(Method::GET, &job_uri, None).await.body;
+            if job["status"] == "completed" || job["status"] == "failed" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(job["status"], "completed");
+        assert_eq!(job["processed"], 1);
+
+        let file_uri = format!("{}/{}", app.files_uri(), file_id);
+        let res = app.send(Method::GET, &file_uri, None).await;
+        let versions = res.body["img_versions"].as_array().unwrap();
+        let names: Vec<&str> = versions
+            .iter()
+            .map(|v| v["version"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["orig", "medium", "thumb"]);
+        assert_eq!(versions[1]["content_type"], "image/jpeg");
+        assert_eq!(versions[2]["dimension"]["width"], 100);
+
+        // Obsolete preview is gone, thumbnail is replaced by a png
+        let object = |version: &str, format: &str| {
+            let filename = filename.replace(".png", &format!(".{}", format));
+            app.storage
+                .get_object("photos", &format!("album/{}/{}", version, filename))
+        };
+        assert!(object("prev", "webp").is_none());
+        assert!(object("thumb", "webp").is_none());
+        assert!(object("orig", "png").is_some());
+        assert_eq!(object("medium", "jpeg").unwrap().content_type, "image/jpeg");
+        let thumb = object("thumb", "png").unwrap();
+        assert_eq!(thumb.content_type, "image/png");
+        let thumb = image::load_from_memory(&thumb.data).unwrap();
+        assert_eq!(thumb.width(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_resume_regenerate_versions() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let res = app.upload("one.png", &png_image(400, 300)).await;
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        app.upload("two.png", &png_image(300, 400)).await;
+        let res = app.upload("notes.pdf", &pdf_document()).await;
+        let doc_id = res.body["id"].as_str().unwrap().to_string();
+
+        let data = RegenerateVersions {
+            dir_id: Some(app.dir.id.clone()),
+            file_id: None,
+        };
+        let job = schedule_regenerate_versions(db_pool, &app.bucket, &data)
+            .await
+            .unwrap();
+        assert_eq!(job.total, 2);
+
+        // Dir is locked while the job is pending
+        let res = app.upload("three.png", &png_image(64, 64)).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert!(
+            schedule_regenerate_versions(db_pool, &app.bucket, &data)
+                .await
+                .is_err()
+        );
+
+        let config = app.state.config.as_ref();
+        run_regenerate_versions(db_pool, app.storage.as_ref(), config, &job)
+            .await
+            .unwrap();
+
+        // Running again from the saved progress has nothing left to do
+        let job = get_job(db_pool, &job.id).await.unwrap().unwrap();
+        assert_eq!(job.processed, 2);
+        run_regenerate_versions(db_pool, app.storage.as_ref(), config, &job)
+            .await
+            .unwrap();
+        let job = get_job(db_pool, &job.id).await.unwrap().unwrap();
+        assert_eq!(job.processed, 2);
+
+        // Only images of the bucket can be regenerated
+        let single = |file_id: &str| RegenerateVersions {
+            dir_id: None,
+            file_id: Some(file_id.to_string()),
+        };
+        let res = schedule_regenerate_versions(db_pool, &app.bucket, &single(&doc_id)).await;
+        assert!(res.is_err());
+        let other = app.create_bucket("other", false).await;
+        let res = schedule_regenerate_versions(db_pool, &other, &single(&file_id)).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_versions_scopes() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+
+        let res = app.upload("one.png", &png_image(400, 300)).await;
+        let file_id = res.body["id"].as_str().unwrap().to_string();
+        let other = app.create_dir(&app.bucket.id, "other").await;
+        let other_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, other.id);
+
+        let whole = RegenerateVersions::default();
+        let in_dir = RegenerateVersions {
+            dir_id: Some(app.dir.id.clone()),
+            file_id: None,
+        };
+        let single = RegenerateVersions {
+            dir_id: None,
+            file_id: Some(file_id.clone()),
+        };
+
+        // Bucket job locks every dir and overlaps every other scope
+        let job = schedule_regenerate_versions(db_pool, &app.bucket, &whole)
+            .await
+            .unwrap();
+        let res = app.upload("two.png", &png_image(64, 64)).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        let res = app.send(Method::DELETE, &other_uri, None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        for data in [&whole, &in_dir, &single] {
+            let res = schedule_regenerate_versions(db_pool, &app.bucket, data).await;
+            assert!(matches!(res, Err(Error::Conflict(_))));
+        }
+        update_job_status(db_pool, &job.id, JobStatus::Completed, None)
+            .await
+            .unwrap();
+
+        // File job locks the dir of the file only
+        let job = schedule_regenerate_versions(db_pool, &app.bucket, &single)
+            .await
+            .unwrap();
+        let dir_uri = format!("/v1/buckets/{}/dirs/{}", app.bucket.id, app.dir.id);
+        let res = app
+            .send(Method::PATCH, &dir_uri, Some(json!({ "name": "renamed" })))
+            .await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        for data in [&whole, &in_dir, &single] {
+            let res = schedule_regenerate_versions(db_pool, &app.bucket, data).await;
+            assert!(matches!(res, Err(Error::Conflict(_))));
+        }
+        let res = app.send(Method::DELETE, &other_uri, None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+        update_job_status(db_pool, &job.id, JobStatus::Completed, None)
+            .await
+            .unwrap();
+        let res = app.upload("two.png", &png_image(64, 64)).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_retry_regenerate_versions() {
+        let app = TestApp::new().await;
+        let db_pool = &app.state.db_pool;
+        let config = app.state.config.as_ref();
+
+        let res = app.upload("one.png", &png_image(400, 300)).await;
+        let first_id = res.body["id"].as_str().unwrap().to_string();
+        let filename = res.body["filename"].as_str().unwrap().to_string();
+        app.upload("two.png", &png_image(300, 400)).await;
+
+        // Broken originals are counted as failed
+        let path = format!("album/orig/{}", filename);
+        app.storage
+            .put_object("photos", &path, "image/png", b"broken");
+        let in_dir = RegenerateVersions {
+            dir_id: Some(app.dir.id.clone()),
+            file_id: None,
+        };
+        let job = schedule_regenerate_versions(db_pool, &app.bucket, &in_dir)
+            .await
+            .unwrap();
+        run_regenerate_versions(db_pool, app.storage.as_ref(), config, &job)
+            .await
+            .unwrap();
+        let job = get_job(db_pool, &job.id).await.unwrap().unwrap();
+        assert_eq!((job.processed, job.failed), (2, 1));
+        update_job_status(db_pool, &job.id, JobStatus::Completed, None)
+            .await
+            .unwrap();
+
+        let retry_uri =
+            |job_id: &str| format!("/v1/buckets/{}/jobs/{}/retry", app.bucket.id, job_id);
+        let res = app.send(Method::POST, &retry_uri(&job.id), None).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        // Job stopped after the first image
+        let whole = RegenerateVersions::default();
+        let job = schedule_regenerate_versions(db_pool, &app.bucket, &whole)
+            .await
+            .unwrap();
+        update_job_progress(db_pool, &job.id, Some(first_id), 1, 0)
+            .await
+            .unwrap();
+        let job_error = Some("Directory has an operation in progress".to_string());
+        update_job_status(db_pool, &job.id, JobStatus::Failed, job_error)
+            .await
+            .unwrap();
+
+        // Not while another job works on the same images
+        let other = schedule_regenerate_versions(db_pool, &app.bucket, &in_dir)
+            .await
+            .unwrap();
+        let res = app.send(Method::POST, &retry_uri(&job.id), None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        update_job_status(db_pool, &other.id, JobStatus::Completed, None)
+            .await
+            .unwrap();
+
+        let res = app.send(Method::POST, &retry_uri(&job.id), None).await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert_eq!(res.body["status"], "pending");
+        assert!(res.body["error"].is_null());
+
+        let job_uri = format!("/v1/buckets/{}/jobs/{}", app.bucket.id, job.id);
+        let mut body = res.body;
+        for _ in 0..200 {
+            body = app.send(Method::GET, &job_uri, None).await.body;
+            if body["status"] == "completed" || body["status"] == "failed" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        // Resumed after the first image, which is still broken
+        assert_eq!(body["status"], "completed");
+        assert_eq!(body["processed"], 2);
+        assert_eq!(body["failed"], 0);
+    }
+}